@@ -2,12 +2,26 @@
 
 extern crate bindgen;
 
-use std::{env, fs::canonicalize, path::PathBuf};
+use std::{
+    env,
+    fs::{self, canonicalize},
+    path::{Path, PathBuf},
+};
+
+/// The libplist release the vendored feature expects in ./libplist
+const LIBPLIST_VERSION: &str = "2.4.0";
+/// The oldest libplist with everything the libplist backend calls
+/// JSON and OpenStep, null nodes, signed integers and `plist_sort` all arrived in 2.3.0
+const MIN_LIBPLIST_VERSION: &str = "2.3.0";
 
 fn main() {
     // Tell cargo to invalidate the built crate whenever build files change
     println!("cargo:rerun-if-changed=wrapper.h");
     println!("cargo:rerun-if-changed=build.rs");
+    println!(
+        "cargo:rustc-env=LIBPLIST_VENDORED_VERSION={}",
+        LIBPLIST_VERSION
+    );
 
     ////////////////////////////
     //   BINDGEN GENERATION   //
@@ -45,54 +59,148 @@ fn main() {
             .expect("Couldn't write bindings!");
     }
 
-    let mut statically = cfg!(feature = "static");
+    if cfg!(feature = "pure-rust") {
+        // Plist is built on the native backend, so there is no libplist to find
+        linked_version("");
+        return;
+    }
+    let statically = cfg!(feature = "static");
     if cfg!(feature = "vendored") {
-        // Change current directory to OUT_DIR
-        let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
-        env::set_current_dir(out_path).unwrap();
-        // Clone the vendored libraries
-        repo_setup("https://github.com/libimobiledevice/libplist.git");
-
-        // Build those bad bois
-        let dst = autotools::Config::new("libplist")
-            .without("cython", None)
-            .build();
+        // cc tells cargo to link the static library it builds
+        build_vendored();
+        linked_version(LIBPLIST_VERSION);
+        return;
+    }
 
+    // Check if folder ./override exists
+    let override_path = PathBuf::from("./override").join(env::var("TARGET").unwrap());
+    if override_path.exists() {
         println!(
-            "cargo:rustc-link-search=native={}",
-            dst.join("lib").display()
+            "cargo:rustc-link-search={}",
+            canonicalize(&override_path).unwrap().display()
         );
-
-        statically = true;
     } else {
-        // Check if folder ./override exists
-        let override_path = PathBuf::from("./override").join(env::var("TARGET").unwrap());
-        if override_path.exists() {
-            println!(
-                "cargo:rustc-link-search={}",
-                canonicalize(&override_path).unwrap().display()
-            );
+        match pkg_config::Config::new()
+            .statik(statically)
+            .probe("libplist-2.0")
+        {
+            Ok(library) => {
+                if version_parts(&library.version) < version_parts(MIN_LIBPLIST_VERSION) {
+                    panic!(
+                        "Found libplist {} with pkg-config, but plist_plus needs {} or newer. Update libplist or enable the vendored feature.",
+                        library.version, MIN_LIBPLIST_VERSION
+                    );
+                }
+                // pkg-config has already told cargo how to link it
+                linked_version(&library.version);
+                return;
+            }
+            Err(e) => {
+                println!(
+                    "cargo:warning=pkg-config could not find libplist-2.0, falling back to the default search paths: {}",
+                    e.to_string().lines().next().unwrap_or_default()
+                );
+                default_search_paths();
+            }
         }
+    }
+
+    // The version can't be checked without pkg-config, but a libplist older than 2.3.0
+    // lacks functions like plist_new_int, so anything that calls them fails to link
+    linked_version("");
+    let kind = if statically { "static" } else { "dylib" };
+    println!("cargo:rustc-link-lib={}=plist-2.0", kind);
+}
+
+/// The usual install paths, for when pkg-config can't find libplist
+fn default_search_paths() {
+    println!("cargo:rustc-link-search=/usr/local/lib");
+    println!("cargo:rustc-link-search=/usr/lib");
+    println!("cargo:rustc-link-search=/opt/homebrew/lib");
+    println!("cargo:rustc-link-search=/usr/local/opt/libimobiledevice/lib");
+    println!("cargo:rustc-link-search=/usr/local/opt/libusbmuxd/lib");
+    println!("cargo:rustc-link-search=/usr/local/opt/libimobiledevice-glue/lib");
+}
+
+/// Records the version of the linked libplist for `plist_plus::build_time_libplist_version`
+/// An empty version means it isn't known
+fn linked_version(version: &str) {
+    println!("cargo:rustc-env=LIBPLIST_LINKED_VERSION={}", version);
+}
+
+/// Splits a version like 2.4.0 into numbers that compare correctly
+fn version_parts(version: &str) -> Vec<u32> {
+    version
+        .split(['.', '-'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+/// Compiles the libplist sources in ./libplist into a static library
+/// This needs no autotools, only a C compiler
+fn build_vendored() {
+    let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("libplist");
+    if !root.join("src").join("plist.c").exists() {
+        // A git checkout has an empty submodule, so there is nothing to fall back on
+        panic!(
+            "The vendored feature builds libplist {} from source, but {} has none. Unpack the libplist {} release tarball there, build without vendored to link the system libplist, or enable pure-rust to not need libplist at all.",
+            LIBPLIST_VERSION,
+            root.display(),
+            LIBPLIST_VERSION
+        );
+    }
+    // Release tarballs record their version, and anything else could be missing functions
+    // the libplist backend calls, so only the pinned release is built
+    let version = fs::read_to_string(root.join(".tarball-version")).unwrap_or_else(|_| {
+        panic!(
+            "{} has no .tarball-version, so it isn't a libplist release. Unpack the libplist {} release tarball there, not a git checkout.",
+            root.display(),
+            LIBPLIST_VERSION
+        )
+    });
+    if version.trim() != LIBPLIST_VERSION {
+        panic!(
+            "{} holds libplist {}, but plist_plus is pinned to {}. Unpack the libplist {} release there.",
+            root.display(),
+            version.trim(),
+            LIBPLIST_VERSION,
+            LIBPLIST_VERSION
+        );
+    }
+    println!("cargo:rerun-if-changed={}", root.display());
 
-        println!("cargo:rustc-link-search=/usr/local/lib");
-        println!("cargo:rustc-link-search=/usr/lib");
-        println!("cargo:rustc-link-search=/opt/homebrew/lib");
-        println!("cargo:rustc-link-search=/usr/local/opt/libimobiledevice/lib");
-        println!("cargo:rustc-link-search=/usr/local/opt/libusbmuxd/lib");
-        println!("cargo:rustc-link-search=/usr/local/opt/libimobiledevice-glue/lib");
+    let mut build = cc::Build::new();
+    build
+        .include(root.join("include"))
+        .include(root.join("src"))
+        .include(root.join("libcnary").join("include"))
+        .define("LIBPLIST_STATIC", None)
+        .define(
+            "PACKAGE_VERSION",
+            Some(format!("\"{}\"", LIBPLIST_VERSION).as_str()),
+        )
+        .warnings(false);
+    // What configure would otherwise detect
+    if env::var("CARGO_CFG_TARGET_OS").unwrap() != "windows" {
+        build
+            .define("HAVE_STRNDUP", None)
+            .define("HAVE_GMTIME_R", None)
+            .define("HAVE_LOCALTIME_R", None)
+            .define("HAVE_FVISIBILITY", None);
     }
-    println!("cargo:rustc-link-lib={}=plist-2.0", if statically { "static" } else { "dylib" });
+    add_sources(&mut build, &root.join("src"));
+    add_sources(&mut build, &root.join("libcnary"));
+    build.compile("plist-2.0");
 }
 
-fn repo_setup(url: &str) {
-    let mut cmd = std::process::Command::new("git");
-    cmd.arg("clone");
-    cmd.arg(url);
-    cmd.output().unwrap();
-    env::set_current_dir(url.split('/').last().unwrap().replace(".git", "")).unwrap();
-    env::set_var("NOCONFIGURE", "1");
-    let mut cmd = std::process::Command::new("./autogen.sh");
-    let _ = cmd.output();
-    env::remove_var("NOCONFIGURE");
-    env::set_current_dir("..").unwrap();
+fn add_sources(build: &mut cc::Build, dir: &Path) {
+    let mut sources: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        // cnary.c is a test program, not part of the library
+        .filter(|path| path.extension().is_some_and(|ext| ext == "c"))
+        .filter(|path| !path.ends_with("cnary.c"))
+        .collect();
+    sources.sort();
+    build.files(sources);
 }
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use plist_plus::Plist;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = plist_plus::bplist::from_bytes(data);
+    if let Ok(inspection) = plist_plus::bplist::inspect(data) {
+        let _ = inspection.to_string();
+    }
+    let _ = Plist::from_bin(data.to_vec());
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use plist_plus::{ParseLimits, Plist};
+
+fuzz_target!(|data: &[u8]| {
+    // Small limits, so inputs that expand a lot are cut off quickly
+    let limits = ParseLimits {
+        max_depth: 64,
+        max_nodes: 10_000,
+        max_string_len: 4096,
+        max_data_len: 4096,
+        max_total_bytes: 1 << 16,
+    };
+    let _ = Plist::from_memory_with_limits(data.to_vec(), &limits);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use plist_plus::Plist;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(openstep) = std::str::from_utf8(data) {
+        let _ = plist_plus::openstep::from_str(openstep);
+        let _ = Plist::from_openstep(openstep.to_string());
+    }
+});
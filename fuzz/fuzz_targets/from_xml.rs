@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use plist_plus::Plist;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(xml) = std::str::from_utf8(data) {
+        let _ = plist_plus::xml::from_str(xml);
+        let _ = Plist::from_xml(xml.to_string());
+    }
+});
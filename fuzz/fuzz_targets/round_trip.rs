@@ -0,0 +1,43 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use plist_plus::{bplist::BinaryWriter, Plist, PlistFormat, PlistValue};
+
+// Anything that parses has to come out the same after being written and parsed again
+// The serialized forms are compared rather than the values, so NaN reals still match
+fuzz_target!(|data: &[u8]| {
+    // The same input through Plist, which is libplist unless built with pure-rust
+    if let Ok(plist) = Plist::from_memory(data.to_vec()) {
+        for format in [PlistFormat::Binary, PlistFormat::Xml] {
+            // Not everything libplist reads can be written in every format
+            if let Ok(bytes) = format.serialize(&plist) {
+                let reparsed =
+                    Plist::from_memory(bytes.clone()).expect("a written plist can be parsed");
+                assert_eq!(format.serialize(&reparsed).unwrap(), bytes);
+            }
+        }
+    }
+
+    let value = match plist_plus::bplist::from_bytes(data) {
+        Ok(value) => value,
+        Err(_) => match std::str::from_utf8(data).map(plist_plus::xml::from_str) {
+            Ok(Ok(value)) => value,
+            _ => return,
+        },
+    };
+
+    let writer = BinaryWriter::new();
+    let bin = writer
+        .write(&value)
+        .expect("a parsed value can be written as binary");
+    let reparsed = plist_plus::bplist::from_bytes(&bin).expect("written binary can be parsed");
+    assert_eq!(writer.write(&reparsed).unwrap(), bin);
+
+    // XML has no null, so binary plists holding one can't be written as XML
+    let xml = match plist_plus::xml::to_string(&value) {
+        Ok(xml) => xml,
+        Err(_) => return,
+    };
+    let reparsed: PlistValue = plist_plus::xml::from_str(&xml).expect("written XML can be parsed");
+    assert_eq!(plist_plus::xml::to_string(&reparsed).unwrap(), xml);
+});
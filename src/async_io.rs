@@ -0,0 +1,121 @@
+// jkcoxson
+
+use log::{trace, warn};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::codec::PlistFormat;
+use crate::error::PlistError;
+use crate::limits::{Budget, ParseLimits};
+use crate::Plist;
+
+impl Plist {
+    /// Reads a plist of any format from an async reader until it ends
+    /// Parsing runs on tokio's blocking pool, so large plists don't stall the runtime.
+    /// Readers from the `futures` crate can be adapted with `tokio_util::compat`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use plist_plus::{Plist, PlistFormat};
+    ///
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// let plist = Plist::new_string("Hello");
+    /// let mut bytes = Vec::new();
+    /// plist.write_to_async(&mut bytes, PlistFormat::Binary).await.unwrap();
+    /// let read = Plist::from_async_reader(bytes.as_slice()).await.unwrap();
+    /// assert_eq!(read, plist);
+    /// # });
+    /// ```
+    pub async fn from_async_reader(reader: impl AsyncRead + Unpin) -> Result<Plist, PlistError> {
+        Plist::from_async_reader_with_limits(reader, &ParseLimits::default()).await
+    }
+
+    /// Like `from_async_reader`, but for input that can't be trusted
+    /// No more than `max_total_bytes` are buffered before the input is rejected.
+    pub async fn from_async_reader_with_limits(
+        reader: impl AsyncRead + Unpin,
+        limits: &ParseLimits,
+    ) -> Result<Plist, PlistError> {
+        trace!("Reading plist from async reader");
+        let mut bin = Vec::new();
+        reader
+            .take((limits.max_total_bytes as u64).saturating_add(1))
+            .read_to_end(&mut bin)
+            .await?;
+        Budget::new(limits).total_bytes(bin.len())?;
+        let limits = *limits;
+        blocking(move || Plist::from_memory_with_limits(bin, &limits)).await
+    }
+
+    /// Serializes the plist on tokio's blocking pool and writes it to an async writer
+    pub async fn write_to_async(
+        &self,
+        mut writer: impl AsyncWrite + Unpin,
+        format: PlistFormat,
+    ) -> Result<(), PlistError> {
+        trace!("Writing plist to async writer");
+        // The blocking pool needs a plist it owns
+        let plist = self.clone();
+        let bytes = blocking(move || format.serialize(&plist)).await?;
+        writer.write_all(&bytes).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+/// Runs libplist work on the blocking pool
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, PlistError> + Send + 'static,
+) -> Result<T, PlistError> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(res) => res,
+        Err(e) => match e.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            Err(e) => {
+                warn!("Blocking plist task was cancelled");
+                Err(PlistError::Io(std::io::Error::other(e)))
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Limit;
+
+    #[tokio::test]
+    async fn from_async_reader_limits_test() {
+        let limits = ParseLimits {
+            max_total_bytes: 8,
+            ..Default::default()
+        };
+        assert!(matches!(
+            Plist::from_async_reader_with_limits(b"<plist><true/></plist>".as_slice(), &limits)
+                .await,
+            Err(PlistError::LimitExceeded {
+                limit: Limit::TotalBytes,
+                ..
+            })
+        ));
+        assert!(matches!(
+            Plist::from_async_reader(b"<plist><true></plist>".as_slice()).await,
+            Err(PlistError::Parse { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn libplist_async_round_trip_test() {
+        let mut plist = Plist::new_dict();
+        plist.dict_set_item("Name", "Device".into()).unwrap();
+        for format in [PlistFormat::Xml, PlistFormat::Binary] {
+            let (mut client, server) = tokio::io::duplex(64);
+            let writer = {
+                let plist = plist.clone();
+                tokio::spawn(async move { plist.write_to_async(server, format).await.unwrap() })
+            };
+            // The writer finishes and hangs up while the reader drains the pipe
+            assert_eq!(Plist::from_async_reader(&mut client).await.unwrap(), plist);
+            writer.await.unwrap();
+        }
+    }
+}
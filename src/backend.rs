@@ -0,0 +1,306 @@
+// jkcoxson
+
+use crate::{error::PlistError, PlistType};
+
+#[cfg(not(feature = "pure-rust"))]
+mod libplist;
+// With libplist linked, the native backend is only built to check it against libplist
+#[cfg(any(feature = "pure-rust", test))]
+mod native;
+
+#[cfg(not(feature = "pure-rust"))]
+pub(crate) use libplist::Libplist;
+#[cfg(any(feature = "pure-rust", test))]
+pub(crate) use native::Native;
+
+/// The backend every `Plist` is built on
+/// pure-rust builds use the native backend, so libplist doesn't have to be linked
+#[cfg(not(feature = "pure-rust"))]
+pub(crate) type Active = Libplist;
+#[cfg(feature = "pure-rust")]
+pub(crate) type Active = Native;
+
+/// The handle a `Plist` holds
+#[cfg(not(feature = "pure-rust"))]
+pub(crate) type Node = crate::unsafe_bindings::plist_t;
+#[cfg(feature = "pure-rust")]
+pub(crate) type Node = *mut native::NativeNode;
+
+/// The operations a plist implementation has to provide
+/// The public API in `types/*.rs` is written against this trait instead of the C bindings,
+/// so the same behavior can be checked against every backend
+pub(crate) trait Backend {
+    /// The handle to a single node of the tree
+    type Node;
+
+    fn new_bool(val: bool) -> Self::Node;
+    fn new_uint(val: u64) -> Self::Node;
+    fn new_int(val: i64) -> Self::Node;
+    fn new_real(val: f64) -> Self::Node;
+    fn new_string(val: &str) -> Self::Node;
+    fn new_data(val: &[u8]) -> Self::Node;
+    /// Seconds and microseconds since the Mac Epoch
+    fn new_date(sec: i32, usec: i32) -> Self::Node;
+    fn new_uid(val: u64) -> Self::Node;
+    fn new_null() -> Self::Node;
+    fn new_array() -> Self::Node;
+    fn new_dict() -> Self::Node;
+
+    /// Makes a deep copy of a node
+    fn copy(node: &Self::Node) -> Self::Node;
+    /// Releases a node and everything it contains
+    fn free(node: Self::Node);
+    fn node_type(node: &Self::Node) -> PlistType;
+    /// Returns the container holding the node, or a null node for a root
+    fn parent(node: &Self::Node) -> Self::Node;
+
+    fn get_bool(node: &Self::Node) -> Result<bool, PlistError>;
+    fn get_uint(node: &Self::Node) -> Result<u64, PlistError>;
+    /// Returns the integer with its sign, wide enough for every u64 and i64
+    fn get_int(node: &Self::Node) -> Result<i128, PlistError>;
+    fn get_real(node: &Self::Node) -> Result<f64, PlistError>;
+    fn get_string(node: &Self::Node) -> Result<String, PlistError>;
+    fn get_key(node: &Self::Node) -> Result<String, PlistError>;
+    fn get_data(node: &Self::Node) -> Result<Vec<u8>, PlistError>;
+    fn get_date(node: &Self::Node) -> Result<(i32, i32), PlistError>;
+    fn get_uid(node: &Self::Node) -> Result<u64, PlistError>;
+
+    /// Setters change the node to the type of the value, like libplist does
+    fn set_bool(node: &mut Self::Node, val: bool);
+    fn set_uint(node: &mut Self::Node, val: u64);
+    fn set_real(node: &mut Self::Node, val: f64);
+    fn set_string(node: &mut Self::Node, val: &str) -> Result<(), PlistError>;
+    fn set_key(node: &mut Self::Node, val: &str) -> Result<(), PlistError>;
+    fn set_data(node: &mut Self::Node, val: &[u8]);
+    fn set_date(node: &mut Self::Node, sec: i32, usec: i32);
+    fn set_uid(node: &mut Self::Node, val: u64);
+
+    fn array_len(node: &Self::Node) -> Result<u32, PlistError>;
+    /// Returns a handle to the item at the index, which still belongs to the array
+    fn array_get(node: &Self::Node, index: u32) -> Result<Self::Node, PlistError>;
+    fn array_set(node: &mut Self::Node, index: u32, item: Self::Node) -> Result<(), PlistError>;
+    fn array_append(node: &mut Self::Node, item: Self::Node) -> Result<(), PlistError>;
+    fn array_insert(node: &mut Self::Node, index: u32, item: Self::Node) -> Result<(), PlistError>;
+    fn array_remove(node: &mut Self::Node, index: u32) -> Result<(), PlistError>;
+    /// Returns the index of a node in the array holding it
+    fn array_item_index(node: &Self::Node) -> Result<u32, PlistError>;
+    /// Removes a node from the array holding it and frees it
+    fn array_item_remove(node: &mut Self::Node) -> Result<(), PlistError>;
+
+    fn dict_len(node: &Self::Node) -> Result<u32, PlistError>;
+    /// Returns the keys in the order they are stored
+    fn dict_keys(node: &Self::Node) -> Result<Vec<String>, PlistError>;
+    /// Returns a handle to the item for the key, or None if the key is missing
+    /// The item still belongs to the dictionary
+    fn dict_get(node: &Self::Node, key: &str) -> Result<Option<Self::Node>, PlistError>;
+    /// Sets the item for the key, replacing any existing item
+    fn dict_set(node: &mut Self::Node, key: &str, item: Self::Node) -> Result<(), PlistError>;
+    fn dict_remove(node: &mut Self::Node, key: &str) -> Result<(), PlistError>;
+    /// Returns the key a node is stored under in the dictionary holding it
+    fn dict_item_key(node: &Self::Node) -> Result<String, PlistError>;
+    /// Returns a new key node with the key a node is stored under, or a null node
+    fn dict_item_key_node(node: &Self::Node) -> Self::Node;
+    /// Copies every item of the source into the dictionary, replacing items with the same key
+    fn dict_merge(node: &mut Self::Node, source: &Self::Node) -> Result<(), PlistError>;
+    /// Sorts the keys of every dictionary in the tree
+    fn sort(node: &mut Self::Node);
+
+    fn to_xml(node: &Self::Node) -> Result<String, PlistError>;
+    fn from_xml(xml: &str) -> Result<Self::Node, PlistError>;
+    fn to_bin(node: &Self::Node) -> Result<Vec<u8>, PlistError>;
+    fn from_bin(bin: &[u8]) -> Result<Self::Node, PlistError>;
+    fn to_json(node: &Self::Node) -> Result<String, PlistError>;
+    fn from_json(json: &str) -> Result<Self::Node, PlistError>;
+    fn to_openstep(node: &Self::Node) -> Result<String, PlistError>;
+    fn from_openstep(openstep: &str) -> Result<Self::Node, PlistError>;
+    /// Parses a plist in any format
+    fn from_memory(bin: &[u8]) -> Result<Self::Node, PlistError>;
+    /// Returns whether the bytes are a binary plist
+    fn is_binary(bin: &[u8]) -> bool;
+}
+
+/// Shared scenarios that every backend has to pass
+/// Each one takes the backend as a type parameter so it can be run against all of them
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scalars<B: Backend>() {
+        let mut node = B::new_bool(false);
+        B::set_bool(&mut node, true);
+        assert!(B::get_bool(&node).unwrap());
+        assert!(B::get_uint(&node).is_err());
+
+        B::set_uint(&mut node, 98709781234);
+        assert_eq!(B::node_type(&node), PlistType::Integer);
+        assert_eq!(B::get_uint(&node).unwrap(), 98709781234);
+
+        B::free(node);
+        let mut node = B::new_int(-5);
+        assert_eq!(B::get_int(&node).unwrap(), -5);
+        B::set_uint(&mut node, u64::MAX);
+        assert_eq!(B::get_int(&node).unwrap(), u64::MAX as i128);
+
+        B::set_real(&mut node, 1234.098765);
+        assert_eq!(B::get_real(&node).unwrap(), 1234.098765);
+
+        B::set_string(&mut node, "a < b").unwrap();
+        assert_eq!(B::get_string(&node).unwrap(), "a < b");
+
+        B::set_data(&mut node, &[5, 4, 3, 2, 1]);
+        assert_eq!(B::get_data(&node).unwrap(), vec![5, 4, 3, 2, 1]);
+
+        B::set_date(&mut node, 567890, 0);
+        assert_eq!(B::get_date(&node).unwrap(), (567890, 0));
+
+        B::set_uid(&mut node, 7);
+        assert_eq!(B::node_type(&node), PlistType::Uid);
+        assert_eq!(B::get_uid(&node).unwrap(), 7);
+        B::free(node);
+
+        let node = B::new_null();
+        assert_eq!(B::node_type(&node), PlistType::None);
+        B::free(node);
+    }
+
+    fn containers<B: Backend>() {
+        let mut array = B::new_array();
+        B::array_append(&mut array, B::new_string("1")).unwrap();
+        B::array_append(&mut array, B::new_string("3")).unwrap();
+        B::array_insert(&mut array, 1, B::new_string("2")).unwrap();
+        assert_eq!(B::array_len(&array).unwrap(), 3);
+        assert_eq!(
+            B::get_string(&B::array_get(&array, 1).unwrap()).unwrap(),
+            "2"
+        );
+
+        B::array_set(&mut array, 0, B::new_uint(1)).unwrap();
+        B::array_remove(&mut array, 2).unwrap();
+        assert_eq!(B::array_len(&array).unwrap(), 2);
+        assert_eq!(B::get_uint(&B::array_get(&array, 0).unwrap()).unwrap(), 1);
+        assert!(B::array_get(&array, 2).is_err());
+        assert!(B::array_remove(&mut array, 2).is_err());
+
+        let mut dict = B::new_dict();
+        B::dict_set(&mut dict, "b", B::new_bool(true)).unwrap();
+        B::dict_set(&mut dict, "a", array).unwrap();
+        B::dict_set(&mut dict, "b", B::new_bool(false)).unwrap();
+        assert_eq!(B::dict_len(&dict).unwrap(), 2);
+        assert!(!B::get_bool(&B::dict_get(&dict, "b").unwrap().unwrap()).unwrap());
+        assert!(B::dict_get(&dict, "c").unwrap().is_none());
+        assert!(B::array_len(&dict).is_err());
+
+        let copy = B::copy(&dict);
+        B::dict_remove(&mut dict, "b").unwrap();
+        assert_eq!(B::dict_keys(&dict).unwrap(), vec!["a"]);
+        assert_eq!(B::dict_len(&copy).unwrap(), 2);
+        B::free(copy);
+        B::free(dict);
+    }
+
+    fn items<B: Backend>() {
+        let mut dict = B::new_dict();
+        let mut array = B::new_array();
+        for item in ["a", "b", "c"] {
+            B::array_append(&mut array, B::new_string(item)).unwrap();
+        }
+        B::dict_set(&mut dict, "list", array).unwrap();
+        let array = B::dict_get(&dict, "list").unwrap().unwrap();
+        assert_eq!(B::dict_item_key(&array).unwrap(), "list");
+        let key = B::dict_item_key_node(&array);
+        assert_eq!(B::get_key(&key).unwrap(), "list");
+        B::free(key);
+
+        let mut item = B::array_get(&array, 1).unwrap();
+        assert_eq!(B::array_item_index(&item).unwrap(), 1);
+        assert_eq!(B::node_type(&B::parent(&item)), PlistType::Array);
+        B::array_item_remove(&mut item).unwrap();
+        assert_eq!(B::array_len(&array).unwrap(), 2);
+        assert!(B::dict_item_key(&array).is_ok());
+        assert!(B::array_item_index(&dict).is_err());
+
+        let mut other = B::new_dict();
+        B::dict_set(&mut other, "b", B::new_uint(1)).unwrap();
+        B::dict_set(&mut other, "list", B::new_bool(true)).unwrap();
+        B::dict_merge(&mut dict, &other).unwrap();
+        B::free(other);
+        assert_eq!(B::dict_keys(&dict).unwrap(), vec!["list", "b"]);
+        assert!(B::get_bool(&B::dict_get(&dict, "list").unwrap().unwrap()).unwrap());
+
+        B::sort(&mut dict);
+        assert_eq!(B::dict_keys(&dict).unwrap(), vec!["b", "list"]);
+        B::free(dict);
+    }
+
+    fn serialization<B: Backend>() {
+        let mut dict = B::new_dict();
+        B::dict_set(&mut dict, "Name", B::new_string("a & b")).unwrap();
+        B::dict_set(&mut dict, "Blob", B::new_data(&[0, 1, 2])).unwrap();
+        let mut array = B::new_array();
+        B::array_append(&mut array, B::new_real(0.1)).unwrap();
+        B::array_append(&mut array, B::new_date(567890, 0)).unwrap();
+        B::dict_set(&mut dict, "List", array).unwrap();
+
+        let xml = B::to_xml(&dict).unwrap();
+        assert!(xml.contains("<string>a &amp; b</string>"));
+        assert!(xml.contains("<real>0.10000000000000001</real>"));
+        let parsed = B::from_xml(&xml).unwrap();
+        assert_eq!(B::to_xml(&parsed).unwrap(), xml);
+        assert!(B::from_xml("<plist><dict>").is_err());
+
+        B::free(parsed);
+
+        let bin = B::to_bin(&dict).unwrap();
+        let parsed = B::from_bin(&bin).unwrap();
+        assert_eq!(B::to_xml(&parsed).unwrap(), xml);
+        B::free(parsed);
+        B::free(dict);
+
+        let parsed = B::from_json(r#"{"Name": "a & b", "List": [1, 0.5, true]}"#).unwrap();
+        assert_eq!(B::dict_keys(&parsed).unwrap(), vec!["Name", "List"]);
+        let list = B::dict_get(&parsed, "List").unwrap().unwrap();
+        assert_eq!(B::get_uint(&B::array_get(&list, 0).unwrap()).unwrap(), 1);
+        assert_eq!(B::get_real(&B::array_get(&list, 1).unwrap()).unwrap(), 0.5);
+        assert!(B::from_json("{\"Name\": }").is_err());
+        let json = B::to_json(&parsed).unwrap();
+        let reparsed = B::from_json(&json).unwrap();
+        assert_eq!(B::to_json(&reparsed).unwrap(), json);
+        B::free(reparsed);
+        B::free(parsed);
+
+        let parsed = B::from_openstep("{ Name = \"a & b\"; Blob = <000102>; }").unwrap();
+        let blob = B::dict_get(&parsed, "Blob").unwrap().unwrap();
+        assert_eq!(B::get_data(&blob).unwrap(), vec![0, 1, 2]);
+        assert!(B::from_openstep("{ Name = ; }").is_err());
+        let openstep = B::to_openstep(&parsed).unwrap();
+        let reparsed = B::from_openstep(&openstep).unwrap();
+        assert_eq!(B::to_openstep(&reparsed).unwrap(), openstep);
+        assert!(B::to_openstep(&B::new_bool(true)).is_err());
+        B::free(reparsed);
+        B::free(parsed);
+
+        let parsed = B::from_memory(b"<plist><array><true/></array></plist>").unwrap();
+        assert_eq!(B::array_len(&parsed).unwrap(), 1);
+        let bin = B::to_bin(&parsed).unwrap();
+        assert!(B::is_binary(&bin));
+        assert!(!B::is_binary(b"<plist/>"));
+        B::free(parsed);
+    }
+
+    #[cfg(not(feature = "pure-rust"))]
+    #[test]
+    fn libplist_test() {
+        scalars::<Libplist>();
+        containers::<Libplist>();
+        items::<Libplist>();
+        serialization::<Libplist>();
+    }
+
+    #[test]
+    fn native_test() {
+        scalars::<Native>();
+        containers::<Native>();
+        items::<Native>();
+        serialization::<Native>();
+    }
+}
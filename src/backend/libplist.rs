@@ -0,0 +1,452 @@
+// jkcoxson
+
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_char,
+};
+
+use log::{trace, warn};
+
+use super::Backend;
+use crate::{error::PlistError, unsafe_bindings, PlistType};
+
+/// The backend built on the C library
+/// Nodes are raw `plist_t` handles, so items returned from containers point into their parent
+pub(crate) struct Libplist;
+
+fn expect_type(node: unsafe_bindings::plist_t, expected: PlistType) -> Result<(), PlistError> {
+    if Libplist::node_type(&node) != expected {
+        warn!("Expected a plist of type {:?}", expected);
+        return Err(PlistError::InvalidArg);
+    }
+    Ok(())
+}
+
+fn c_string(val: &str) -> Result<CString, PlistError> {
+    match CString::new(val) {
+        Ok(s) => Ok(s),
+        Err(_) => {
+            warn!("Could not convert string to CString");
+            Err(PlistError::InvalidArg)
+        }
+    }
+}
+
+/// Copies a buffer allocated by the C library and frees the original
+fn take_buffer(ptr: *mut c_char, len: u64) -> Vec<u8> {
+    if ptr.is_null() {
+        return Vec::new();
+    }
+    let buffer = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) }.to_vec();
+    unsafe { libc::free(ptr as *mut libc::c_void) };
+    buffer
+}
+
+/// Takes a string allocated by the C library
+fn take_string(ptr: *mut c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    trace!("Converting cstring to string");
+    let string = unsafe { CStr::from_ptr(ptr) }
+        .to_string_lossy()
+        .into_owned();
+    unsafe { libc::free(ptr as *mut libc::c_void) };
+    string
+}
+
+/// Takes a text buffer produced by one of the serializers
+fn text_buffer(ptr: *mut c_char, len: u32) -> Result<String, PlistError> {
+    match String::from_utf8(take_buffer(ptr, len as u64)) {
+        Ok(text) => Ok(text),
+        Err(_) => {
+            warn!("libplist produced text that isn't UTF-8");
+            Err(PlistError::Format)
+        }
+    }
+}
+
+impl Backend for Libplist {
+    type Node = unsafe_bindings::plist_t;
+
+    fn new_bool(val: bool) -> Self::Node {
+        unsafe { unsafe_bindings::plist_new_bool(val as u8) }
+    }
+    fn new_uint(val: u64) -> Self::Node {
+        unsafe { unsafe_bindings::plist_new_uint(val) }
+    }
+    fn new_int(val: i64) -> Self::Node {
+        unsafe { unsafe_bindings::plist_new_int(val) }
+    }
+    fn new_real(val: f64) -> Self::Node {
+        unsafe { unsafe_bindings::plist_new_real(val) }
+    }
+    fn new_string(val: &str) -> Self::Node {
+        let val = match CString::new(val) {
+            Ok(s) => s,
+            Err(_) => {
+                panic!("Could not convert string to CString");
+            }
+        };
+        unsafe { unsafe_bindings::plist_new_string(val.as_ptr()) }
+    }
+    fn new_data(val: &[u8]) -> Self::Node {
+        unsafe { unsafe_bindings::plist_new_data(val.as_ptr() as *const c_char, val.len() as u64) }
+    }
+    fn new_date(sec: i32, usec: i32) -> Self::Node {
+        unsafe { unsafe_bindings::plist_new_date(sec, usec) }
+    }
+    fn new_uid(val: u64) -> Self::Node {
+        unsafe { unsafe_bindings::plist_new_uid(val) }
+    }
+    fn new_null() -> Self::Node {
+        unsafe { unsafe_bindings::plist_new_null() }
+    }
+    fn new_array() -> Self::Node {
+        unsafe { unsafe_bindings::plist_new_array() }
+    }
+    fn new_dict() -> Self::Node {
+        unsafe { unsafe_bindings::plist_new_dict() }
+    }
+
+    fn copy(node: &Self::Node) -> Self::Node {
+        unsafe { unsafe_bindings::plist_copy(*node) }
+    }
+    fn free(node: Self::Node) {
+        unsafe { unsafe_bindings::plist_free(node) }
+    }
+    fn node_type(node: &Self::Node) -> PlistType {
+        unsafe { unsafe_bindings::plist_get_node_type(*node) }.into()
+    }
+    fn parent(node: &Self::Node) -> Self::Node {
+        unsafe { unsafe_bindings::plist_get_parent(*node) }
+    }
+
+    fn get_bool(node: &Self::Node) -> Result<bool, PlistError> {
+        expect_type(*node, PlistType::Boolean)?;
+        let mut val = 0;
+        unsafe { unsafe_bindings::plist_get_bool_val(*node, &mut val) };
+        Ok(val != 0)
+    }
+    fn get_uint(node: &Self::Node) -> Result<u64, PlistError> {
+        expect_type(*node, PlistType::Integer)?;
+        let mut val = 0;
+        unsafe { unsafe_bindings::plist_get_uint_val(*node, &mut val) };
+        Ok(val)
+    }
+    fn get_int(node: &Self::Node) -> Result<i128, PlistError> {
+        expect_type(*node, PlistType::Integer)?;
+        if unsafe { unsafe_bindings::plist_int_val_is_negative(*node) } == 0 {
+            return Ok(Self::get_uint(node)? as i128);
+        }
+        let mut val = 0;
+        unsafe { unsafe_bindings::plist_get_int_val(*node, &mut val) };
+        Ok(val as i128)
+    }
+    fn get_real(node: &Self::Node) -> Result<f64, PlistError> {
+        expect_type(*node, PlistType::Real)?;
+        let mut val = 0.0;
+        unsafe { unsafe_bindings::plist_get_real_val(*node, &mut val) };
+        Ok(val)
+    }
+    fn get_string(node: &Self::Node) -> Result<String, PlistError> {
+        expect_type(*node, PlistType::String)?;
+        let mut val = std::ptr::null_mut();
+        unsafe { unsafe_bindings::plist_get_string_val(*node, &mut val) };
+        Ok(take_string(val))
+    }
+    fn get_key(node: &Self::Node) -> Result<String, PlistError> {
+        expect_type(*node, PlistType::Key)?;
+        let mut val = std::ptr::null_mut();
+        unsafe { unsafe_bindings::plist_get_key_val(*node, &mut val) };
+        Ok(take_string(val))
+    }
+    fn get_data(node: &Self::Node) -> Result<Vec<u8>, PlistError> {
+        expect_type(*node, PlistType::Data)?;
+        let mut val = std::ptr::null_mut();
+        let mut size = 0;
+        unsafe { unsafe_bindings::plist_get_data_val(*node, &mut val, &mut size) };
+        Ok(take_buffer(val, size))
+    }
+    fn get_date(node: &Self::Node) -> Result<(i32, i32), PlistError> {
+        expect_type(*node, PlistType::Date)?;
+        let mut sec = 0;
+        let mut usec = 0;
+        unsafe { unsafe_bindings::plist_get_date_val(*node, &mut sec, &mut usec) };
+        Ok((sec, usec))
+    }
+    fn get_uid(node: &Self::Node) -> Result<u64, PlistError> {
+        expect_type(*node, PlistType::Uid)?;
+        let mut val = 0;
+        unsafe { unsafe_bindings::plist_get_uid_val(*node, &mut val) };
+        Ok(val)
+    }
+
+    fn set_bool(node: &mut Self::Node, val: bool) {
+        unsafe { unsafe_bindings::plist_set_bool_val(*node, val as u8) }
+    }
+    fn set_uint(node: &mut Self::Node, val: u64) {
+        unsafe { unsafe_bindings::plist_set_uint_val(*node, val) }
+    }
+    fn set_real(node: &mut Self::Node, val: f64) {
+        unsafe { unsafe_bindings::plist_set_real_val(*node, val) }
+    }
+    fn set_string(node: &mut Self::Node, val: &str) -> Result<(), PlistError> {
+        let val = c_string(val)?;
+        unsafe { unsafe_bindings::plist_set_string_val(*node, val.as_ptr()) };
+        Ok(())
+    }
+    fn set_key(node: &mut Self::Node, val: &str) -> Result<(), PlistError> {
+        let val = c_string(val)?;
+        unsafe { unsafe_bindings::plist_set_key_val(*node, val.as_ptr()) };
+        Ok(())
+    }
+    fn set_data(node: &mut Self::Node, val: &[u8]) {
+        unsafe {
+            unsafe_bindings::plist_set_data_val(
+                *node,
+                val.as_ptr() as *const c_char,
+                val.len() as u64,
+            )
+        }
+    }
+    fn set_date(node: &mut Self::Node, sec: i32, usec: i32) {
+        unsafe { unsafe_bindings::plist_set_date_val(*node, sec, usec) }
+    }
+    fn set_uid(node: &mut Self::Node, val: u64) {
+        unsafe { unsafe_bindings::plist_set_uid_val(*node, val) }
+    }
+
+    fn array_len(node: &Self::Node) -> Result<u32, PlistError> {
+        expect_type(*node, PlistType::Array)?;
+        Ok(unsafe { unsafe_bindings::plist_array_get_size(*node) })
+    }
+    fn array_get(node: &Self::Node, index: u32) -> Result<Self::Node, PlistError> {
+        if index >= Self::array_len(node)? {
+            warn!("Array index {} is out of range", index);
+            return Err(PlistError::InvalidArg);
+        }
+        Ok(unsafe { unsafe_bindings::plist_array_get_item(*node, index) })
+    }
+    fn array_set(node: &mut Self::Node, index: u32, item: Self::Node) -> Result<(), PlistError> {
+        if index >= Self::array_len(node)? {
+            warn!("Array index {} is out of range", index);
+            return Err(PlistError::InvalidArg);
+        }
+        unsafe { unsafe_bindings::plist_array_set_item(*node, item, index) };
+        Ok(())
+    }
+    fn array_append(node: &mut Self::Node, item: Self::Node) -> Result<(), PlistError> {
+        expect_type(*node, PlistType::Array)?;
+        unsafe { unsafe_bindings::plist_array_append_item(*node, item) };
+        Ok(())
+    }
+    fn array_insert(node: &mut Self::Node, index: u32, item: Self::Node) -> Result<(), PlistError> {
+        if index > Self::array_len(node)? {
+            warn!("Array index {} is out of range", index);
+            return Err(PlistError::InvalidArg);
+        }
+        unsafe { unsafe_bindings::plist_array_insert_item(*node, item, index) };
+        Ok(())
+    }
+    fn array_remove(node: &mut Self::Node, index: u32) -> Result<(), PlistError> {
+        if index >= Self::array_len(node)? {
+            warn!("Array index {} is out of range", index);
+            return Err(PlistError::InvalidArg);
+        }
+        unsafe { unsafe_bindings::plist_array_remove_item(*node, index) };
+        Ok(())
+    }
+    fn array_item_index(node: &Self::Node) -> Result<u32, PlistError> {
+        expect_type(Self::parent(node), PlistType::Array)?;
+        Ok(unsafe { unsafe_bindings::plist_array_get_item_index(*node) })
+    }
+    fn array_item_remove(node: &mut Self::Node) -> Result<(), PlistError> {
+        expect_type(Self::parent(node), PlistType::Array)?;
+        unsafe { unsafe_bindings::plist_array_item_remove(*node) };
+        Ok(())
+    }
+
+    fn dict_len(node: &Self::Node) -> Result<u32, PlistError> {
+        expect_type(*node, PlistType::Dictionary)?;
+        Ok(unsafe { unsafe_bindings::plist_dict_get_size(*node) })
+    }
+    fn dict_keys(node: &Self::Node) -> Result<Vec<String>, PlistError> {
+        expect_type(*node, PlistType::Dictionary)?;
+        let mut iter = std::ptr::null_mut();
+        unsafe { unsafe_bindings::plist_dict_new_iter(*node, &mut iter) };
+        let mut keys = Vec::new();
+        loop {
+            let mut key = std::ptr::null_mut();
+            let mut item = std::ptr::null_mut();
+            unsafe { unsafe_bindings::plist_dict_next_item(*node, iter, &mut key, &mut item) };
+            if item.is_null() {
+                break;
+            }
+            keys.push(unsafe { CStr::from_ptr(key).to_string_lossy().into_owned() });
+            unsafe { libc::free(key as *mut libc::c_void) };
+        }
+        unsafe { libc::free(iter) };
+        Ok(keys)
+    }
+    fn dict_get(node: &Self::Node, key: &str) -> Result<Option<Self::Node>, PlistError> {
+        expect_type(*node, PlistType::Dictionary)?;
+        let key = c_string(key)?;
+        let item = unsafe { unsafe_bindings::plist_dict_get_item(*node, key.as_ptr()) };
+        Ok(if item.is_null() { None } else { Some(item) })
+    }
+    fn dict_set(node: &mut Self::Node, key: &str, item: Self::Node) -> Result<(), PlistError> {
+        expect_type(*node, PlistType::Dictionary)?;
+        let key = c_string(key)?;
+        unsafe { unsafe_bindings::plist_dict_set_item(*node, key.as_ptr(), item) };
+        Ok(())
+    }
+    fn dict_remove(node: &mut Self::Node, key: &str) -> Result<(), PlistError> {
+        expect_type(*node, PlistType::Dictionary)?;
+        let key = c_string(key)?;
+        unsafe { unsafe_bindings::plist_dict_remove_item(*node, key.as_ptr()) };
+        Ok(())
+    }
+    fn dict_item_key(node: &Self::Node) -> Result<String, PlistError> {
+        expect_type(Self::parent(node), PlistType::Dictionary)?;
+        let mut key = std::ptr::null_mut();
+        unsafe { unsafe_bindings::plist_dict_get_item_key(*node, &mut key) };
+        Ok(take_string(key))
+    }
+    fn dict_item_key_node(node: &Self::Node) -> Self::Node {
+        // The key node belongs to the dictionary, so the caller gets a copy it can free
+        let key = unsafe { unsafe_bindings::plist_dict_item_get_key(*node) };
+        if key.is_null() {
+            return key;
+        }
+        Self::copy(&key)
+    }
+    fn dict_merge(node: &mut Self::Node, source: &Self::Node) -> Result<(), PlistError> {
+        expect_type(*node, PlistType::Dictionary)?;
+        expect_type(*source, PlistType::Dictionary)?;
+        unsafe { unsafe_bindings::plist_dict_merge(node, *source) };
+        Ok(())
+    }
+    fn sort(node: &mut Self::Node) {
+        unsafe { unsafe_bindings::plist_sort(*node) }
+    }
+
+    fn to_xml(node: &Self::Node) -> Result<String, PlistError> {
+        let mut data = std::ptr::null_mut();
+        let mut size = 0;
+        trace!("Converting plist to XML data");
+        let result = unsafe { unsafe_bindings::plist_to_xml(*node, &mut data, &mut size) };
+        if result != 0 {
+            return Err(result.into());
+        }
+        text_buffer(data, size)
+    }
+    fn from_xml(xml: &str) -> Result<Self::Node, PlistError> {
+        let mut node = std::ptr::null_mut();
+        trace!("Parsing xml");
+        let result = unsafe {
+            unsafe_bindings::plist_from_xml(
+                xml.as_ptr() as *const c_char,
+                xml.len() as u32,
+                &mut node,
+            )
+        };
+        if result != 0 {
+            return Err(result.into());
+        }
+        Ok(node)
+    }
+    fn to_bin(node: &Self::Node) -> Result<Vec<u8>, PlistError> {
+        let mut data = std::ptr::null_mut();
+        let mut size = 0;
+        trace!("Converting plist to binary data");
+        let result = unsafe { unsafe_bindings::plist_to_bin(*node, &mut data, &mut size) };
+        if result != 0 {
+            return Err(result.into());
+        }
+        Ok(take_buffer(data, size as u64))
+    }
+    fn from_bin(bin: &[u8]) -> Result<Self::Node, PlistError> {
+        let mut node = std::ptr::null_mut();
+        let result = unsafe {
+            unsafe_bindings::plist_from_bin(
+                bin.as_ptr() as *const c_char,
+                bin.len() as u32,
+                &mut node,
+            )
+        };
+        if result != 0 {
+            return Err(result.into());
+        }
+        Ok(node)
+    }
+    fn to_json(node: &Self::Node) -> Result<String, PlistError> {
+        let mut data = std::ptr::null_mut();
+        let mut size = 0;
+        trace!("Converting plist to JSON data");
+        let result = unsafe { unsafe_bindings::plist_to_json(*node, &mut data, &mut size, 1) };
+        if result != 0 {
+            return Err(result.into());
+        }
+        text_buffer(data, size)
+    }
+    fn from_json(json: &str) -> Result<Self::Node, PlistError> {
+        let mut node = std::ptr::null_mut();
+        trace!("Parsing json");
+        let result = unsafe {
+            unsafe_bindings::plist_from_json(
+                json.as_ptr() as *const c_char,
+                json.len() as u32,
+                &mut node,
+            )
+        };
+        if result != 0 {
+            return Err(result.into());
+        }
+        Ok(node)
+    }
+    fn to_openstep(node: &Self::Node) -> Result<String, PlistError> {
+        let mut data = std::ptr::null_mut();
+        let mut size = 0;
+        trace!("Converting plist to OpenStep data");
+        let result = unsafe { unsafe_bindings::plist_to_openstep(*node, &mut data, &mut size, 1) };
+        if result != 0 {
+            return Err(result.into());
+        }
+        text_buffer(data, size)
+    }
+    fn from_openstep(openstep: &str) -> Result<Self::Node, PlistError> {
+        let mut node = std::ptr::null_mut();
+        trace!("Parsing openstep");
+        let result = unsafe {
+            unsafe_bindings::plist_from_openstep(
+                openstep.as_ptr() as *const c_char,
+                openstep.len() as u32,
+                &mut node,
+            )
+        };
+        if result != 0 {
+            return Err(result.into());
+        }
+        Ok(node)
+    }
+    fn from_memory(bin: &[u8]) -> Result<Self::Node, PlistError> {
+        let mut node = std::ptr::null_mut();
+        let result = unsafe {
+            unsafe_bindings::plist_from_memory(
+                bin.as_ptr() as *const c_char,
+                bin.len() as u32,
+                &mut node,
+                std::ptr::null_mut(),
+            )
+        };
+        if result != 0 {
+            return Err(result.into());
+        }
+        Ok(node)
+    }
+    fn is_binary(bin: &[u8]) -> bool {
+        let len = bin.len() as u32;
+        unsafe { unsafe_bindings::plist_is_binary(bin.as_ptr() as *const c_char, len) != 0 }
+    }
+}
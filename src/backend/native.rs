@@ -0,0 +1,542 @@
+// jkcoxson
+
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+
+use super::Backend;
+use crate::{
+    error::PlistError,
+    limits::{self, ParseLimits},
+    types::date::MAC_EPOCH,
+    value::system_time_to_mac_secs,
+    PlistType, PlistValue,
+};
+
+/// The backend built in Rust, which doesn't need the C library
+/// Nodes are heap allocated and know their parent like libplist's do, so items returned from
+/// containers point into their parent and `Plist` behaves the same on both backends.
+pub(crate) struct Native;
+
+/// A single node of a native tree
+/// Public only so `Plist` can be built from a handle, the module itself is private
+pub struct NativeNode {
+    value: NodeValue,
+    /// The container holding this node, or null for a root
+    parent: *mut NativeNode,
+}
+
+enum NodeValue {
+    Boolean(bool),
+    Integer(i128),
+    Real(f64),
+    Date(SystemTime),
+    Data(Vec<u8>),
+    String(String),
+    Key(String),
+    Uid(u64),
+    Null,
+    Array(Vec<*mut NativeNode>),
+    Dictionary(Vec<(String, *mut NativeNode)>),
+}
+
+fn type_error<T>(expected: PlistType) -> Result<T, PlistError> {
+    warn!("Expected a plist of type {:?}", expected);
+    Err(PlistError::InvalidArg)
+}
+
+/// Rejects strings libplist couldn't store, so setters fail the same way on both backends
+fn no_nul(val: &str) -> Result<String, PlistError> {
+    if val.contains('\0') {
+        warn!("String holds a NUL byte");
+        return Err(PlistError::InvalidArg);
+    }
+    Ok(val.to_string())
+}
+
+fn index_error<T>(index: u32) -> Result<T, PlistError> {
+    warn!("Array index {} is out of range", index);
+    Err(PlistError::InvalidArg)
+}
+
+fn alloc(value: NodeValue) -> *mut NativeNode {
+    Box::into_raw(Box::new(NativeNode {
+        value,
+        parent: std::ptr::null_mut(),
+    }))
+}
+
+fn value<'a>(node: *mut NativeNode) -> Option<&'a NodeValue> {
+    unsafe { node.as_ref() }.map(|node| &node.value)
+}
+
+fn value_mut<'a>(node: *mut NativeNode) -> Option<&'a mut NodeValue> {
+    unsafe { node.as_mut() }.map(|node| &mut node.value)
+}
+
+fn parent(node: *mut NativeNode) -> *mut NativeNode {
+    match unsafe { node.as_ref() } {
+        Some(node) => node.parent,
+        None => std::ptr::null_mut(),
+    }
+}
+
+fn items<'a>(node: *mut NativeNode) -> Result<&'a mut Vec<*mut NativeNode>, PlistError> {
+    match value_mut(node) {
+        Some(NodeValue::Array(items)) => Ok(items),
+        _ => type_error(PlistType::Array),
+    }
+}
+
+fn entries<'a>(
+    node: *mut NativeNode,
+) -> Result<&'a mut Vec<(String, *mut NativeNode)>, PlistError> {
+    match value_mut(node) {
+        Some(NodeValue::Dictionary(entries)) => Ok(entries),
+        _ => type_error(PlistType::Dictionary),
+    }
+}
+
+/// Makes a node the child of a container
+/// A node can only be in one container, like in libplist, and can't contain itself
+fn attach(container: *mut NativeNode, item: *mut NativeNode) -> Result<(), PlistError> {
+    let mut ancestor = container;
+    while !ancestor.is_null() {
+        if ancestor == item {
+            warn!("Cannot add a plist to itself");
+            return Err(PlistError::InvalidArg);
+        }
+        ancestor = parent(ancestor);
+    }
+    match unsafe { item.as_mut() } {
+        Some(item) if item.parent.is_null() => {
+            item.parent = container;
+            Ok(())
+        }
+        Some(_) => {
+            warn!("The item is already in a container");
+            Err(PlistError::InvalidArg)
+        }
+        None => {
+            warn!("Cannot add a null plist to a container");
+            Err(PlistError::InvalidArg)
+        }
+    }
+}
+
+/// Frees a node that is no longer in a container, along with everything it contains
+fn destroy(node: *mut NativeNode) {
+    if node.is_null() {
+        return;
+    }
+    let node = unsafe { Box::from_raw(node) };
+    release(node.value);
+}
+
+/// Frees the children of a value that is being dropped or replaced
+fn release(value: NodeValue) {
+    match value {
+        NodeValue::Array(items) => items.into_iter().for_each(destroy),
+        NodeValue::Dictionary(entries) => entries.into_iter().for_each(|(_, item)| destroy(item)),
+        _ => {}
+    }
+}
+
+/// Replaces the value of a node, freeing the children it had
+fn replace(node: *mut NativeNode, new: NodeValue) {
+    if let Some(old) = value_mut(node) {
+        release(std::mem::replace(old, new));
+    }
+}
+
+/// Takes a node out of its container without freeing it
+fn detach(node: *mut NativeNode) {
+    let container = parent(node);
+    match value_mut(container) {
+        Some(NodeValue::Array(items)) => items.retain(|item| *item != node),
+        Some(NodeValue::Dictionary(entries)) => entries.retain(|(_, item)| *item != node),
+        _ => {}
+    }
+    if let Some(node) = unsafe { node.as_mut() } {
+        node.parent = std::ptr::null_mut();
+    }
+}
+
+fn date(sec: i32, usec: i32) -> SystemTime {
+    let micros = (sec as i64 + MAC_EPOCH as i64) * 1000000 + usec as i64;
+    if micros >= 0 {
+        SystemTime::UNIX_EPOCH + Duration::from_micros(micros as u64)
+    } else {
+        SystemTime::UNIX_EPOCH - Duration::from_micros(micros.unsigned_abs())
+    }
+}
+
+/// Builds a native tree from a value
+fn build(value: &PlistValue) -> *mut NativeNode {
+    let node = alloc(match value {
+        PlistValue::Boolean(val) => NodeValue::Boolean(*val),
+        PlistValue::Integer(val) => NodeValue::Integer(*val),
+        PlistValue::Real(val) => NodeValue::Real(*val),
+        PlistValue::Date(val) => NodeValue::Date(*val),
+        PlistValue::Data(val) => NodeValue::Data(val.clone()),
+        PlistValue::String(val) => NodeValue::String(val.clone()),
+        PlistValue::Uid(val) => NodeValue::Uid(*val),
+        PlistValue::Null => NodeValue::Null,
+        PlistValue::Array(_) => NodeValue::Array(Vec::new()),
+        PlistValue::Dictionary(_) => NodeValue::Dictionary(Vec::new()),
+    });
+    match (value, value_mut(node)) {
+        (PlistValue::Array(items), Some(NodeValue::Array(children))) => {
+            for item in items {
+                let child = build(item);
+                unsafe { (*child).parent = node };
+                children.push(child);
+            }
+        }
+        (PlistValue::Dictionary(entries), Some(NodeValue::Dictionary(children))) => {
+            for (key, item) in entries {
+                let child = build(item);
+                unsafe { (*child).parent = node };
+                children.push((key.clone(), child));
+            }
+        }
+        _ => {}
+    }
+    node
+}
+
+/// Copies a native tree into a value
+fn to_value(node: *mut NativeNode) -> PlistValue {
+    match value(node) {
+        Some(NodeValue::Boolean(val)) => PlistValue::Boolean(*val),
+        Some(NodeValue::Integer(val)) => PlistValue::Integer(*val),
+        Some(NodeValue::Real(val)) => PlistValue::Real(*val),
+        Some(NodeValue::Date(val)) => PlistValue::Date(*val),
+        Some(NodeValue::Data(val)) => PlistValue::Data(val.clone()),
+        Some(NodeValue::String(val)) | Some(NodeValue::Key(val)) => PlistValue::String(val.clone()),
+        Some(NodeValue::Uid(val)) => PlistValue::Uid(*val),
+        Some(NodeValue::Array(items)) => {
+            PlistValue::Array(items.iter().map(|item| to_value(*item)).collect())
+        }
+        Some(NodeValue::Dictionary(entries)) => PlistValue::Dictionary(
+            entries
+                .iter()
+                .map(|(key, item)| (key.clone(), to_value(*item)))
+                .collect(),
+        ),
+        Some(NodeValue::Null) | None => PlistValue::Null,
+    }
+}
+
+/// Sorts the keys of every dictionary in the tree
+fn sort(node: *mut NativeNode) {
+    match value_mut(node) {
+        Some(NodeValue::Array(items)) => items.iter().for_each(|item| sort(*item)),
+        Some(NodeValue::Dictionary(entries)) => {
+            entries.sort_by(|(l, _), (r, _)| l.cmp(r));
+            entries.iter().for_each(|(_, item)| sort(*item));
+        }
+        _ => {}
+    }
+}
+
+impl Backend for Native {
+    type Node = *mut NativeNode;
+
+    fn new_bool(val: bool) -> Self::Node {
+        alloc(NodeValue::Boolean(val))
+    }
+    fn new_uint(val: u64) -> Self::Node {
+        alloc(NodeValue::Integer(val as i128))
+    }
+    fn new_int(val: i64) -> Self::Node {
+        alloc(NodeValue::Integer(val as i128))
+    }
+    fn new_real(val: f64) -> Self::Node {
+        alloc(NodeValue::Real(val))
+    }
+    fn new_string(val: &str) -> Self::Node {
+        alloc(NodeValue::String(val.to_string()))
+    }
+    fn new_data(val: &[u8]) -> Self::Node {
+        alloc(NodeValue::Data(val.to_vec()))
+    }
+    fn new_date(sec: i32, usec: i32) -> Self::Node {
+        alloc(NodeValue::Date(date(sec, usec)))
+    }
+    fn new_uid(val: u64) -> Self::Node {
+        alloc(NodeValue::Uid(val))
+    }
+    fn new_null() -> Self::Node {
+        alloc(NodeValue::Null)
+    }
+    fn new_array() -> Self::Node {
+        alloc(NodeValue::Array(Vec::new()))
+    }
+    fn new_dict() -> Self::Node {
+        alloc(NodeValue::Dictionary(Vec::new()))
+    }
+
+    fn copy(node: &Self::Node) -> Self::Node {
+        if node.is_null() {
+            return std::ptr::null_mut();
+        }
+        match value(*node) {
+            Some(NodeValue::Key(key)) => alloc(NodeValue::Key(key.clone())),
+            _ => build(&to_value(*node)),
+        }
+    }
+    fn free(node: Self::Node) {
+        detach(node);
+        destroy(node);
+    }
+    fn node_type(node: &Self::Node) -> PlistType {
+        match value(*node) {
+            Some(NodeValue::Boolean(_)) => PlistType::Boolean,
+            Some(NodeValue::Integer(_)) => PlistType::Integer,
+            Some(NodeValue::Real(_)) => PlistType::Real,
+            Some(NodeValue::Date(_)) => PlistType::Date,
+            Some(NodeValue::Data(_)) => PlistType::Data,
+            Some(NodeValue::String(_)) => PlistType::String,
+            Some(NodeValue::Key(_)) => PlistType::Key,
+            Some(NodeValue::Uid(_)) => PlistType::Uid,
+            Some(NodeValue::Null) => PlistType::None,
+            Some(NodeValue::Array(_)) => PlistType::Array,
+            Some(NodeValue::Dictionary(_)) => PlistType::Dictionary,
+            // libplist reports PLIST_NONE for a null node, which maps to Unknown
+            None => PlistType::Unknown,
+        }
+    }
+    fn parent(node: &Self::Node) -> Self::Node {
+        parent(*node)
+    }
+
+    fn get_bool(node: &Self::Node) -> Result<bool, PlistError> {
+        match value(*node) {
+            Some(NodeValue::Boolean(val)) => Ok(*val),
+            _ => type_error(PlistType::Boolean),
+        }
+    }
+    fn get_uint(node: &Self::Node) -> Result<u64, PlistError> {
+        match value(*node) {
+            // Negative integers come back as their two's complement, like libplist
+            Some(NodeValue::Integer(val)) => Ok(*val as u64),
+            _ => type_error(PlistType::Integer),
+        }
+    }
+    fn get_int(node: &Self::Node) -> Result<i128, PlistError> {
+        match value(*node) {
+            Some(NodeValue::Integer(val)) => Ok(*val),
+            _ => type_error(PlistType::Integer),
+        }
+    }
+    fn get_real(node: &Self::Node) -> Result<f64, PlistError> {
+        match value(*node) {
+            Some(NodeValue::Real(val)) => Ok(*val),
+            _ => type_error(PlistType::Real),
+        }
+    }
+    fn get_string(node: &Self::Node) -> Result<String, PlistError> {
+        match value(*node) {
+            Some(NodeValue::String(val)) => Ok(val.clone()),
+            _ => type_error(PlistType::String),
+        }
+    }
+    fn get_key(node: &Self::Node) -> Result<String, PlistError> {
+        match value(*node) {
+            Some(NodeValue::Key(val)) => Ok(val.clone()),
+            _ => type_error(PlistType::Key),
+        }
+    }
+    fn get_data(node: &Self::Node) -> Result<Vec<u8>, PlistError> {
+        match value(*node) {
+            Some(NodeValue::Data(val)) => Ok(val.clone()),
+            _ => type_error(PlistType::Data),
+        }
+    }
+    fn get_date(node: &Self::Node) -> Result<(i32, i32), PlistError> {
+        match value(*node) {
+            Some(NodeValue::Date(time)) => {
+                let secs = system_time_to_mac_secs(*time);
+                let sec = secs.floor();
+                let usec = ((secs - sec) * 1000000.0).round();
+                Ok((sec as i32, usec as i32))
+            }
+            _ => type_error(PlistType::Date),
+        }
+    }
+    fn get_uid(node: &Self::Node) -> Result<u64, PlistError> {
+        match value(*node) {
+            Some(NodeValue::Uid(val)) => Ok(*val),
+            _ => type_error(PlistType::Uid),
+        }
+    }
+
+    fn set_bool(node: &mut Self::Node, val: bool) {
+        replace(*node, NodeValue::Boolean(val));
+    }
+    fn set_uint(node: &mut Self::Node, val: u64) {
+        replace(*node, NodeValue::Integer(val as i128));
+    }
+    fn set_real(node: &mut Self::Node, val: f64) {
+        replace(*node, NodeValue::Real(val));
+    }
+    fn set_string(node: &mut Self::Node, val: &str) -> Result<(), PlistError> {
+        replace(*node, NodeValue::String(no_nul(val)?));
+        Ok(())
+    }
+    fn set_key(node: &mut Self::Node, val: &str) -> Result<(), PlistError> {
+        replace(*node, NodeValue::Key(no_nul(val)?));
+        Ok(())
+    }
+    fn set_data(node: &mut Self::Node, val: &[u8]) {
+        replace(*node, NodeValue::Data(val.to_vec()));
+    }
+    fn set_date(node: &mut Self::Node, sec: i32, usec: i32) {
+        replace(*node, NodeValue::Date(date(sec, usec)));
+    }
+    fn set_uid(node: &mut Self::Node, val: u64) {
+        replace(*node, NodeValue::Uid(val));
+    }
+
+    fn array_len(node: &Self::Node) -> Result<u32, PlistError> {
+        Ok(items(*node)?.len() as u32)
+    }
+    fn array_get(node: &Self::Node, index: u32) -> Result<Self::Node, PlistError> {
+        match items(*node)?.get(index as usize) {
+            Some(item) => Ok(*item),
+            None => index_error(index),
+        }
+    }
+    fn array_set(node: &mut Self::Node, index: u32, item: Self::Node) -> Result<(), PlistError> {
+        let items = items(*node)?;
+        if index as usize >= items.len() {
+            return index_error(index);
+        }
+        attach(*node, item)?;
+        destroy(std::mem::replace(&mut items[index as usize], item));
+        Ok(())
+    }
+    fn array_append(node: &mut Self::Node, item: Self::Node) -> Result<(), PlistError> {
+        let items = items(*node)?;
+        attach(*node, item)?;
+        items.push(item);
+        Ok(())
+    }
+    fn array_insert(node: &mut Self::Node, index: u32, item: Self::Node) -> Result<(), PlistError> {
+        let items = items(*node)?;
+        if index as usize > items.len() {
+            return index_error(index);
+        }
+        attach(*node, item)?;
+        items.insert(index as usize, item);
+        Ok(())
+    }
+    fn array_remove(node: &mut Self::Node, index: u32) -> Result<(), PlistError> {
+        let items = items(*node)?;
+        if index as usize >= items.len() {
+            return index_error(index);
+        }
+        destroy(items.remove(index as usize));
+        Ok(())
+    }
+    fn array_item_index(node: &Self::Node) -> Result<u32, PlistError> {
+        let items = items(parent(*node))?;
+        match items.iter().position(|item| item == node) {
+            Some(index) => Ok(index as u32),
+            None => type_error(PlistType::Array),
+        }
+    }
+    fn array_item_remove(node: &mut Self::Node) -> Result<(), PlistError> {
+        items(parent(*node))?;
+        detach(*node);
+        destroy(*node);
+        Ok(())
+    }
+
+    fn dict_len(node: &Self::Node) -> Result<u32, PlistError> {
+        Ok(entries(*node)?.len() as u32)
+    }
+    fn dict_keys(node: &Self::Node) -> Result<Vec<String>, PlistError> {
+        Ok(entries(*node)?.iter().map(|(key, _)| key.clone()).collect())
+    }
+    fn dict_get(node: &Self::Node, key: &str) -> Result<Option<Self::Node>, PlistError> {
+        Ok(entries(*node)?
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, item)| *item))
+    }
+    fn dict_set(node: &mut Self::Node, key: &str, item: Self::Node) -> Result<(), PlistError> {
+        let entries = entries(*node)?;
+        attach(*node, item)?;
+        match entries.iter_mut().find(|(k, _)| k == key) {
+            Some((_, slot)) => destroy(std::mem::replace(slot, item)),
+            None => entries.push((key.to_string(), item)),
+        }
+        Ok(())
+    }
+    fn dict_remove(node: &mut Self::Node, key: &str) -> Result<(), PlistError> {
+        let entries = entries(*node)?;
+        if let Some(index) = entries.iter().position(|(k, _)| k == key) {
+            destroy(entries.remove(index).1);
+        }
+        Ok(())
+    }
+    fn dict_item_key(node: &Self::Node) -> Result<String, PlistError> {
+        let entries = entries(parent(*node))?;
+        match entries.iter().find(|(_, item)| item == node) {
+            Some((key, _)) => Ok(key.clone()),
+            None => type_error(PlistType::Dictionary),
+        }
+    }
+    fn dict_item_key_node(node: &Self::Node) -> Self::Node {
+        match Self::dict_item_key(node) {
+            Ok(key) => alloc(NodeValue::Key(key)),
+            Err(_) => std::ptr::null_mut(),
+        }
+    }
+    fn dict_merge(node: &mut Self::Node, source: &Self::Node) -> Result<(), PlistError> {
+        entries(*node)?;
+        for (key, item) in entries(*source)?.clone() {
+            Self::dict_set(node, &key, Self::copy(&item))?;
+        }
+        Ok(())
+    }
+    fn sort(node: &mut Self::Node) {
+        sort(*node);
+    }
+
+    fn to_xml(node: &Self::Node) -> Result<String, PlistError> {
+        crate::xml::to_string(&to_value(*node))
+    }
+    fn from_xml(xml: &str) -> Result<Self::Node, PlistError> {
+        Ok(build(&crate::xml::from_str(xml)?))
+    }
+    fn to_bin(node: &Self::Node) -> Result<Vec<u8>, PlistError> {
+        to_value(*node).to_bin()
+    }
+    fn from_bin(bin: &[u8]) -> Result<Self::Node, PlistError> {
+        Ok(build(&crate::bplist::from_bytes(bin)?))
+    }
+    fn to_json(node: &Self::Node) -> Result<String, PlistError> {
+        crate::json::to_string(&to_value(*node))
+    }
+    fn from_json(json: &str) -> Result<Self::Node, PlistError> {
+        Ok(build(&crate::json::from_str(json)?))
+    }
+    fn to_openstep(node: &Self::Node) -> Result<String, PlistError> {
+        crate::openstep::to_string(&to_value(*node))
+    }
+    fn from_openstep(openstep: &str) -> Result<Self::Node, PlistError> {
+        Ok(build(&crate::openstep::from_str(openstep)?))
+    }
+    fn from_memory(bin: &[u8]) -> Result<Self::Node, PlistError> {
+        Ok(build(&limits::from_memory(
+            bin,
+            &ParseLimits::depth_only(),
+        )?))
+    }
+    fn is_binary(bin: &[u8]) -> bool {
+        bin.starts_with(b"bplist00")
+    }
+}
@@ -0,0 +1,167 @@
+// jkcoxson
+
+use log::warn;
+
+use crate::error::PlistError;
+
+mod inspect;
+mod reader;
+mod writer;
+
+pub use inspect::{inspect, Inspection, ObjectInfo, ObjectKind};
+pub use reader::{from_bytes, from_bytes_with_limits};
+pub use writer::{BinaryWriter, WriteStats};
+
+/// The header every binary plist starts with
+pub const MAGIC: &[u8] = b"bplist00";
+/// The size of the trailer at the end of every binary plist
+pub const TRAILER_SIZE: usize = 32;
+
+/// The trailer at the end of a binary plist describing where to find its objects
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Trailer {
+    /// The size in bytes of each entry in the offset table
+    pub offset_size: u8,
+    /// The size in bytes of each object reference inside containers
+    pub ref_size: u8,
+    /// The number of objects in the plist
+    pub object_count: u64,
+    /// The index of the root object
+    pub top_object: u64,
+    /// The position of the offset table in the file
+    pub offset_table_offset: u64,
+}
+
+impl Trailer {
+    /// Reads and validates the header and trailer of a binary plist
+    pub fn parse(bytes: &[u8]) -> Result<Trailer, PlistError> {
+        if bytes.len() < MAGIC.len() + TRAILER_SIZE || !bytes.starts_with(MAGIC) {
+            warn!("Not a binary plist");
+            return Err(PlistError::Format);
+        }
+        let trailer = &bytes[bytes.len() - TRAILER_SIZE..];
+        let read_u64 =
+            |start: usize| u64::from_be_bytes(trailer[start..start + 8].try_into().unwrap());
+        let trailer = Trailer {
+            offset_size: trailer[6],
+            ref_size: trailer[7],
+            object_count: read_u64(8),
+            top_object: read_u64(16),
+            offset_table_offset: read_u64(24),
+        };
+
+        if !(1..=8).contains(&trailer.offset_size) || !(1..=8).contains(&trailer.ref_size) {
+            return Err(parse_error("Invalid offset or reference size in trailer"));
+        }
+        if trailer.top_object >= trailer.object_count {
+            return Err(parse_error("Top object is out of range"));
+        }
+        // The offset table has to fit between the header and the trailer
+        let table_end = trailer
+            .object_count
+            .checked_mul(trailer.offset_size as u64)
+            .and_then(|size| size.checked_add(trailer.offset_table_offset));
+        match table_end {
+            Some(end)
+                if trailer.offset_table_offset >= MAGIC.len() as u64
+                    && end <= (bytes.len() - TRAILER_SIZE) as u64 => {}
+            _ => {
+                return Err(parse_error("Offset table is out of range"));
+            }
+        }
+        Ok(trailer)
+    }
+
+    /// Returns the position of the object with the given index
+    pub fn object_offset(&self, bytes: &[u8], index: u64) -> Result<usize, PlistError> {
+        if index >= self.object_count {
+            return Err(parse_error(format!(
+                "Object reference {} is out of range",
+                index
+            )));
+        }
+        let start = (self.offset_table_offset + index * self.offset_size as u64) as usize;
+        let entry = &bytes[start..start + self.offset_size as usize];
+        let offset = entry.iter().fold(0_u64, |acc, &b| (acc << 8) | b as u64);
+        if offset < MAGIC.len() as u64 || offset >= self.offset_table_offset {
+            return Err(parse_error(format!(
+                "Object {} has an invalid offset {}",
+                index, offset
+            )));
+        }
+        Ok(offset as usize)
+    }
+}
+
+/// Logs and returns a parse error
+/// Binary plists have no lines, so the message says where in the file the problem is
+pub(crate) fn parse_error(message: impl Into<String>) -> PlistError {
+    let message = message.into();
+    warn!("{}", message);
+    PlistError::parse(message)
+}
+
+/// Returns `len` bytes starting at `start`, or an error if the input is too short
+pub(crate) fn slice(bytes: &[u8], start: usize, len: usize) -> Result<&[u8], PlistError> {
+    let end = start
+        .checked_add(len)
+        .ok_or_else(|| parse_error("Length overflows"))?;
+    bytes
+        .get(start..end)
+        .ok_or_else(|| parse_error(format!("Unexpected end of binary plist at {}", start)))
+}
+
+/// Reads a big endian unsigned integer of any size up to 8 bytes
+pub(crate) fn read_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, &b| (acc << 8) | b as u64)
+}
+
+/// Reads the length of a data, string or container object
+/// Returns the length and the offset of the contents
+pub(crate) fn read_length(
+    bytes: &[u8],
+    offset: usize,
+    info: u8,
+) -> Result<(usize, usize), PlistError> {
+    if info != 0x0F {
+        return Ok((info as usize, offset + 1));
+    }
+    // Lengths of 15 or more are stored in an integer object following the marker
+    let marker = *slice(bytes, offset + 1, 1)?.first().unwrap();
+    if marker >> 4 != 0x1 || marker & 0x0F > 3 {
+        return Err(parse_error(format!(
+            "Invalid length marker at {}",
+            offset + 1
+        )));
+    }
+    let size = 1 << (marker & 0x0F);
+    let len = read_uint(slice(bytes, offset + 2, size)?);
+    if len > bytes.len() as u64 {
+        return Err(parse_error(format!(
+            "Length {} at {} is past the end of the plist",
+            len, offset
+        )));
+    }
+    Ok((len as usize, offset + 2 + size))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    /// { "a": 1, "b": [true, "hi"] } as written by plutil
+    pub(crate) const SAMPLE: &[u8] = &[
+        0x62, 0x70, 0x6c, 0x69, 0x73, 0x74, 0x30, 0x30, // bplist00
+        0xd2, 0x01, 0x02, 0x03, 0x04, // dict with 2 entries
+        0x51, 0x61, // "a"
+        0x51, 0x62, // "b"
+        0x10, 0x01, // 1
+        0xa2, 0x05, 0x06, // array with 2 items
+        0x09, // true
+        0x52, 0x68, 0x69, // "hi"
+        // offset table
+        0x08, 0x0d, 0x0f, 0x11, 0x13, 0x16, 0x17, // trailer
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x01, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1a,
+    ];
+}
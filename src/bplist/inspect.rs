@@ -0,0 +1,277 @@
+// jkcoxson
+
+use std::fmt::{Display, Formatter};
+
+use log::trace;
+
+use super::{read_length, read_uint, slice, Trailer, MAGIC};
+use crate::error::PlistError;
+
+/// The kind of object a marker byte describes
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ObjectKind {
+    Null,
+    Boolean,
+    Integer,
+    Real,
+    Date,
+    Data,
+    AsciiString,
+    Utf16String,
+    Uid,
+    Array,
+    Set,
+    Dictionary,
+    Unknown,
+}
+
+/// What the inspector found out about a single object
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ObjectInfo {
+    /// The index of the object in the offset table
+    pub index: u64,
+    /// The position of the object in the file
+    pub offset: u64,
+    /// The marker byte, if the offset points inside the file
+    pub marker: Option<u8>,
+    pub kind: ObjectKind,
+    /// The number of bytes, characters or items, for objects that have a length
+    pub len: Option<u64>,
+    /// The objects this one references, for containers
+    pub refs: Vec<u64>,
+    /// What is wrong with the object, if anything
+    pub problem: Option<String>,
+}
+
+/// The structure of a binary plist, as reported by `inspect`
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Inspection {
+    pub trailer: Trailer,
+    /// The offset of each object, indexed by object
+    pub offsets: Vec<u64>,
+    pub objects: Vec<ObjectInfo>,
+}
+
+impl Inspection {
+    /// Returns the objects that have a problem
+    pub fn problems(&self) -> impl Iterator<Item = &ObjectInfo> {
+        self.objects.iter().filter(|o| o.problem.is_some())
+    }
+}
+
+/// Reports the layout of a binary plist without building a tree
+/// Only a broken header or trailer is an error, problems with single objects are
+/// recorded on the object so the rest of the file can still be looked at
+/// ```rust
+/// let bin = [
+///     b'b', b'p', b'l', b'i', b's', b't', b'0', b'0', 0xa1, 0x01, 0x09, 0x08, 0x0a, 0, 0, 0, 0,
+///     0, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 11,
+/// ];
+/// let inspection = plist_plus::bplist::inspect(&bin).unwrap();
+/// assert_eq!(inspection.objects[0].refs, vec![1]);
+/// println!("{}", inspection);
+/// ```
+pub fn inspect(bytes: &[u8]) -> Result<Inspection, PlistError> {
+    trace!("Inspecting binary plist");
+    let trailer = Trailer::parse(bytes)?;
+    let table = &bytes[trailer.offset_table_offset as usize..];
+    let offsets: Vec<u64> = table
+        .chunks(trailer.offset_size as usize)
+        .take(trailer.object_count as usize)
+        .map(read_uint)
+        .collect();
+    let objects = offsets
+        .iter()
+        .enumerate()
+        .map(|(index, &offset)| inspect_object(bytes, &trailer, index as u64, offset))
+        .collect();
+    Ok(Inspection {
+        trailer,
+        offsets,
+        objects,
+    })
+}
+
+fn kind_of(marker: u8) -> ObjectKind {
+    let info = marker & 0x0F;
+    match marker >> 4 {
+        0x0 if info == 0x0 => ObjectKind::Null,
+        0x0 if info == 0x8 || info == 0x9 => ObjectKind::Boolean,
+        0x1 if info <= 4 => ObjectKind::Integer,
+        0x2 if info == 2 || info == 3 => ObjectKind::Real,
+        0x3 if info == 3 => ObjectKind::Date,
+        0x4 => ObjectKind::Data,
+        0x5 => ObjectKind::AsciiString,
+        0x6 => ObjectKind::Utf16String,
+        0x8 if info < 8 => ObjectKind::Uid,
+        0xA => ObjectKind::Array,
+        0xC => ObjectKind::Set,
+        0xD => ObjectKind::Dictionary,
+        _ => ObjectKind::Unknown,
+    }
+}
+
+fn inspect_object(bytes: &[u8], trailer: &Trailer, index: u64, offset: u64) -> ObjectInfo {
+    let mut info = ObjectInfo {
+        index,
+        offset,
+        marker: None,
+        kind: ObjectKind::Unknown,
+        len: None,
+        refs: Vec::new(),
+        problem: None,
+    };
+    if offset < MAGIC.len() as u64 || offset >= trailer.offset_table_offset {
+        info.problem = Some(format!("offset {} is outside the object table", offset));
+        return info;
+    }
+    // Nothing an object contains may run into the offset table
+    let objects = &bytes[..trailer.offset_table_offset as usize];
+    let offset = offset as usize;
+    let marker = objects[offset];
+    info.marker = Some(marker);
+    info.kind = kind_of(marker);
+
+    let low = marker & 0x0F;
+    let (len, start, size) = match info.kind {
+        ObjectKind::Unknown => {
+            info.problem = Some(format!("unknown marker {:#04x}", marker));
+            return info;
+        }
+        ObjectKind::Null | ObjectKind::Boolean => return info,
+        ObjectKind::Integer | ObjectKind::Real => (None, offset + 1, 1 << low),
+        ObjectKind::Date => (None, offset + 1, 8),
+        ObjectKind::Uid => (None, offset + 1, low as usize + 1),
+        kind => match read_length(objects, offset, low) {
+            Ok((len, start)) => {
+                let unit = match kind {
+                    ObjectKind::Data | ObjectKind::AsciiString => 1,
+                    ObjectKind::Utf16String => 2,
+                    ObjectKind::Dictionary => 2 * trailer.ref_size as usize,
+                    _ => trailer.ref_size as usize,
+                };
+                (Some(len as u64), start, len.saturating_mul(unit))
+            }
+            Err(_) => {
+                info.problem = Some("length runs past the object table".to_string());
+                return info;
+            }
+        },
+    };
+    info.len = len;
+
+    let contents = match slice(objects, start, size) {
+        Ok(contents) => contents,
+        Err(_) => {
+            info.problem = Some(format!(
+                "{} bytes of contents run past the object table",
+                size
+            ));
+            return info;
+        }
+    };
+    if matches!(
+        info.kind,
+        ObjectKind::Array | ObjectKind::Set | ObjectKind::Dictionary
+    ) {
+        info.refs = contents
+            .chunks(trailer.ref_size as usize)
+            .map(read_uint)
+            .collect();
+        if let Some(r) = info.refs.iter().find(|&&r| r >= trailer.object_count) {
+            info.problem = Some(format!("reference {} is out of range", r));
+        } else if info.refs.contains(&index) {
+            info.problem = Some("references itself".to_string());
+        }
+    }
+    info
+}
+
+impl Display for ObjectInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{} @{}", self.index, self.offset)?;
+        if let Some(marker) = self.marker {
+            write!(f, " {:#04x} {:?}", marker, self.kind)?;
+        }
+        if let Some(len) = self.len {
+            write!(f, " len={}", len)?;
+        }
+        if !self.refs.is_empty() {
+            write!(f, " refs={:?}", self.refs)?;
+        }
+        if let Some(problem) = &self.problem {
+            write!(f, " !! {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Inspection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "offset size {}, ref size {}, {} objects, top object {}, offset table at {}",
+            self.trailer.offset_size,
+            self.trailer.ref_size,
+            self.trailer.object_count,
+            self.trailer.top_object,
+            self.trailer.offset_table_offset
+        )?;
+        for object in &self.objects {
+            writeln!(f, "{}", object)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bplist::tests::SAMPLE;
+
+    #[test]
+    fn inspect_test() {
+        let inspection = inspect(SAMPLE).unwrap();
+        assert_eq!(inspection.trailer.object_count, 7);
+        assert_eq!(inspection.offsets, vec![8, 13, 15, 17, 19, 22, 23]);
+        let kinds: Vec<ObjectKind> = inspection.objects.iter().map(|o| o.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ObjectKind::Dictionary,
+                ObjectKind::AsciiString,
+                ObjectKind::AsciiString,
+                ObjectKind::Integer,
+                ObjectKind::Array,
+                ObjectKind::Boolean,
+                ObjectKind::AsciiString,
+            ]
+        );
+        assert_eq!(inspection.objects[0].refs, vec![1, 2, 3, 4]);
+        assert_eq!(inspection.objects[6].len, Some(2));
+        assert_eq!(inspection.problems().count(), 0);
+    }
+
+    #[test]
+    fn inspect_malformed_test() {
+        let mut broken = SAMPLE.to_vec();
+        // The array references itself and an object that doesn't exist
+        broken[20] = 0x04;
+        broken[21] = 0x09;
+        // The string claims to be longer than the file
+        broken[23] = 0x5e;
+        // The integer points into the header
+        broken[29] = 0x02;
+        let inspection = inspect(&broken).unwrap();
+        let problems: Vec<u64> = inspection.problems().map(|o| o.index).collect();
+        assert_eq!(problems, vec![3, 4, 6]);
+        assert!(inspection.objects[4]
+            .problem
+            .as_ref()
+            .unwrap()
+            .contains("9"));
+        assert!(inspection.to_string().contains("!!"));
+
+        assert!(inspect(&SAMPLE[..20]).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+// jkcoxson
+
+use log::trace;
+
+use crate::error::PlistError;
+use crate::limits::{Budget, ParseLimits};
+use crate::value::{mac_secs_to_system_time, PlistValue};
+
+use super::{parse_error, read_length, read_uint, slice, Trailer};
+
+/// Parses a binary plist (bplist00) into a value without using the C library
+/// Only the nesting depth is limited, so use `from_bytes_with_limits` for input that can't be trusted
+pub fn from_bytes(bytes: &[u8]) -> Result<PlistValue, PlistError> {
+    from_bytes_with_limits(bytes, &ParseLimits::depth_only())
+}
+
+/// Parses a binary plist, rejecting it as soon as it goes over the limits
+/// The object count in the trailer is checked before anything is read
+pub fn from_bytes_with_limits(
+    bytes: &[u8],
+    limits: &ParseLimits,
+) -> Result<PlistValue, PlistError> {
+    trace!("Parsing binary plist natively");
+    let budget = Budget::new(limits);
+    budget.total_bytes(bytes.len())?;
+    let trailer = Trailer::parse(bytes)?;
+    budget.nodes(trailer.object_count)?;
+    let mut reader = Reader {
+        bytes,
+        trailer,
+        stack: Vec::new(),
+        budget,
+    };
+    reader.read_object(trailer.top_object)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    trailer: Trailer,
+    /// Objects currently being read, used to detect reference cycles
+    stack: Vec<u64>,
+    budget: Budget,
+}
+
+impl Reader<'_> {
+    fn read_object(&mut self, index: u64) -> Result<PlistValue, PlistError> {
+        if self.stack.contains(&index) {
+            return Err(parse_error(format!("Object {} references itself", index)));
+        }
+        // Shared objects count every time they are read, so references can't blow up the tree
+        self.budget.node()?;
+        self.stack.push(index);
+        let offset = self.trailer.object_offset(self.bytes, index)?;
+        let value = self.read_object_at(offset);
+        self.stack.pop();
+        value
+    }
+
+    fn read_object_at(&mut self, offset: usize) -> Result<PlistValue, PlistError> {
+        let marker = *slice(self.bytes, offset, 1)?.first().unwrap();
+        let info = marker & 0x0F;
+        Ok(match marker >> 4 {
+            0x0 => match info {
+                0x0 => PlistValue::Null,
+                0x8 => PlistValue::Boolean(false),
+                0x9 => PlistValue::Boolean(true),
+                _ => {
+                    return Err(parse_error(format!(
+                        "Unknown marker {:#04x} at {}",
+                        marker, offset
+                    )));
+                }
+            },
+            0x1 => PlistValue::Integer(read_int(self.bytes, offset + 1, 1 << info)?),
+            0x2 => {
+                let bytes = slice(self.bytes, offset + 1, 1 << info)?;
+                PlistValue::Real(match bytes.len() {
+                    4 => f32::from_be_bytes(bytes.try_into().unwrap()) as f64,
+                    8 => f64::from_be_bytes(bytes.try_into().unwrap()),
+                    _ => return Err(parse_error(format!("Invalid real size at {}", offset))),
+                })
+            }
+            0x3 if info == 0x3 => {
+                let bytes = slice(self.bytes, offset + 1, 8)?;
+                let secs = f64::from_be_bytes(bytes.try_into().unwrap());
+                match mac_secs_to_system_time(secs) {
+                    Ok(time) => PlistValue::Date(time),
+                    Err(_) => return Err(parse_error(format!("Invalid date at {}", offset))),
+                }
+            }
+            0x4 => {
+                let (len, start) = self.read_length(offset, info)?;
+                self.budget.data(len)?;
+                PlistValue::Data(slice(self.bytes, start, len)?.to_vec())
+            }
+            0x5 => {
+                let (len, start) = self.read_length(offset, info)?;
+                self.budget.string(len)?;
+                // ASCII strings from other writers are sometimes Latin-1
+                PlistValue::String(
+                    slice(self.bytes, start, len)?
+                        .iter()
+                        .map(|&b| b as char)
+                        .collect(),
+                )
+            }
+            0x6 => {
+                let (len, start) = self.read_length(offset, info)?;
+                self.budget.string(len)?;
+                let units = slice(
+                    self.bytes,
+                    start,
+                    len.checked_mul(2)
+                        .ok_or_else(|| parse_error("Length overflows"))?,
+                )?
+                .chunks(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect::<Vec<u16>>();
+                PlistValue::String(
+                    String::from_utf16(&units)
+                        .map_err(|_| parse_error(format!("Invalid UTF-16 string at {}", offset)))?,
+                )
+            }
+            0x8 if info < 8 => {
+                PlistValue::Uid(read_uint(slice(self.bytes, offset + 1, info as usize + 1)?))
+            }
+            // Sets are read as arrays
+            0xA | 0xC => {
+                let (len, start) = self.read_length(offset, info)?;
+                let refs = self.read_refs(start, len)?;
+                self.budget.enter()?;
+                let mut items = Vec::with_capacity(refs.len());
+                for r in refs {
+                    items.push(self.read_object(r)?);
+                }
+                self.budget.leave();
+                PlistValue::Array(items)
+            }
+            0xD => {
+                let (len, start) = self.read_length(offset, info)?;
+                let keys = self.read_refs(start, len)?;
+                let values = self.read_refs(start + len * self.trailer.ref_size as usize, len)?;
+                self.budget.enter()?;
+                let mut entries = Vec::with_capacity(len);
+                for (k, v) in keys.into_iter().zip(values) {
+                    let key = match self.read_object(k)? {
+                        PlistValue::String(key) => key,
+                        _ => {
+                            return Err(parse_error(format!(
+                                "Dictionary key at {} is not a string",
+                                offset
+                            )));
+                        }
+                    };
+                    entries.push((key, self.read_object(v)?));
+                }
+                self.budget.leave();
+                PlistValue::Dictionary(entries)
+            }
+            _ => {
+                return Err(parse_error(format!(
+                    "Unknown marker {:#04x} at {}",
+                    marker, offset
+                )));
+            }
+        })
+    }
+
+    fn read_length(&self, offset: usize, info: u8) -> Result<(usize, usize), PlistError> {
+        read_length(self.bytes, offset, info)
+    }
+
+    fn read_refs(&self, start: usize, count: usize) -> Result<Vec<u64>, PlistError> {
+        let size = self.trailer.ref_size as usize;
+        let bytes = slice(
+            self.bytes,
+            start,
+            count
+                .checked_mul(size)
+                .ok_or_else(|| parse_error("Length overflows"))?,
+        )?;
+        Ok(bytes.chunks(size).map(read_uint).collect())
+    }
+}
+
+/// Reads an integer object's value
+/// 8 byte integers are signed, everything smaller is unsigned and 16 byte integers hold a u64
+fn read_int(bytes: &[u8], start: usize, size: usize) -> Result<i128, PlistError> {
+    let bytes = slice(bytes, start, size)?;
+    Ok(match size {
+        1 | 2 | 4 => read_uint(bytes) as i128,
+        8 => read_uint(bytes) as i64 as i128,
+        16 => read_uint(&bytes[8..]) as i128,
+        _ => {
+            return Err(parse_error(format!("Invalid integer size {}", size)));
+        }
+    })
+}
+
+impl PlistValue {
+    /// Parses a binary plist without using the C library
+    /// This is the native alternative to `Plist::from_bin`
+    pub fn from_bin(bin: &[u8]) -> Result<PlistValue, PlistError> {
+        from_bytes(bin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bplist::tests::SAMPLE;
+
+    /// A binary plist holding only a date
+    fn date_plist(secs: f64) -> Vec<u8> {
+        let mut bytes = b"bplist00\x33".to_vec();
+        bytes.extend_from_slice(&secs.to_be_bytes());
+        // The offset table, then the trailer
+        bytes.push(8);
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 1, 1]);
+        bytes.extend_from_slice(&1_u64.to_be_bytes());
+        bytes.extend_from_slice(&0_u64.to_be_bytes());
+        bytes.extend_from_slice(&17_u64.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn native_bin_test() {
+        let value = from_bytes(SAMPLE).unwrap();
+        assert!(matches!(
+            from_bytes(&date_plist(0.5)),
+            Ok(PlistValue::Date(_))
+        ));
+        assert_eq!(
+            value,
+            PlistValue::Dictionary(vec![
+                ("a".to_string(), PlistValue::Integer(1)),
+                (
+                    "b".to_string(),
+                    PlistValue::Array(vec![
+                        PlistValue::Boolean(true),
+                        PlistValue::String("hi".to_string())
+                    ])
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn native_bin_malformed_test() {
+        assert!(from_bytes(&SAMPLE[..SAMPLE.len() - 1]).is_err());
+        assert!(from_bytes(b"bplist00").is_err());
+
+        // Point the array's first item back at the array itself
+        let mut cyclic = SAMPLE.to_vec();
+        cyclic[20] = 0x04;
+        assert!(from_bytes(&cyclic).is_err());
+
+        // Dates that can't be a SystemTime
+        for secs in [f64::NAN, 1e300] {
+            assert!(from_bytes(&date_plist(secs)).is_err());
+        }
+    }
+
+    #[test]
+    fn native_bin_limits_test() {
+        use crate::limits::Limit;
+
+        let limits = |max_depth, max_nodes| ParseLimits {
+            max_depth,
+            max_nodes,
+            ..Default::default()
+        };
+        assert!(from_bytes_with_limits(SAMPLE, &limits(2, 7)).is_ok());
+        assert!(matches!(
+            from_bytes_with_limits(SAMPLE, &limits(1, 7)),
+            Err(PlistError::LimitExceeded {
+                limit: Limit::Depth,
+                ..
+            })
+        ));
+        // The trailer alone already claims too many objects
+        assert!(matches!(
+            from_bytes_with_limits(SAMPLE, &limits(2, 6)),
+            Err(PlistError::LimitExceeded {
+                limit: Limit::Nodes,
+                ..
+            })
+        ));
+
+        // Each array holds the next one twice, so 41 objects expand to 2^41 values
+        let mut bomb = b"bplist00".to_vec();
+        for i in 0..40_u8 {
+            bomb.extend([0xA2, i + 1, i + 1]);
+        }
+        bomb.push(0x08);
+        let table = bomb.len() as u64;
+        bomb.extend((0..41).map(|i| 8 + 3 * i as u8));
+        bomb.extend([0, 0, 0, 0, 0, 0, 1, 1]);
+        bomb.extend(41_u64.to_be_bytes());
+        bomb.extend(0_u64.to_be_bytes());
+        bomb.extend(table.to_be_bytes());
+        assert!(matches!(
+            from_bytes_with_limits(&bomb, &limits(64, 1000)),
+            Err(PlistError::LimitExceeded {
+                limit: Limit::Nodes,
+                max: 1000
+            })
+        ));
+    }
+}
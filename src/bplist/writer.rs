@@ -0,0 +1,442 @@
+// jkcoxson
+
+use std::collections::HashMap;
+
+use log::{trace, warn};
+
+use super::{MAGIC, TRAILER_SIZE};
+use crate::error::PlistError;
+use crate::value::{system_time_to_mac_secs, PlistValue};
+use crate::Plist;
+
+/// Writes binary plists natively, with control over the layout of the output
+/// ```rust
+/// use plist_plus::bplist::BinaryWriter;
+/// use plist_plus::PlistValue;
+///
+/// let value = PlistValue::Array(vec![
+///     PlistValue::String("same".to_string()),
+///     PlistValue::String("same".to_string()),
+/// ]);
+/// let (bytes, stats) = BinaryWriter::default().write_with_stats(&value).unwrap();
+/// assert_eq!(stats.object_count, 2);
+/// assert_eq!(plist_plus::bplist::from_bytes(&bytes).unwrap(), value);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BinaryWriter {
+    /// Write identical strings, including dictionary keys, only once
+    pub dedup_strings: bool,
+    /// Write identical data blobs only once
+    pub dedup_data: bool,
+    /// The size in bytes of each offset table entry, or None for the smallest that fits
+    pub offset_size: Option<u8>,
+    /// The size in bytes of each object reference, or None for the smallest that fits
+    pub ref_size: Option<u8>,
+}
+
+impl Default for BinaryWriter {
+    fn default() -> Self {
+        BinaryWriter {
+            dedup_strings: true,
+            dedup_data: true,
+            offset_size: None,
+            ref_size: None,
+        }
+    }
+}
+
+/// Statistics about a binary plist that was written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WriteStats {
+    /// The number of objects in the plist
+    pub object_count: u64,
+    /// The size in bytes of the offset table
+    pub offset_table_size: u64,
+    /// The bytes deduplication kept out of the object and offset tables
+    pub bytes_saved: u64,
+}
+
+/// An object after the tree has been flattened, with containers holding object references
+enum Object<'a> {
+    Scalar(&'a PlistValue),
+    Key(&'a str),
+    Array(Vec<u64>),
+    Dictionary(Vec<u64>, Vec<u64>),
+}
+
+struct Flattener<'a> {
+    writer: &'a BinaryWriter,
+    objects: Vec<Object<'a>>,
+    strings: HashMap<&'a str, u64>,
+    data: HashMap<&'a [u8], u64>,
+    /// The encoded size of every object that was skipped because it was a duplicate
+    duplicate_bytes: u64,
+    duplicate_count: u64,
+}
+
+impl<'a> Flattener<'a> {
+    fn push(&mut self, object: Object<'a>) -> u64 {
+        self.objects.push(object);
+        self.objects.len() as u64 - 1
+    }
+
+    fn string(&mut self, s: &'a str, object: Object<'a>) -> u64 {
+        if !self.writer.dedup_strings {
+            return self.push(object);
+        }
+        if let Some(&index) = self.strings.get(s) {
+            self.duplicate_bytes += string_size(s);
+            self.duplicate_count += 1;
+            return index;
+        }
+        let index = self.push(object);
+        self.strings.insert(s, index);
+        index
+    }
+
+    fn flatten(&mut self, value: &'a PlistValue) -> u64 {
+        match value {
+            PlistValue::String(s) => self.string(s, Object::Scalar(value)),
+            PlistValue::Data(bytes) if self.writer.dedup_data => {
+                if let Some(&index) = self.data.get(bytes.as_slice()) {
+                    self.duplicate_bytes += header_size(bytes.len() as u64) + bytes.len() as u64;
+                    self.duplicate_count += 1;
+                    return index;
+                }
+                let index = self.push(Object::Scalar(value));
+                self.data.insert(bytes, index);
+                index
+            }
+            PlistValue::Array(items) => {
+                // Containers come before their children, like CoreFoundation writes them
+                let index = self.push(Object::Array(Vec::new()));
+                let refs = items.iter().map(|item| self.flatten(item)).collect();
+                self.objects[index as usize] = Object::Array(refs);
+                index
+            }
+            PlistValue::Dictionary(entries) => {
+                let index = self.push(Object::Dictionary(Vec::new(), Vec::new()));
+                let keys = entries
+                    .iter()
+                    .map(|(key, _)| self.string(key, Object::Key(key)))
+                    .collect();
+                let values = entries.iter().map(|(_, item)| self.flatten(item)).collect();
+                self.objects[index as usize] = Object::Dictionary(keys, values);
+                index
+            }
+            _ => self.push(Object::Scalar(value)),
+        }
+    }
+}
+
+/// Returns the smallest of 1, 2, 4 or 8 bytes that can hold the value
+fn int_size(val: u64) -> u8 {
+    match val {
+        0..=0xFF => 1,
+        0x100..=0xFFFF => 2,
+        0x10000..=0xFFFF_FFFF => 4,
+        _ => 8,
+    }
+}
+
+/// Returns the smallest number of bytes that can hold the value
+fn min_size(val: u64) -> u8 {
+    ((64 - val.leading_zeros()).div_ceil(8) as u8).max(1)
+}
+
+fn header_size(len: u64) -> u64 {
+    if len < 15 {
+        1
+    } else {
+        2 + int_size(len) as u64
+    }
+}
+
+fn string_size(s: &str) -> u64 {
+    if s.is_ascii() {
+        header_size(s.len() as u64) + s.len() as u64
+    } else {
+        let units = s.encode_utf16().count() as u64;
+        header_size(units) + units * 2
+    }
+}
+
+fn push_uint(out: &mut Vec<u8>, val: u64, size: u8) {
+    out.extend_from_slice(&val.to_be_bytes()[8 - size as usize..]);
+}
+
+/// Writes a marker with the length in its low nibble, or in a following integer if it doesn't fit
+fn push_header(out: &mut Vec<u8>, marker: u8, len: u64) {
+    if len < 15 {
+        out.push(marker | len as u8);
+    } else {
+        let size = int_size(len);
+        out.push(marker | 0xF);
+        out.push(0x10 | size.trailing_zeros() as u8);
+        push_uint(out, len, size);
+    }
+}
+
+fn push_string(out: &mut Vec<u8>, s: &str) {
+    if s.is_ascii() {
+        push_header(out, 0x50, s.len() as u64);
+        out.extend_from_slice(s.as_bytes());
+    } else {
+        let units: Vec<u16> = s.encode_utf16().collect();
+        push_header(out, 0x60, units.len() as u64);
+        for unit in units {
+            out.extend_from_slice(&unit.to_be_bytes());
+        }
+    }
+}
+
+fn push_refs(out: &mut Vec<u8>, refs: &[u64], ref_size: u8) {
+    for &r in refs {
+        push_uint(out, r, ref_size);
+    }
+}
+
+fn push_object(out: &mut Vec<u8>, object: &Object, ref_size: u8) -> Result<(), PlistError> {
+    let value = match object {
+        Object::Key(key) => {
+            push_string(out, key);
+            return Ok(());
+        }
+        Object::Array(refs) => {
+            push_header(out, 0xA0, refs.len() as u64);
+            push_refs(out, refs, ref_size);
+            return Ok(());
+        }
+        Object::Dictionary(keys, values) => {
+            push_header(out, 0xD0, keys.len() as u64);
+            push_refs(out, keys, ref_size);
+            push_refs(out, values, ref_size);
+            return Ok(());
+        }
+        Object::Scalar(value) => value,
+    };
+    match value {
+        PlistValue::Null => out.push(0x00),
+        PlistValue::Boolean(false) => out.push(0x08),
+        PlistValue::Boolean(true) => out.push(0x09),
+        PlistValue::Integer(i) if *i < 0 && *i >= i64::MIN as i128 => {
+            out.push(0x13);
+            out.extend_from_slice(&(*i as i64).to_be_bytes());
+        }
+        PlistValue::Integer(i) if *i >= 0 && *i <= i64::MAX as i128 => {
+            let size = int_size(*i as u64);
+            out.push(0x10 | size.trailing_zeros() as u8);
+            push_uint(out, *i as u64, size);
+        }
+        // Unsigned values past i64::MAX need the 16 byte form
+        PlistValue::Integer(i) if *i >= 0 && *i <= u64::MAX as i128 => {
+            out.push(0x14);
+            out.extend_from_slice(&(*i as u128).to_be_bytes());
+        }
+        PlistValue::Integer(i) => {
+            warn!("Integer {} doesn't fit in a binary plist", i);
+            return Err(PlistError::Format);
+        }
+        // Reals that survive a round trip through f32 are written with 4 bytes, like libplist
+        PlistValue::Real(r) if (*r as f32) as f64 == *r => {
+            out.push(0x22);
+            out.extend_from_slice(&(*r as f32).to_be_bytes());
+        }
+        PlistValue::Real(r) => {
+            out.push(0x23);
+            out.extend_from_slice(&r.to_be_bytes());
+        }
+        PlistValue::Date(time) => {
+            out.push(0x33);
+            out.extend_from_slice(&system_time_to_mac_secs(*time).to_be_bytes());
+        }
+        PlistValue::Data(data) => {
+            push_header(out, 0x40, data.len() as u64);
+            out.extend_from_slice(data);
+        }
+        PlistValue::String(s) => push_string(out, s),
+        PlistValue::Uid(uid) => {
+            let size = int_size(*uid);
+            out.push(0x80 | (size - 1));
+            push_uint(out, *uid, size);
+        }
+        // Containers are flattened before they get here
+        PlistValue::Array(_) | PlistValue::Dictionary(_) => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Checks a requested integer size against the smallest one that fits
+fn choose_size(requested: Option<u8>, needed: u8, name: &str) -> Result<u8, PlistError> {
+    match requested {
+        None => Ok(needed),
+        Some(size) if (needed..=8).contains(&size) => Ok(size),
+        Some(size) => {
+            warn!(
+                "A {} size of {} can't be used, at least {} is needed",
+                name, size, needed
+            );
+            Err(PlistError::InvalidArg)
+        }
+    }
+}
+
+impl BinaryWriter {
+    /// Creates a writer with the default options
+    pub fn new() -> BinaryWriter {
+        BinaryWriter::default()
+    }
+
+    /// Serializes a value as a binary plist
+    pub fn write(&self, value: &PlistValue) -> Result<Vec<u8>, PlistError> {
+        self.write_with_stats(value).map(|(bytes, _)| bytes)
+    }
+
+    /// Serializes a plist as a binary plist
+    pub fn write_plist(&self, plist: &Plist) -> Result<Vec<u8>, PlistError> {
+        self.write(&PlistValue::from(plist))
+    }
+
+    /// Serializes a value as a binary plist and reports what was written
+    pub fn write_with_stats(
+        &self,
+        value: &PlistValue,
+    ) -> Result<(Vec<u8>, WriteStats), PlistError> {
+        trace!("Writing binary plist natively");
+        let mut flattener = Flattener {
+            writer: self,
+            objects: Vec::new(),
+            strings: HashMap::new(),
+            data: HashMap::new(),
+            duplicate_bytes: 0,
+            duplicate_count: 0,
+        };
+        flattener.flatten(value);
+        let object_count = flattener.objects.len() as u64;
+        let ref_size = choose_size(self.ref_size, min_size(object_count - 1), "reference")?;
+
+        let mut out = MAGIC.to_vec();
+        let mut offsets = Vec::with_capacity(flattener.objects.len());
+        for object in &flattener.objects {
+            offsets.push(out.len() as u64);
+            push_object(&mut out, object, ref_size)?;
+        }
+
+        let offset_table_offset = out.len() as u64;
+        let offset_size = choose_size(
+            self.offset_size,
+            min_size(*offsets.last().unwrap()),
+            "offset",
+        )?;
+        for offset in offsets {
+            push_uint(&mut out, offset, offset_size);
+        }
+
+        let mut trailer = [0_u8; TRAILER_SIZE];
+        trailer[6] = offset_size;
+        trailer[7] = ref_size;
+        trailer[8..16].copy_from_slice(&object_count.to_be_bytes());
+        trailer[24..32].copy_from_slice(&offset_table_offset.to_be_bytes());
+        out.extend_from_slice(&trailer);
+
+        let stats = WriteStats {
+            object_count,
+            offset_table_size: object_count * offset_size as u64,
+            bytes_saved: flattener.duplicate_bytes + flattener.duplicate_count * offset_size as u64,
+        };
+        trace!("Wrote {} objects in {} bytes", object_count, out.len());
+        Ok((out, stats))
+    }
+}
+
+impl PlistValue {
+    /// Serializes the value as a binary plist with the default `BinaryWriter`
+    /// This is the native alternative to converting a `Plist` into `Vec<u8>`
+    pub fn to_bin(&self) -> Result<Vec<u8>, PlistError> {
+        BinaryWriter::default().write(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bplist::{from_bytes, tests::SAMPLE, Trailer};
+
+    #[test]
+    fn write_bin_test() {
+        let value = from_bytes(SAMPLE).unwrap();
+        assert_eq!(value.to_bin().unwrap(), SAMPLE);
+
+        let value = PlistValue::Dictionary(vec![
+            ("big".to_string(), PlistValue::Integer(u64::MAX as i128)),
+            ("negative".to_string(), PlistValue::Integer(-5)),
+            ("half".to_string(), PlistValue::Real(0.5)),
+            ("tenth".to_string(), PlistValue::Real(0.1)),
+            ("name".to_string(), PlistValue::String("Grüße".repeat(5))),
+            ("blob".to_string(), PlistValue::Data(vec![7; 300])),
+            ("ref".to_string(), PlistValue::Uid(300)),
+            ("nothing".to_string(), PlistValue::Null),
+        ]);
+        assert_eq!(from_bytes(&value.to_bin().unwrap()).unwrap(), value);
+    }
+
+    #[test]
+    fn write_bin_dedup_test() {
+        let item = PlistValue::Dictionary(vec![
+            ("Name".to_string(), PlistValue::String("Name".to_string())),
+            ("Icon".to_string(), PlistValue::Data(vec![1, 2, 3])),
+        ]);
+        let value = PlistValue::Array(vec![item.clone(), item.clone(), item]);
+
+        let (bytes, stats) = BinaryWriter::default().write_with_stats(&value).unwrap();
+        // The array, three dictionaries, two keys and the data
+        assert_eq!(stats.object_count, 7);
+        assert_eq!(stats.offset_table_size, 7);
+        // Seven repeated strings and two repeated blobs, each with an offset table entry
+        assert_eq!(stats.bytes_saved, 7 * (5 + 1) + 2 * (4 + 1));
+        assert_eq!(from_bytes(&bytes).unwrap(), value);
+
+        let writer = BinaryWriter {
+            dedup_strings: false,
+            dedup_data: false,
+            ..Default::default()
+        };
+        let (plain, stats) = writer.write_with_stats(&value).unwrap();
+        assert_eq!(stats.object_count, 16);
+        assert_eq!(stats.bytes_saved, 0);
+        assert_eq!(from_bytes(&plain).unwrap(), value);
+        assert!(plain.len() > bytes.len());
+    }
+
+    #[test]
+    fn write_bin_sizes_test() {
+        let value = PlistValue::Array(vec![PlistValue::Boolean(true)]);
+        let writer = BinaryWriter {
+            offset_size: Some(4),
+            ref_size: Some(2),
+            ..Default::default()
+        };
+        let (bytes, stats) = writer.write_with_stats(&value).unwrap();
+        let trailer = Trailer::parse(&bytes).unwrap();
+        assert_eq!(trailer.offset_size, 4);
+        assert_eq!(trailer.ref_size, 2);
+        assert_eq!(stats.offset_table_size, 8);
+        assert_eq!(from_bytes(&bytes).unwrap(), value);
+
+        // The second blob starts past the first 255 bytes
+        let long = PlistValue::Array(vec![
+            PlistValue::Data(vec![0; 300]),
+            PlistValue::Data(vec![1; 300]),
+        ]);
+        let writer = BinaryWriter {
+            offset_size: Some(1),
+            ..Default::default()
+        };
+        assert!(writer.write(&long).is_err());
+        let writer = BinaryWriter {
+            ref_size: Some(9),
+            ..Default::default()
+        };
+        assert!(writer.write(&value).is_err());
+    }
+}
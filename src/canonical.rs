@@ -0,0 +1,104 @@
+// jkcoxson
+
+use log::trace;
+
+use crate::{
+    backend::{Active, Backend},
+    Plist, PlistType,
+};
+
+impl Plist {
+    /// Sorts the keys of every dictionary in the plist lexicographically
+    /// Recurses into arrays and nested dictionaries
+    pub fn sort_keys(&mut self) {
+        trace!("Sorting plist keys");
+        Active::sort(&mut self.plist_t)
+    }
+
+    /// Rewrites the plist into a canonical form, so that equal plists serialize to the same bytes
+    /// Dictionary keys are sorted and reals are normalized, so `-0.0` becomes `0.0` and every NaN
+    /// uses the same bit pattern.
+    /// The binary writer emits objects in the order it walks the tree, so sorting the keys also
+    /// makes the binary object table stable.
+    ///
+    /// # Example
+    /// ```rust
+    /// use plist_plus::Plist;
+    ///
+    /// let mut a = Plist::new_dict();
+    /// a.dict_set_item("b", 1_u64.into()).unwrap();
+    /// a.dict_set_item("a", (-0.0).into()).unwrap();
+    /// let mut b = Plist::new_dict();
+    /// b.dict_set_item("a", 0.0.into()).unwrap();
+    /// b.dict_set_item("b", 1_u64.into()).unwrap();
+    ///
+    /// a.canonicalize();
+    /// b.canonicalize();
+    /// assert_eq!(Vec::<u8>::from(a), Vec::<u8>::from(b));
+    /// ```
+    pub fn canonicalize(&mut self) {
+        trace!("Canonicalizing plist");
+        self.sort_keys();
+        self.normalize_reals();
+    }
+
+    fn normalize_reals(&self) {
+        match self.plist_type {
+            PlistType::Real => {
+                let val = self.get_real_val().unwrap_or_default();
+                let normalized = if val.is_nan() {
+                    f64::NAN
+                } else if val == 0.0 {
+                    0.0
+                } else {
+                    val
+                };
+                if normalized.to_bits() != val.to_bits() {
+                    self.set_real_val(normalized).unwrap();
+                }
+            }
+            PlistType::Array => {
+                for item in self.array_items() {
+                    item.normalize_reals();
+                }
+            }
+            PlistType::Dictionary => {
+                for (_, item) in self.sorted_dict_entries() {
+                    item.normalize_reals();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_keys_test() {
+        let mut inner = Plist::new_dict();
+        inner.dict_set_item("z", 1_u64.into()).unwrap();
+        inner.dict_set_item("y", 2_u64.into()).unwrap();
+        let mut p = Plist::new_dict();
+        p.dict_set_item("b", inner).unwrap();
+        p.dict_set_item("a", true.into()).unwrap();
+        p.sort_keys();
+
+        assert_eq!(p.dict_get_keys().unwrap(), vec!["a", "b"]);
+        assert_eq!(
+            p.dict_get_item("b").unwrap().dict_get_keys().unwrap(),
+            vec!["y", "z"]
+        );
+    }
+
+    #[test]
+    fn canonicalize_test() {
+        let mut p = Plist::new_array();
+        p.array_append_item((-0.0).into()).unwrap();
+        p.canonicalize();
+        let val = p.array_get_item(0).unwrap().get_real_val().unwrap();
+        assert_eq!(val.to_bits(), 0.0_f64.to_bits());
+    }
+}
@@ -0,0 +1,229 @@
+// jkcoxson
+
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+use log::trace;
+
+use crate::{
+    backend::{Active, Backend},
+    Plist, PlistType,
+};
+
+impl Plist {
+    /// Returns the seconds and microseconds since the Mac Epoch as stored by the backend
+    /// Used for comparisons so that dates before 2001 don't wrap around
+    fn raw_date_val(&self) -> (i32, i32) {
+        Active::get_date(&self.plist_t).unwrap_or_default()
+    }
+
+    /// Returns the uid value without requiring the plist to be a Uid
+    fn raw_uid_val(&self) -> u64 {
+        Active::get_uid(&self.plist_t).unwrap_or_default()
+    }
+
+    /// Returns the dictionary entries sorted by key
+    /// The values are borrowed from self and are false dropped
+    pub(crate) fn sorted_dict_entries(&self) -> Vec<(String, Plist)> {
+        let mut keys = self.dict_get_keys().unwrap_or_default();
+        keys.sort();
+        keys.into_iter()
+            .filter_map(|key| self.dict_get_item(&key).ok().map(|item| (key, item)))
+            .collect()
+    }
+
+    /// Returns the array items in order
+    /// The values are borrowed from self and are false dropped
+    pub(crate) fn array_items(&self) -> Vec<Plist> {
+        (0..self.array_get_size().unwrap_or(0))
+            .filter_map(|i| self.array_get_item(i).ok())
+            .collect()
+    }
+}
+
+/// The position of each type when ordering plists of different types
+fn type_rank(plist_type: &PlistType) -> u8 {
+    match plist_type {
+        PlistType::Boolean => 0,
+        PlistType::Integer => 1,
+        PlistType::Real => 2,
+        PlistType::Date => 3,
+        PlistType::Data => 4,
+        PlistType::String => 5,
+        PlistType::Array => 6,
+        PlistType::Dictionary => 7,
+        PlistType::Unknown => 8,
+        PlistType::Key => 9,
+        PlistType::Uid => 10,
+        PlistType::None => 11,
+    }
+}
+
+impl PartialEq for Plist {
+    fn eq(&self, other: &Self) -> bool {
+        if self.plist_type != other.plist_type {
+            return false;
+        }
+        match self.plist_type {
+            PlistType::Array => {
+                let (l, r) = (self.array_items(), other.array_items());
+                l.len() == r.len() && l.iter().zip(r.iter()).all(|(l, r)| l == r)
+            }
+            PlistType::Dictionary => {
+                let (l, r) = (self.sorted_dict_entries(), other.sorted_dict_entries());
+                l.len() == r.len()
+                    && l.iter()
+                        .zip(r.iter())
+                        .all(|((lk, lv), (rk, rv))| lk == rk && lv == rv)
+            }
+            // The C library compares reals with ==, which would make NaN unequal to itself
+            PlistType::Real => {
+                self.get_real_val().unwrap_or_default().to_bits()
+                    == other.get_real_val().unwrap_or_default().to_bits()
+            }
+            PlistType::Boolean => self.get_bool_val().ok() == other.get_bool_val().ok(),
+            PlistType::Integer => self.get_int_val().ok() == other.get_int_val().ok(),
+            PlistType::Date => self.raw_date_val() == other.raw_date_val(),
+            PlistType::Data => self.get_data_val().ok() == other.get_data_val().ok(),
+            PlistType::String => self.get_string_val().ok() == other.get_string_val().ok(),
+            PlistType::Key => self.get_key_val().ok() == other.get_key_val().ok(),
+            PlistType::Uid => self.raw_uid_val() == other.raw_uid_val(),
+            PlistType::Unknown | PlistType::None => {
+                trace!("Comparing valueless plists");
+                true
+            }
+        }
+    }
+}
+
+impl Eq for Plist {}
+
+impl Hash for Plist {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.plist_type.hash(state);
+        match self.plist_type {
+            PlistType::Boolean => self.get_bool_val().unwrap_or_default().hash(state),
+            PlistType::Integer => self.get_int_val().unwrap_or_default().hash(state),
+            PlistType::Real => self
+                .get_real_val()
+                .unwrap_or_default()
+                .to_bits()
+                .hash(state),
+            PlistType::Date => self.raw_date_val().hash(state),
+            PlistType::Data => self.get_data_val().unwrap_or_default().hash(state),
+            PlistType::String => self.get_string_val().unwrap_or_default().hash(state),
+            PlistType::Key => self.get_key_val().unwrap_or_default().hash(state),
+            PlistType::Uid => self.raw_uid_val().hash(state),
+            PlistType::Array => self.array_items().hash(state),
+            // Sorted so that dictionaries with the same entries in a different order hash the same
+            PlistType::Dictionary => self.sorted_dict_entries().hash(state),
+            PlistType::Unknown | PlistType::None => {}
+        }
+    }
+}
+
+impl PartialOrd for Plist {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Plist {
+    /// Orders plists first by type, then by value
+    /// Dictionaries are compared by their entries sorted by key
+    fn cmp(&self, other: &Self) -> Ordering {
+        let rank = type_rank(&self.plist_type).cmp(&type_rank(&other.plist_type));
+        if rank != Ordering::Equal {
+            return rank;
+        }
+        match self.plist_type {
+            PlistType::Boolean => self.get_bool_val().ok().cmp(&other.get_bool_val().ok()),
+            PlistType::Integer => self.get_int_val().ok().cmp(&other.get_int_val().ok()),
+            PlistType::Real => self
+                .get_real_val()
+                .unwrap_or_default()
+                .total_cmp(&other.get_real_val().unwrap_or_default()),
+            PlistType::Date => self.raw_date_val().cmp(&other.raw_date_val()),
+            PlistType::Data => self.get_data_val().ok().cmp(&other.get_data_val().ok()),
+            PlistType::String => self.get_string_val().ok().cmp(&other.get_string_val().ok()),
+            PlistType::Key => self.get_key_val().ok().cmp(&other.get_key_val().ok()),
+            PlistType::Uid => self.raw_uid_val().cmp(&other.raw_uid_val()),
+            PlistType::Array => self.array_items().cmp(&other.array_items()),
+            PlistType::Dictionary => self.sorted_dict_entries().cmp(&other.sorted_dict_entries()),
+            PlistType::Unknown | PlistType::None => Ordering::Equal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashSet};
+
+    use super::*;
+
+    fn sample_dict(reverse: bool) -> Plist {
+        let mut p = Plist::new_dict();
+        let mut entries = vec![("a", Plist::new_uint(1)), ("b", Plist::new_string("two"))];
+        if reverse {
+            entries.reverse();
+        }
+        for (k, v) in entries {
+            p.dict_set_item(k, v).unwrap();
+        }
+        p
+    }
+
+    #[test]
+    fn eq_test() {
+        assert_eq!(Plist::new_string("a"), Plist::new_string("a"));
+        assert_ne!(Plist::new_string("a"), Plist::new_string("b"));
+        assert_ne!(Plist::new_uint(1), Plist::new_bool(true));
+        assert_eq!(sample_dict(false), sample_dict(true));
+        assert!(Plist::compare_node_values(
+            &sample_dict(false),
+            &sample_dict(true)
+        ));
+    }
+
+    #[test]
+    fn hash_set_test() {
+        let mut set = HashSet::new();
+        set.insert(sample_dict(false));
+        set.insert(sample_dict(true));
+        set.insert(Plist::new_real(1.5));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn ord_test() {
+        let mut set = BTreeSet::new();
+        set.insert(Plist::new_uint(2));
+        set.insert(Plist::new_uint(1));
+        set.insert(Plist::new_bool(true));
+        set.insert(Plist::new_uint(1));
+        let sorted: Vec<Plist> = set.into_iter().collect();
+        assert_eq!(
+            sorted,
+            vec![
+                Plist::new_bool(true),
+                Plist::new_uint(1),
+                Plist::new_uint(2)
+            ]
+        );
+    }
+
+    #[test]
+    fn signed_integer_test() {
+        // -1 and u64::MAX share their bits, but are different integers
+        let (negative, max) = (Plist::new_int(-1), Plist::new_uint(u64::MAX));
+        assert_ne!(negative, max);
+        assert!(negative < Plist::new_uint(0));
+        assert!(negative < max);
+        assert_eq!(Plist::new_int(-1), negative);
+
+        let set: BTreeSet<Plist> = [max.clone(), negative.clone(), Plist::new_int(-1)].into();
+        assert_eq!(set.into_iter().collect::<Vec<_>>(), vec![negative, max]);
+        let set: HashSet<Plist> = [Plist::new_int(-1), Plist::new_uint(u64::MAX)].into();
+        assert_eq!(set.len(), 2);
+    }
+}
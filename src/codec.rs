@@ -0,0 +1,255 @@
+// jkcoxson
+
+use std::io::{Read, Write};
+
+use log::{trace, warn};
+
+use crate::backend::{Active, Backend};
+use crate::error::PlistError;
+use crate::limits::{Budget, ParseLimits};
+use crate::Plist;
+
+/// The length in bytes of the big-endian length that comes before every plist
+pub const FRAME_HEADER_LEN: usize = 4;
+
+/// The format a plist is serialized in
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum PlistFormat {
+    #[default]
+    Xml,
+    Binary,
+}
+
+impl PlistFormat {
+    /// Serializes a plist in this format
+    pub fn serialize(self, plist: &Plist) -> Result<Vec<u8>, PlistError> {
+        match self {
+            PlistFormat::Xml => Active::to_xml(&plist.plist_t).map(String::into_bytes),
+            PlistFormat::Binary => Active::to_bin(&plist.plist_t),
+        }
+    }
+}
+
+/// Reads and writes plists framed with a 4-byte big-endian length,
+/// the way lockdownd and most device services send them
+/// Incoming plists may be XML or binary, whatever the format the codec writes.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct PlistCodec {
+    /// The format plists are written in by the tokio `Encoder`
+    pub format: PlistFormat,
+    /// The limits incoming plists are checked against
+    /// A frame longer than `max_total_bytes` is rejected before it is read.
+    pub limits: ParseLimits,
+}
+
+impl PlistCodec {
+    /// Creates a codec that writes plists in the given format
+    pub fn new(format: PlistFormat) -> PlistCodec {
+        PlistCodec {
+            format,
+            limits: ParseLimits::default(),
+        }
+    }
+
+    /// Reads one framed plist
+    ///
+    /// # Example
+    /// ```rust
+    /// use plist_plus::{Plist, PlistCodec, PlistFormat};
+    ///
+    /// let codec = PlistCodec::default();
+    /// let mut plist = Plist::new_dict();
+    /// plist.dict_set_item("Request", "QueryType".into()).unwrap();
+    ///
+    /// let mut pipe = Vec::new();
+    /// codec.write_framed(&mut pipe, &plist, PlistFormat::Binary).unwrap();
+    /// let read = codec.read_framed(&mut pipe.as_slice()).unwrap();
+    /// assert_eq!(read, plist);
+    /// ```
+    pub fn read_framed(&self, mut reader: impl Read) -> Result<Plist, PlistError> {
+        let mut header = [0; FRAME_HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        let len = self.frame_len(header)?;
+        trace!("Reading framed plist of {} bytes", len);
+        let mut body = Vec::new();
+        reader.take(len as u64).read_to_end(&mut body)?;
+        if body.len() != len {
+            warn!("Stream ended in the middle of a framed plist");
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        Plist::from_memory_with_limits(body, &self.limits)
+    }
+
+    /// Writes one plist with its length in front
+    pub fn write_framed(
+        &self,
+        mut writer: impl Write,
+        plist: &Plist,
+        format: PlistFormat,
+    ) -> Result<(), PlistError> {
+        let body = format.serialize(plist)?;
+        trace!("Writing framed plist of {} bytes", body.len());
+        writer.write_all(&frame_header(body.len())?)?;
+        writer.write_all(&body)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Checks the length from a frame header against the limits
+    fn frame_len(&self, header: [u8; FRAME_HEADER_LEN]) -> Result<usize, PlistError> {
+        let len = u32::from_be_bytes(header) as usize;
+        Budget::new(&self.limits).total_bytes(len)?;
+        Ok(len)
+    }
+}
+
+/// Returns the header for a plist of the given length
+fn frame_header(len: usize) -> Result<[u8; FRAME_HEADER_LEN], PlistError> {
+    match u32::try_from(len) {
+        Ok(len) => Ok(len.to_be_bytes()),
+        Err(_) => {
+            warn!("Plist is too long to frame");
+            Err(PlistError::InvalidArg)
+        }
+    }
+}
+
+#[cfg(feature = "tokio-codec")]
+mod tokio_codec {
+    use bytes::{Buf, BufMut, BytesMut};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::*;
+
+    impl Decoder for PlistCodec {
+        type Item = Plist;
+        type Error = PlistError;
+
+        fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Plist>, PlistError> {
+            if src.len() < FRAME_HEADER_LEN {
+                return Ok(None);
+            }
+            let mut header = [0; FRAME_HEADER_LEN];
+            header.copy_from_slice(&src[..FRAME_HEADER_LEN]);
+            let len = self.frame_len(header)?;
+            if src.len() < FRAME_HEADER_LEN + len {
+                src.reserve(FRAME_HEADER_LEN + len - src.len());
+                return Ok(None);
+            }
+            src.advance(FRAME_HEADER_LEN);
+            let body = src.split_to(len);
+            Plist::from_memory_with_limits(body.to_vec(), &self.limits).map(Some)
+        }
+    }
+
+    /// Writes plists in the codec's format
+    impl Encoder<Plist> for PlistCodec {
+        type Error = PlistError;
+
+        fn encode(&mut self, plist: Plist, dst: &mut BytesMut) -> Result<(), PlistError> {
+            let format = self.format;
+            self.encode((plist, format), dst)
+        }
+    }
+
+    /// Writes each plist in the format it is paired with
+    impl Encoder<(Plist, PlistFormat)> for PlistCodec {
+        type Error = PlistError;
+
+        fn encode(
+            &mut self,
+            (plist, format): (Plist, PlistFormat),
+            dst: &mut BytesMut,
+        ) -> Result<(), PlistError> {
+            let body = format.serialize(&plist)?;
+            dst.reserve(FRAME_HEADER_LEN + body.len());
+            dst.put_slice(&frame_header(body.len())?);
+            dst.put_slice(&body);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Limit;
+
+    #[test]
+    fn read_framed_limits_test() {
+        let codec = PlistCodec {
+            limits: ParseLimits {
+                max_total_bytes: 16,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut frame = 17_u32.to_be_bytes().to_vec();
+        frame.extend([b' '; 17]);
+        assert!(matches!(
+            codec.read_framed(frame.as_slice()),
+            Err(PlistError::LimitExceeded {
+                limit: Limit::TotalBytes,
+                ..
+            })
+        ));
+
+        // A frame cut short is an error rather than a shorter plist
+        let mut frame = 8_u32.to_be_bytes().to_vec();
+        frame.extend(b"<plist>");
+        match codec.read_framed(frame.as_slice()) {
+            Err(PlistError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof),
+            e => panic!("Unexpected result {:?}", e.map(|_| ())),
+        }
+        assert!(matches!(
+            codec.read_framed([0_u8, 0].as_slice()),
+            Err(PlistError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn libplist_framed_round_trip_test() {
+        let codec = PlistCodec::new(PlistFormat::Binary);
+        let mut plist = Plist::new_dict();
+        plist.dict_set_item("Request", "GetValue".into()).unwrap();
+
+        let mut pipe = Vec::new();
+        codec
+            .write_framed(&mut pipe, &plist, PlistFormat::Xml)
+            .unwrap();
+        codec
+            .write_framed(&mut pipe, &plist, PlistFormat::Binary)
+            .unwrap();
+        let len = u32::from_be_bytes(pipe[..4].try_into().unwrap()) as usize;
+        assert!(pipe[4..].starts_with(b"<?xml"));
+        assert!(pipe[8 + len..].starts_with(b"bplist00"));
+
+        let mut reader = pipe.as_slice();
+        assert_eq!(codec.read_framed(&mut reader).unwrap(), plist);
+        assert_eq!(codec.read_framed(&mut reader).unwrap(), plist);
+        assert!(reader.is_empty());
+    }
+
+    #[cfg(feature = "tokio-codec")]
+    #[test]
+    fn libplist_tokio_codec_test() {
+        use bytes::BytesMut;
+        use tokio_util::codec::{Decoder, Encoder};
+
+        let mut codec = PlistCodec::default();
+        let plist = Plist::new_string("Hello");
+        let mut buf = BytesMut::new();
+        codec.encode(plist.clone(), &mut buf).unwrap();
+        codec
+            .encode((plist.clone(), PlistFormat::Binary), &mut buf)
+            .unwrap();
+
+        // Frames that haven't fully arrived are left in the buffer
+        let mut partial = buf.split_to(6);
+        assert!(codec.decode(&mut partial).unwrap().is_none());
+        partial.unsplit(buf);
+        assert_eq!(codec.decode(&mut partial).unwrap(), Some(plist.clone()));
+        assert_eq!(codec.decode(&mut partial).unwrap(), Some(plist));
+        assert!(codec.decode(&mut partial).unwrap().is_none());
+    }
+}
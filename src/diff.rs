@@ -0,0 +1,331 @@
+// jkcoxson
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use log::trace;
+
+use crate::path::{KeyPath, PathSegment};
+use crate::{Plist, PlistType};
+
+/// A single difference between two plists
+#[derive(PartialEq, Debug, Clone)]
+pub enum Change {
+    /// A dictionary entry or array item only present in the new plist
+    Added { path: KeyPath, value: Plist },
+    /// A dictionary entry or array item only present in the old plist
+    Removed { path: KeyPath, value: Plist },
+    /// A value of the same type that differs between the plists
+    Changed {
+        path: KeyPath,
+        old: Plist,
+        new: Plist,
+    },
+    /// A value whose type differs between the plists
+    TypeChanged {
+        path: KeyPath,
+        old: Plist,
+        new: Plist,
+    },
+    /// An array item that is present in both plists at a different index
+    /// The path points at the array itself
+    Moved { path: KeyPath, from: u32, to: u32 },
+}
+
+impl Change {
+    /// Returns the path the change applies to
+    pub fn path(&self) -> &KeyPath {
+        match self {
+            Change::Added { path, .. }
+            | Change::Removed { path, .. }
+            | Change::Changed { path, .. }
+            | Change::TypeChanged { path, .. }
+            | Change::Moved { path, .. } => path,
+        }
+    }
+}
+
+/// Compares two plists structurally and returns the changes needed to turn `a` into `b`
+/// Dictionary order is ignored, array items are matched by value before being compared by index
+/// Arrays with over a thousand or so changed items on each side are only compared by index
+///
+/// # Example
+/// ```rust
+/// use plist_plus::{diff, Change, Plist};
+///
+/// let mut a = Plist::new_dict();
+/// a.dict_set_item("ProductVersion", "15.0".into()).unwrap();
+/// let mut b = Plist::new_dict();
+/// b.dict_set_item("ProductVersion", "15.1".into()).unwrap();
+///
+/// let changes = diff(&a, &b);
+/// assert_eq!(changes[0].path().to_string(), "/ProductVersion");
+/// assert!(matches!(changes[0], Change::Changed { .. }));
+/// ```
+pub fn diff(a: &Plist, b: &Plist) -> Vec<Change> {
+    trace!("Diffing plists");
+    let mut changes = Vec::new();
+    diff_node(&KeyPath::root(), a, b, &mut changes);
+    changes
+}
+
+fn diff_node(path: &KeyPath, a: &Plist, b: &Plist, changes: &mut Vec<Change>) {
+    if a.plist_type != b.plist_type {
+        changes.push(Change::TypeChanged {
+            path: path.clone(),
+            old: a.clone(),
+            new: b.clone(),
+        });
+        return;
+    }
+    match a.plist_type {
+        PlistType::Dictionary => diff_dict(path, a, b, changes),
+        PlistType::Array => diff_array(path, a, b, changes),
+        _ => {
+            if a != b {
+                changes.push(Change::Changed {
+                    path: path.clone(),
+                    old: a.clone(),
+                    new: b.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn diff_dict(path: &KeyPath, a: &Plist, b: &Plist, changes: &mut Vec<Change>) {
+    let a_keys = a.dict_get_keys().unwrap_or_default();
+    let b_keys = b.dict_get_keys().unwrap_or_default();
+    let a_set: HashSet<&String> = a_keys.iter().collect();
+    let b_set: HashSet<&String> = b_keys.iter().collect();
+    for key in &a_keys {
+        let child = path.join(PathSegment::Key(key.clone()));
+        let old = a.dict_get_item(key).unwrap();
+        if b_set.contains(key) {
+            diff_node(&child, &old, &b.dict_get_item(key).unwrap(), changes);
+        } else {
+            changes.push(Change::Removed {
+                path: child,
+                value: old.clone(),
+            });
+        }
+    }
+    for key in b_keys.iter().filter(|key| !a_set.contains(key)) {
+        changes.push(Change::Added {
+            path: path.join(PathSegment::Key(key.clone())),
+            value: b.dict_get_item(key).unwrap().clone(),
+        });
+    }
+}
+
+/// The largest table the longest common subsequence is worked out with
+/// Past this, changed arrays are compared index by index instead
+const MAX_LCS_CELLS: usize = 1 << 20;
+
+fn diff_array(path: &KeyPath, a: &Plist, b: &Plist, changes: &mut Vec<Change>) {
+    let a_items = a.array_items();
+    let b_items = b.array_items();
+
+    // Equal items get the same id, so each one is only hashed once
+    let mut ids = HashMap::new();
+    let mut id_of = |item| {
+        let next = ids.len();
+        *ids.entry(item).or_insert(next)
+    };
+    let a_ids: Vec<usize> = a_items.iter().map(&mut id_of).collect();
+    let b_ids: Vec<usize> = b_items.iter().map(&mut id_of).collect();
+
+    // Items that stay in place at the start and end need no table
+    let prefix = a_ids.iter().zip(&b_ids).take_while(|(a, b)| a == b).count();
+    let suffix = a_ids[prefix..]
+        .iter()
+        .rev()
+        .zip(b_ids[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a_range = prefix..a_ids.len() - suffix;
+    let b_range = prefix..b_ids.len() - suffix;
+
+    let (a_unmatched, b_unmatched) = if a_range.len() * b_range.len() <= MAX_LCS_CELLS {
+        let (mut a_unmatched, mut b_unmatched) =
+            unmatched(&a_ids[a_range.clone()], &b_ids[b_range.clone()], prefix);
+
+        // Equal items left over on both sides were moved
+        let mut b_by_id: HashMap<usize, VecDeque<usize>> = HashMap::new();
+        for &to in &b_unmatched {
+            b_by_id.entry(b_ids[to]).or_default().push_back(to);
+        }
+        let mut moved = HashSet::new();
+        a_unmatched.retain(|&from| {
+            match b_by_id.get_mut(&a_ids[from]).and_then(VecDeque::pop_front) {
+                Some(to) => {
+                    changes.push(Change::Moved {
+                        path: path.clone(),
+                        from: from as u32,
+                        to: to as u32,
+                    });
+                    moved.insert(to);
+                    false
+                }
+                None => true,
+            }
+        });
+        b_unmatched.retain(|to| !moved.contains(to));
+        (a_unmatched, b_unmatched)
+    } else {
+        trace!(
+            "Comparing arrays of {} and {} changed items by index",
+            a_range.len(),
+            b_range.len()
+        );
+        (a_range.collect(), b_range.collect())
+    };
+
+    // Anything else at the same index was modified in place
+    let a_set: HashSet<usize> = a_unmatched.iter().copied().collect();
+    let b_set: HashSet<usize> = b_unmatched.iter().copied().collect();
+    for &index in &a_unmatched {
+        let child = path.join(PathSegment::Index(index as u32));
+        if b_set.contains(&index) {
+            diff_node(&child, &a_items[index], &b_items[index], changes);
+        } else {
+            changes.push(Change::Removed {
+                path: child,
+                value: a_items[index].clone(),
+            });
+        }
+    }
+    for &index in b_unmatched.iter().filter(|index| !a_set.contains(index)) {
+        changes.push(Change::Added {
+            path: path.join(PathSegment::Index(index as u32)),
+            value: b_items[index].clone(),
+        });
+    }
+}
+
+/// Finds the items that aren't part of a longest common subsequence
+/// Returns their indices on each side, shifted by `offset`
+fn unmatched(a: &[usize], b: &[usize], offset: usize) -> (Vec<usize>, Vec<usize>) {
+    let width = b.len() + 1;
+    let mut lcs = vec![0_u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+    let mut a_unmatched = Vec::new();
+    let mut b_unmatched = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+            a_unmatched.push(offset + i);
+            i += 1;
+        } else {
+            b_unmatched.push(offset + j);
+            j += 1;
+        }
+    }
+    a_unmatched.extend(offset + i..offset + a.len());
+    b_unmatched.extend(offset + j..offset + b.len());
+    (a_unmatched, b_unmatched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn array(items: &[&str]) -> Plist {
+        let mut p = Plist::new_array();
+        for item in items {
+            p.array_append_item(Plist::new_string(item)).unwrap();
+        }
+        p
+    }
+
+    #[test]
+    fn dict_diff_test() {
+        let mut a = Plist::new_dict();
+        a.dict_set_item("same", Plist::new_uint(1)).unwrap();
+        a.dict_set_item("removed", Plist::new_bool(true)).unwrap();
+        a.dict_set_item("retyped", Plist::new_uint(2)).unwrap();
+        let mut b = Plist::new_dict();
+        b.dict_set_item("retyped", Plist::new_string("2")).unwrap();
+        b.dict_set_item("added", Plist::new_bool(false)).unwrap();
+        b.dict_set_item("same", Plist::new_uint(1)).unwrap();
+
+        let changes = diff(&a, &b);
+        assert_eq!(changes.len(), 3);
+        assert!(
+            matches!(&changes[0], Change::Removed { path, .. } if path.to_string() == "/removed")
+        );
+        assert!(
+            matches!(&changes[1], Change::TypeChanged { path, .. } if path.to_string() == "/retyped")
+        );
+        assert!(matches!(&changes[2], Change::Added { path, .. } if path.to_string() == "/added"));
+    }
+
+    #[test]
+    fn array_diff_test() {
+        let changes = diff(&array(&["a", "b", "c"]), &array(&["c", "a", "b"]));
+        assert_eq!(
+            changes,
+            vec![Change::Moved {
+                path: KeyPath::root(),
+                from: 2,
+                to: 0
+            }]
+        );
+
+        let changes = diff(&array(&["a", "b"]), &array(&["a", "x", "y"]));
+        assert_eq!(changes.len(), 2);
+        assert!(matches!(&changes[0], Change::Changed { path, .. } if path.to_string() == "/1"));
+        assert!(matches!(&changes[1], Change::Added { path, .. } if path.to_string() == "/2"));
+    }
+
+    #[test]
+    fn large_array_diff_test() {
+        let ints = |values: &mut dyn Iterator<Item = u64>| {
+            let mut p = Plist::new_array();
+            for value in values {
+                p.array_append_item(Plist::new_uint(value)).unwrap();
+            }
+            p
+        };
+        let a = ints(&mut (0..2000));
+        // Too many changed items in the middle for the table, so they are compared by index
+        let b = ints(&mut (0..2000).map(|i| {
+            if (500..1700).contains(&i) {
+                i + 10000
+            } else {
+                i
+            }
+        }));
+        let changes = diff(&a, &b);
+        assert_eq!(changes.len(), 1200);
+        assert!(changes
+            .iter()
+            .all(|change| matches!(change, Change::Changed { .. })));
+        assert_eq!(changes[0].path().to_string(), "/500");
+
+        // The same items in a different place are still found when the middle is small
+        let b = ints(&mut (0..2000).map(|i| match i {
+            10 => 11,
+            11 => 10,
+            i => i,
+        }));
+        assert_eq!(
+            diff(&a, &b),
+            vec![Change::Moved {
+                path: KeyPath::root(),
+                from: 10,
+                to: 11
+            }]
+        );
+    }
+}
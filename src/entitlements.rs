@@ -0,0 +1,264 @@
+// jkcoxson
+
+use log::{trace, warn};
+
+use crate::error::PlistError;
+use crate::limits::ParseLimits;
+use crate::{Plist, PlistValue};
+
+/// The entitlements of an app or the ones a provisioning profile allows
+///
+/// # Example
+/// ```rust
+/// use plist_plus::Entitlements;
+///
+/// let app = Entitlements::from_bytes(br#"{
+///     "application-identifier" = "ABCDE12345.com.example.app";
+///     "keychain-access-groups" = ("ABCDE12345.com.example.app");
+/// }"#).unwrap();
+/// let provisioned = Entitlements::from_bytes(br#"{
+///     "application-identifier" = "ABCDE12345.*";
+///     "keychain-access-groups" = ("ABCDE12345.*");
+/// }"#).unwrap();
+/// assert!(app.is_subset_of(&provisioned));
+/// ```
+#[derive(PartialEq, Debug, Clone)]
+pub struct Entitlements {
+    /// Always a dictionary
+    value: PlistValue,
+}
+
+impl Default for Entitlements {
+    fn default() -> Self {
+        Entitlements {
+            value: PlistValue::Dictionary(Vec::new()),
+        }
+    }
+}
+
+impl Entitlements {
+    /// Creates empty entitlements
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps a dictionary
+    pub fn from_value(value: PlistValue) -> Result<Self, PlistError> {
+        match value {
+            PlistValue::Dictionary(_) => Ok(Entitlements { value }),
+            _ => {
+                warn!("Entitlements are not a dictionary");
+                Err(PlistError::InvalidArg)
+            }
+        }
+    }
+
+    /// Parses entitlements in any format
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PlistError> {
+        Self::from_bytes_with_limits(bytes, &ParseLimits::default())
+    }
+
+    /// Parses entitlements, rejecting them as soon as they go over the limits
+    /// Use this for entitlements pulled out of binaries that can't be trusted
+    pub fn from_bytes_with_limits(bytes: &[u8], limits: &ParseLimits) -> Result<Self, PlistError> {
+        trace!("Parsing entitlements");
+        Self::from_value(crate::limits::from_memory(bytes, limits)?)
+    }
+
+    /// Wraps a copy of a dictionary plist
+    pub fn from_plist(plist: &Plist) -> Result<Self, PlistError> {
+        Self::from_value(PlistValue::from(plist))
+    }
+
+    /// Returns the whole dictionary
+    pub fn as_value(&self) -> &PlistValue {
+        &self.value
+    }
+
+    /// Returns the dictionary as a plist
+    pub fn to_plist(&self) -> Plist {
+        (&self.value).into()
+    }
+
+    /// Returns the value of any entitlement
+    pub fn get(&self, key: &str) -> Option<&PlistValue> {
+        self.value.dict_get(key)
+    }
+
+    /// Sets the value of any entitlement
+    pub fn set(&mut self, key: &str, value: PlistValue) {
+        self.value.dict_set(key, value);
+    }
+
+    /// `application-identifier`, the team ID and bundle ID joined by a dot
+    pub fn application_identifier(&self) -> Option<&str> {
+        self.get("application-identifier")
+            .and_then(PlistValue::as_str)
+    }
+
+    /// `com.apple.developer.team-identifier`
+    pub fn team_identifier(&self) -> Option<&str> {
+        self.get("com.apple.developer.team-identifier")
+            .and_then(PlistValue::as_str)
+    }
+
+    /// `keychain-access-groups`
+    pub fn keychain_access_groups(&self) -> Vec<&str> {
+        self.get("keychain-access-groups")
+            .and_then(PlistValue::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(PlistValue::as_str)
+            .collect()
+    }
+
+    /// `get-task-allow`, whether a debugger may attach
+    pub fn get_task_allow(&self) -> bool {
+        self.get("get-task-allow")
+            .and_then(PlistValue::as_bool)
+            .unwrap_or_default()
+    }
+
+    /// Returns whether every entitlement here is allowed by the provisioned ones
+    pub fn is_subset_of(&self, provisioned: &Entitlements) -> bool {
+        self.violations(provisioned).is_empty()
+    }
+
+    /// Returns the entitlements that the provisioned ones don't allow
+    /// Strings in the provisioned entitlements may contain `*` wildcards, and an array
+    /// allows any value matching one of its items. A `true` needs a provisioned `true`,
+    /// while a `false` is always allowed.
+    pub fn violations(&self, provisioned: &Entitlements) -> Vec<String> {
+        let entries = match &self.value {
+            PlistValue::Dictionary(entries) => entries,
+            _ => return Vec::new(),
+        };
+        entries
+            .iter()
+            .filter(|(key, value)| !permitted(value, provisioned.get(key)))
+            .map(|(key, _)| {
+                trace!("Entitlement {} is not provisioned", key);
+                key.clone()
+            })
+            .collect()
+    }
+}
+
+/// Returns whether a value is allowed by a provisioned value
+fn permitted(value: &PlistValue, provisioned: Option<&PlistValue>) -> bool {
+    match (value, provisioned) {
+        (PlistValue::Boolean(false), _) => true,
+        (_, None) => false,
+        (_, Some(PlistValue::String(pattern))) if pattern == "*" => true,
+        (PlistValue::String(s), Some(PlistValue::String(pattern))) => matches_pattern(pattern, s),
+        (PlistValue::String(_), Some(PlistValue::Array(patterns))) => patterns
+            .iter()
+            .any(|pattern| permitted(value, Some(pattern))),
+        (PlistValue::Array(items), Some(_)) => {
+            items.iter().all(|item| permitted(item, provisioned))
+        }
+        (PlistValue::Dictionary(entries), Some(provisioned)) => entries
+            .iter()
+            .all(|(key, value)| permitted(value, provisioned.dict_get(key))),
+        (value, Some(provisioned)) => value == provisioned,
+    }
+}
+
+/// Returns whether a string matches a pattern where `*` stands for any run of characters,
+/// like the `TEAMID.*` identifiers in provisioning profiles
+pub fn matches_pattern(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // There is always a first part, even if it is empty
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match value.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some(split) => split,
+        // No wildcard, so the whole string has to match
+        None => return rest.is_empty(),
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_pattern_test() {
+        assert!(matches_pattern(
+            "ABCDE12345.*",
+            "ABCDE12345.com.example.app"
+        ));
+        assert!(matches_pattern("ABCDE12345.*", "ABCDE12345."));
+        assert!(!matches_pattern(
+            "ABCDE12345.*",
+            "FGHIJ67890.com.example.app"
+        ));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern(
+            "group.*.shared",
+            "group.com.example.shared"
+        ));
+        assert!(!matches_pattern(
+            "group.*.shared",
+            "group.com.example.sharedx"
+        ));
+        assert!(matches_pattern("exact", "exact"));
+        assert!(!matches_pattern("exact", "exactly"));
+        assert!(!matches_pattern("a*a", "a"));
+    }
+
+    #[test]
+    fn is_subset_of_test() {
+        let provisioned = Entitlements::from_bytes(
+            br#"{
+                "application-identifier" = "ABCDE12345.*";
+                "com.apple.developer.team-identifier" = ABCDE12345;
+                "keychain-access-groups" = ("ABCDE12345.*", "com.apple.token");
+                "com.apple.developer.associated-domains" = "*";
+            }"#,
+        )
+        .unwrap();
+        let app_bytes = br#"<plist><dict>
+            <key>application-identifier</key><string>ABCDE12345.com.example.app</string>
+            <key>com.apple.developer.team-identifier</key><string>ABCDE12345</string>
+            <key>keychain-access-groups</key><array><string>ABCDE12345.com.example.app</string><string>com.apple.token</string></array>
+            <key>com.apple.developer.associated-domains</key><array><string>applinks:example.com</string></array>
+            <key>get-task-allow</key><false/>
+        </dict></plist>"#;
+        let mut app = Entitlements::from_bytes(app_bytes).unwrap();
+        assert_eq!(app.team_identifier(), Some("ABCDE12345"));
+        assert_eq!(
+            app.keychain_access_groups(),
+            vec!["ABCDE12345.com.example.app", "com.apple.token"]
+        );
+        assert!(!app.get_task_allow());
+        assert!(app.is_subset_of(&provisioned));
+        let limits = ParseLimits {
+            max_nodes: 8,
+            ..Default::default()
+        };
+        assert!(Entitlements::from_bytes_with_limits(b"{ a = (1, 2); }", &limits).is_ok());
+        assert!(Entitlements::from_bytes_with_limits(app_bytes, &limits).is_err());
+
+        app.set("get-task-allow", PlistValue::Boolean(true));
+        app.set(
+            "application-identifier",
+            PlistValue::String("FGHIJ67890.com.example.app".to_string()),
+        );
+        assert_eq!(
+            app.violations(&provisioned),
+            vec!["application-identifier", "get-task-allow"]
+        );
+    }
+}
@@ -1,13 +1,76 @@
 // Jackson Coxson
 
+use crate::limits::Limit;
+
 #[derive(Debug)]
 pub enum PlistError {
     Success,
     InvalidArg,
     Format,
-    Parse,
+    /// The input could not be parsed
+    /// For text formats the line and column are 1-based and point at the problem,
+    /// with the offending line in the snippet. They are 0 when no position is known.
+    Parse {
+        line: usize,
+        column: usize,
+        snippet: String,
+        message: String,
+    },
     NoMem,
     Unknown,
+    /// The input went over one of the `ParseLimits`
+    LimitExceeded {
+        limit: Limit,
+        max: usize,
+    },
+    /// Reading or writing a stream failed
+    Io(std::io::Error),
+}
+
+/// The most characters of the offending line kept in a snippet
+const SNIPPET_LEN: usize = 80;
+
+impl PlistError {
+    /// Creates a parse error without a position
+    pub(crate) fn parse(message: impl Into<String>) -> PlistError {
+        PlistError::Parse {
+            line: 0,
+            column: 0,
+            snippet: String::new(),
+            message: message.into(),
+        }
+    }
+
+    /// Creates a parse error pointing at a byte position in some text
+    pub(crate) fn parse_at(text: &str, pos: usize, message: impl Into<String>) -> PlistError {
+        let mut pos = pos.min(text.len());
+        while !text.is_char_boundary(pos) {
+            pos -= 1;
+        }
+        let line_start = text[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = text[pos..]
+            .find('\n')
+            .map(|i| i + pos)
+            .unwrap_or(text.len());
+        let line = text[..line_start].matches('\n').count() + 1;
+        let column = text[line_start..pos].chars().count() + 1;
+
+        // Long lines are cut down to a window around the column
+        let chars: Vec<char> = text[line_start..line_end]
+            .trim_end_matches('\r')
+            .chars()
+            .collect();
+        let start = (column - 1).saturating_sub(SNIPPET_LEN / 2);
+        let start = start.min(chars.len().saturating_sub(SNIPPET_LEN));
+        let snippet = chars.iter().skip(start).take(SNIPPET_LEN).collect();
+
+        PlistError::Parse {
+            line,
+            column,
+            snippet,
+            message: message.into(),
+        }
+    }
 }
 
 impl From<i32> for PlistError {
@@ -16,24 +79,76 @@ impl From<i32> for PlistError {
             0 => PlistError::Success,
             -1 => PlistError::InvalidArg,
             -2 => PlistError::Format,
-            -3 => PlistError::Parse,
+            -3 => PlistError::parse("libplist could not parse the input"),
             -4 => PlistError::NoMem,
             _ => PlistError::Unknown,
         }
     }
 }
 
+impl From<std::io::Error> for PlistError {
+    fn from(error: std::io::Error) -> PlistError {
+        PlistError::Io(error)
+    }
+}
+
 impl std::fmt::Display for PlistError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             PlistError::Success => write!(f, "Success"),
             PlistError::InvalidArg => write!(f, "InvalidArg"),
             PlistError::Format => write!(f, "Format"),
-            PlistError::Parse => write!(f, "Parse"),
+            PlistError::Parse {
+                line: 0, message, ..
+            } => write!(f, "Parse: {}", message),
+            PlistError::Parse {
+                line,
+                column,
+                snippet,
+                message,
+            } => write!(
+                f,
+                "Parse: {} at line {}, column {}: {}",
+                message, line, column, snippet
+            ),
             PlistError::NoMem => write!(f, "NoMem"),
             PlistError::Unknown => write!(f, "Unknown"),
+            PlistError::LimitExceeded { limit, max } => {
+                write!(f, "LimitExceeded: the {} is limited to {}", limit, max)
+            }
+            PlistError::Io(error) => write!(f, "Io: {}", error),
         }
     }
 }
 
 impl std::error::Error for PlistError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_at_test() {
+        let text = "<dict>\n\t<key>a</key>\n\t<strin>b</string>\n</dict>";
+        let pos = text.find("<strin>").unwrap();
+        match PlistError::parse_at(text, pos, "Unexpected tag") {
+            PlistError::Parse {
+                line,
+                column,
+                snippet,
+                message,
+            } => {
+                assert_eq!((line, column), (3, 2));
+                assert_eq!(snippet, "\t<strin>b</string>");
+                assert_eq!(message, "Unexpected tag");
+            }
+            e => panic!("Unexpected error {:?}", e),
+        }
+
+        let long = format!("{}!", "x".repeat(200));
+        let error = PlistError::parse_at(&long, 200, "Bang");
+        assert!(error
+            .to_string()
+            .ends_with(&format!("column 201: {}!", "x".repeat(79))));
+    }
+}
@@ -0,0 +1,455 @@
+// jkcoxson
+
+use log::{trace, warn};
+
+use crate::backend::{Active, Backend};
+use crate::error::PlistError;
+use crate::limits::ParseLimits;
+use crate::{Plist, PlistValue};
+
+/// The devices an app runs on, from `UIDeviceFamily`
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DeviceFamily {
+    IPhone,
+    IPad,
+    AppleTv,
+    AppleWatch,
+    Other(i128),
+}
+
+impl From<i128> for DeviceFamily {
+    fn from(family: i128) -> Self {
+        match family {
+            1 => DeviceFamily::IPhone,
+            2 => DeviceFamily::IPad,
+            3 => DeviceFamily::AppleTv,
+            4 => DeviceFamily::AppleWatch,
+            other => DeviceFamily::Other(other),
+        }
+    }
+}
+
+impl From<DeviceFamily> for i128 {
+    fn from(family: DeviceFamily) -> Self {
+        match family {
+            DeviceFamily::IPhone => 1,
+            DeviceFamily::IPad => 2,
+            DeviceFamily::AppleTv => 3,
+            DeviceFamily::AppleWatch => 4,
+            DeviceFamily::Other(other) => other,
+        }
+    }
+}
+
+/// An entry of `CFBundleURLTypes`, the URL schemes an app handles
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct UrlType {
+    /// `CFBundleURLName`
+    pub name: Option<String>,
+    /// `CFBundleURLSchemes`
+    pub schemes: Vec<String>,
+    /// `CFBundleTypeRole`, such as Editor or Viewer
+    pub role: Option<String>,
+}
+
+/// A domain in the `NSExceptionDomains` of `NSAppTransportSecurity`
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ExceptionDomain {
+    pub domain: String,
+    /// `NSIncludesSubdomains`
+    pub includes_subdomains: bool,
+    /// `NSExceptionAllowsInsecureHTTPLoads`
+    pub allows_insecure_http_loads: bool,
+    /// `NSExceptionMinimumTLSVersion`
+    pub minimum_tls_version: Option<String>,
+}
+
+/// The `NSAppTransportSecurity` settings of an app
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct AppTransportSecurity {
+    /// `NSAllowsArbitraryLoads`
+    pub allows_arbitrary_loads: bool,
+    /// `NSAllowsLocalNetworking`
+    pub allows_local_networking: bool,
+    /// `NSExceptionDomains`
+    pub exception_domains: Vec<ExceptionDomain>,
+}
+
+/// The Info.plist of an app bundle
+/// The common CFBundle keys have typed accessors. Every other key is kept as it is,
+/// in its original order, so the plist can be edited and written back without losing anything.
+///
+/// # Example
+/// ```rust
+/// use plist_plus::InfoPlist;
+///
+/// let mut info = InfoPlist::from_bytes(br#"{ CFBundleIdentifier = "com.example.app"; UIDeviceFamily = (1, 2); }"#).unwrap();
+/// assert_eq!(info.bundle_identifier(), Some("com.example.app"));
+/// info.set_bundle_version("42");
+/// assert!(info.to_xml().unwrap().contains("<string>42</string>"));
+/// ```
+#[derive(PartialEq, Debug, Clone)]
+pub struct InfoPlist {
+    /// Always a dictionary
+    value: PlistValue,
+}
+
+impl Default for InfoPlist {
+    fn default() -> Self {
+        InfoPlist {
+            value: PlistValue::Dictionary(Vec::new()),
+        }
+    }
+}
+
+impl InfoPlist {
+    /// Creates an empty Info.plist
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps a dictionary
+    pub fn from_value(value: PlistValue) -> Result<Self, PlistError> {
+        match value {
+            PlistValue::Dictionary(_) => Ok(InfoPlist { value }),
+            _ => {
+                warn!("Info.plist is not a dictionary");
+                Err(PlistError::InvalidArg)
+            }
+        }
+    }
+
+    /// Parses an Info.plist in any format
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PlistError> {
+        Self::from_bytes_with_limits(bytes, &ParseLimits::default())
+    }
+
+    /// Parses an Info.plist, rejecting it as soon as it goes over the limits
+    /// Use this for Info.plists from app bundles that can't be trusted
+    pub fn from_bytes_with_limits(bytes: &[u8], limits: &ParseLimits) -> Result<Self, PlistError> {
+        trace!("Parsing Info.plist");
+        Self::from_value(crate::limits::from_memory(bytes, limits)?)
+    }
+
+    /// Wraps a copy of a dictionary plist
+    pub fn from_plist(plist: &Plist) -> Result<Self, PlistError> {
+        Self::from_value(PlistValue::from(plist))
+    }
+
+    /// Returns the whole dictionary
+    pub fn as_value(&self) -> &PlistValue {
+        &self.value
+    }
+
+    /// Returns the whole dictionary
+    pub fn into_value(self) -> PlistValue {
+        self.value
+    }
+
+    /// Returns the dictionary as a plist
+    pub fn to_plist(&self) -> Plist {
+        (&self.value).into()
+    }
+
+    /// Serializes the Info.plist as XML, the same way `Plist` does
+    pub fn to_xml(&self) -> Result<String, PlistError> {
+        Active::to_xml(&self.to_plist().plist_t)
+    }
+
+    /// Serializes the Info.plist as a binary plist, the way it is shipped in app bundles
+    pub fn to_bin(&self) -> Result<Vec<u8>, PlistError> {
+        Active::to_bin(&self.to_plist().plist_t)
+    }
+
+    /// Returns the value of any key
+    pub fn get(&self, key: &str) -> Option<&PlistValue> {
+        self.value.dict_get(key)
+    }
+
+    /// Sets the value of any key, keeping its place if it already exists
+    pub fn set(&mut self, key: &str, value: PlistValue) {
+        self.value.dict_set(key, value);
+    }
+
+    /// Removes any key, returning its value
+    pub fn remove(&mut self, key: &str) -> Option<PlistValue> {
+        self.value.dict_remove(key)
+    }
+
+    fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(PlistValue::as_str)
+    }
+
+    fn set_str(&mut self, key: &str, value: &str) {
+        self.set(key, PlistValue::String(value.to_string()));
+    }
+
+    /// `CFBundleIdentifier`
+    pub fn bundle_identifier(&self) -> Option<&str> {
+        self.get_str("CFBundleIdentifier")
+    }
+
+    pub fn set_bundle_identifier(&mut self, identifier: &str) {
+        self.set_str("CFBundleIdentifier", identifier)
+    }
+
+    /// `CFBundleName`
+    pub fn bundle_name(&self) -> Option<&str> {
+        self.get_str("CFBundleName")
+    }
+
+    pub fn set_bundle_name(&mut self, name: &str) {
+        self.set_str("CFBundleName", name)
+    }
+
+    /// `CFBundleDisplayName`
+    pub fn display_name(&self) -> Option<&str> {
+        self.get_str("CFBundleDisplayName")
+    }
+
+    pub fn set_display_name(&mut self, name: &str) {
+        self.set_str("CFBundleDisplayName", name)
+    }
+
+    /// `CFBundleExecutable`
+    pub fn executable(&self) -> Option<&str> {
+        self.get_str("CFBundleExecutable")
+    }
+
+    pub fn set_executable(&mut self, executable: &str) {
+        self.set_str("CFBundleExecutable", executable)
+    }
+
+    /// `CFBundleShortVersionString`, the version shown to users
+    pub fn short_version(&self) -> Option<&str> {
+        self.get_str("CFBundleShortVersionString")
+    }
+
+    pub fn set_short_version(&mut self, version: &str) {
+        self.set_str("CFBundleShortVersionString", version)
+    }
+
+    /// `CFBundleVersion`, the build number
+    pub fn bundle_version(&self) -> Option<&str> {
+        self.get_str("CFBundleVersion")
+    }
+
+    pub fn set_bundle_version(&mut self, version: &str) {
+        self.set_str("CFBundleVersion", version)
+    }
+
+    /// `MinimumOSVersion`
+    pub fn minimum_os_version(&self) -> Option<&str> {
+        self.get_str("MinimumOSVersion")
+    }
+
+    pub fn set_minimum_os_version(&mut self, version: &str) {
+        self.set_str("MinimumOSVersion", version)
+    }
+
+    /// `UIDeviceFamily`
+    /// A single number is read as a list of one, and entries that aren't numbers are skipped.
+    pub fn device_family(&self) -> Vec<DeviceFamily> {
+        let items = match self.get("UIDeviceFamily") {
+            Some(PlistValue::Array(items)) => items.as_slice(),
+            Some(single) => std::slice::from_ref(single),
+            None => return Vec::new(),
+        };
+        items
+            .iter()
+            .filter_map(|item| match item {
+                PlistValue::Integer(i) => Some(*i),
+                // Some tools write the numbers as strings
+                PlistValue::String(s) => s.parse().ok(),
+                _ => None,
+            })
+            .map(DeviceFamily::from)
+            .collect()
+    }
+
+    /// Sets `UIDeviceFamily` to the families, always as an array of numbers
+    pub fn set_device_family(&mut self, families: &[DeviceFamily]) {
+        self.set(
+            "UIDeviceFamily",
+            PlistValue::Array(
+                families
+                    .iter()
+                    .map(|family| PlistValue::Integer((*family).into()))
+                    .collect(),
+            ),
+        )
+    }
+
+    /// `CFBundleURLTypes`
+    pub fn url_types(&self) -> Vec<UrlType> {
+        let items = self
+            .get("CFBundleURLTypes")
+            .and_then(PlistValue::as_array)
+            .unwrap_or_default();
+        items
+            .iter()
+            .map(|item| UrlType {
+                name: item
+                    .dict_get("CFBundleURLName")
+                    .and_then(PlistValue::as_str)
+                    .map(String::from),
+                schemes: strings(item.dict_get("CFBundleURLSchemes")),
+                role: item
+                    .dict_get("CFBundleTypeRole")
+                    .and_then(PlistValue::as_str)
+                    .map(String::from),
+            })
+            .collect()
+    }
+
+    /// `NSAppTransportSecurity`, if the app changes the defaults
+    pub fn app_transport_security(&self) -> Option<AppTransportSecurity> {
+        let ats = self.get("NSAppTransportSecurity")?;
+        let flag = |value: &PlistValue, key: &str| {
+            value
+                .dict_get(key)
+                .and_then(PlistValue::as_bool)
+                .unwrap_or_default()
+        };
+        let exception_domains = match ats.dict_get("NSExceptionDomains") {
+            Some(PlistValue::Dictionary(domains)) => domains
+                .iter()
+                .map(|(domain, settings)| ExceptionDomain {
+                    domain: domain.clone(),
+                    includes_subdomains: flag(settings, "NSIncludesSubdomains"),
+                    allows_insecure_http_loads: flag(
+                        settings,
+                        "NSExceptionAllowsInsecureHTTPLoads",
+                    ),
+                    minimum_tls_version: settings
+                        .dict_get("NSExceptionMinimumTLSVersion")
+                        .and_then(PlistValue::as_str)
+                        .map(String::from),
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Some(AppTransportSecurity {
+            allows_arbitrary_loads: flag(ats, "NSAllowsArbitraryLoads"),
+            allows_local_networking: flag(ats, "NSAllowsLocalNetworking"),
+            exception_domains,
+        })
+    }
+}
+
+/// Returns the strings in an array, skipping anything else
+fn strings(value: Option<&PlistValue>) -> Vec<String> {
+    value
+        .and_then(PlistValue::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(PlistValue::as_str)
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+	<key>CFBundleIdentifier</key>
+	<string>com.example.app</string>
+	<key>CFBundleShortVersionString</key>
+	<string>1.2</string>
+	<key>CFBundleVersion</key>
+	<string>7</string>
+	<key>MinimumOSVersion</key>
+	<string>15.0</string>
+	<key>UIDeviceFamily</key>
+	<array>
+		<integer>1</integer>
+		<integer>2</integer>
+	</array>
+	<key>CFBundleURLTypes</key>
+	<array>
+		<dict>
+			<key>CFBundleURLName</key>
+			<string>com.example.app</string>
+			<key>CFBundleURLSchemes</key>
+			<array>
+				<string>example</string>
+			</array>
+		</dict>
+	</array>
+	<key>NSAppTransportSecurity</key>
+	<dict>
+		<key>NSExceptionDomains</key>
+		<dict>
+			<key>example.com</key>
+			<dict>
+				<key>NSIncludesSubdomains</key>
+				<true/>
+			</dict>
+		</dict>
+	</dict>
+	<key>UILaunchStoryboardName</key>
+	<string>LaunchScreen</string>
+</dict>
+</plist>
+"#;
+
+    #[test]
+    fn info_plist_read_test() {
+        let info = InfoPlist::from_bytes(INFO.as_bytes()).unwrap();
+        assert_eq!(info.bundle_identifier(), Some("com.example.app"));
+        assert_eq!(info.short_version(), Some("1.2"));
+        assert_eq!(info.bundle_version(), Some("7"));
+        assert_eq!(info.minimum_os_version(), Some("15.0"));
+        assert_eq!(info.display_name(), None);
+        assert_eq!(
+            info.device_family(),
+            vec![DeviceFamily::IPhone, DeviceFamily::IPad]
+        );
+        assert_eq!(
+            info.url_types(),
+            vec![UrlType {
+                name: Some("com.example.app".to_string()),
+                schemes: vec!["example".to_string()],
+                role: None,
+            }]
+        );
+        let ats = info.app_transport_security().unwrap();
+        assert!(!ats.allows_arbitrary_loads);
+        assert_eq!(ats.exception_domains[0].domain, "example.com");
+        assert!(ats.exception_domains[0].includes_subdomains);
+
+        assert!(InfoPlist::from_bytes(b"(1, 2)").is_err());
+        let limits = ParseLimits {
+            max_depth: 3,
+            ..Default::default()
+        };
+        assert!(InfoPlist::from_bytes_with_limits(INFO.as_bytes(), &limits).is_err());
+    }
+
+    #[test]
+    fn info_plist_write_test() {
+        let mut info = InfoPlist::from_bytes(INFO.as_bytes()).unwrap();
+        // Writing back untouched gives the same plist
+        let xml = info.to_xml().unwrap();
+        assert_eq!(InfoPlist::from_bytes(xml.as_bytes()).unwrap(), info);
+        assert_eq!(xml, Plist::from_xml(INFO.to_string()).unwrap().to_string());
+
+        info.set_bundle_version("8");
+        info.set_display_name("Example");
+        info.set_device_family(&[DeviceFamily::IPad]);
+        let written = InfoPlist::from_bytes(&info.to_bin().unwrap()).unwrap();
+        assert_eq!(written, info);
+        assert_eq!(written.bundle_version(), Some("8"));
+        assert_eq!(written.device_family(), vec![DeviceFamily::IPad]);
+        // Unknown keys survive and keep their place, new keys go at the end
+        let PlistValue::Dictionary(entries) = written.as_value() else {
+            panic!("Info.plist is not a dictionary");
+        };
+        let keys: Vec<&str> = entries.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys[7], "UILaunchStoryboardName");
+        assert_eq!(keys[8], "CFBundleDisplayName");
+    }
+}
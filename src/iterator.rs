@@ -1,15 +1,18 @@
 // jkcoxson
 
-use libc::c_void;
 use log::trace;
 
-use crate::{unsafe_bindings, Plist, PlistType};
+use crate::{Plist, PlistType};
 
 pub struct PlistIterator {
-    iter_pointer: *mut c_void,
     plist: Plist,
+    index: u32,
+    /// The dictionary keys, read once when the iterator is created
+    keys: Vec<String>,
 }
 
+/// An item of an array or dictionary
+/// The plist is a copy, so it can outlive the container it came from
 #[derive(Debug)]
 pub struct PlistItem {
     pub plist: Plist,
@@ -21,20 +24,16 @@ impl IntoIterator for Plist {
     type IntoIter = PlistIterator;
 
     fn into_iter(self) -> Self::IntoIter {
-        let mut pointer = unsafe { std::mem::zeroed() };
-        match self.plist_type.clone() {
-            PlistType::Array => unsafe {
-                unsafe_bindings::plist_array_new_iter(self.plist_t, &mut pointer)
-            },
-            PlistType::Dictionary => unsafe {
-                unsafe_bindings::plist_dict_new_iter(self.plist_t, &mut pointer)
-            },
+        let keys = match self.plist_type {
+            PlistType::Array => Vec::new(),
+            PlistType::Dictionary => self.dict_get_keys().unwrap_or_default(),
             _ => panic!("Cannot iterate over non-array or non-dictionary plist"),
         };
 
         PlistIterator {
-            iter_pointer: pointer,
             plist: self,
+            index: 0,
+            keys,
         }
     }
 }
@@ -43,68 +42,28 @@ impl Iterator for PlistIterator {
     type Item = PlistItem;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.plist.plist_type {
+        let item = match self.plist.plist_type {
             PlistType::Array => {
-                let to_fill = unsafe { std::mem::zeroed() };
                 trace!("Getting next item in array");
-                unsafe {
-                    unsafe_bindings::plist_array_next_item(
-                        self.plist.plist_t,
-                        self.iter_pointer,
-                        to_fill,
-                    )
-                };
-                if to_fill.is_null() {
-                    trace!("No more items in array");
-                    None
-                } else {
-                    trace!("Getting type of next item in array");
-                    Some(PlistItem {
-                        plist: unsafe { *to_fill }.into(),
-                        key: None,
-                    })
+                let plist = self.plist.array_get_item(self.index).ok()?;
+                PlistItem {
+                    plist: plist.clone(),
+                    key: None,
                 }
             }
             PlistType::Dictionary => {
-                let mut key = unsafe { std::mem::zeroed() };
-                let mut to_fill = unsafe { std::mem::zeroed() };
                 trace!("Getting next item in dictionary");
-                unsafe {
-                    unsafe_bindings::plist_dict_next_item(
-                        self.plist.plist_t,
-                        self.iter_pointer,
-                        &mut key,
-                        &mut to_fill,
-                    )
-                };
-                if to_fill.is_null() {
-                    trace!("No more items in dictionary");
-                    None
-                } else {
-                    let key_str = unsafe {
-                        std::ffi::CString::from_raw(key)
-                            .to_str()
-                            .unwrap()
-                            .to_string()
-                    };
-                    trace!("Getting type of next item in dictionary");
-                    Some(PlistItem {
-                        plist: to_fill.into(),
-                        key: Some(key_str),
-                    })
+                let key = self.keys.get(self.index as usize)?;
+                let plist = self.plist.dict_get_item(key).ok()?;
+                PlistItem {
+                    plist: plist.clone(),
+                    key: Some(key.clone()),
                 }
             }
             _ => panic!("Cannot iterate over non-array or non-dictionary plist"),
-        }
-    }
-}
-
-impl Drop for PlistIterator {
-    fn drop(&mut self) {
-        // Free the pointer
-        unsafe {
-            libc::free(self.iter_pointer);
-        }
+        };
+        self.index += 1;
+        Some(item)
     }
 }
 
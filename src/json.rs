@@ -0,0 +1,434 @@
+// jkcoxson
+
+use log::{trace, warn};
+
+use crate::error::PlistError;
+use crate::limits::{Budget, ParseLimits};
+use crate::value::PlistValue;
+
+/// Parses a JSON plist into a value without using the C library
+/// Numbers without a fraction or exponent become integers, everything else maps directly
+/// Only the nesting depth is limited, so use `from_str_with_limits` for input that can't be trusted
+pub fn from_str(json: &str) -> Result<PlistValue, PlistError> {
+    from_str_with_limits(json, &ParseLimits::depth_only())
+}
+
+/// Parses a JSON plist, rejecting it as soon as it goes over the limits
+pub fn from_str_with_limits(json: &str, limits: &ParseLimits) -> Result<PlistValue, PlistError> {
+    trace!("Parsing JSON plist natively");
+    let budget = Budget::new(limits);
+    budget.total_bytes(json.len())?;
+    let mut reader = Reader {
+        text: json,
+        pos: 0,
+        budget,
+    };
+    reader.skip_whitespace();
+    let value = reader.read_value()?;
+    reader.skip_whitespace();
+    if reader.pos < json.len() {
+        return Err(reader.error("Unexpected content after the root value"));
+    }
+    Ok(value)
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    pos: usize,
+    budget: Budget,
+}
+
+impl<'a> Reader<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn error(&self, message: &str) -> PlistError {
+        self.error_at(self.pos, message)
+    }
+
+    fn error_at(&self, pos: usize, message: &str) -> PlistError {
+        warn!("{} at byte {}", message, pos);
+        PlistError::parse_at(self.text, pos, message)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let trimmed = self.rest().trim_start_matches([' ', '\t', '\n', '\r']);
+        self.pos = self.text.len() - trimmed.len();
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), PlistError> {
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("Expected '{}'", c)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn read_value(&mut self) -> Result<PlistValue, PlistError> {
+        self.budget.node()?;
+        match self.peek() {
+            Some('{') => {
+                self.budget.enter()?;
+                let object = self.read_object()?;
+                self.budget.leave();
+                Ok(object)
+            }
+            Some('[') => {
+                self.budget.enter()?;
+                let array = self.read_array()?;
+                self.budget.leave();
+                Ok(array)
+            }
+            Some('"') => Ok(PlistValue::String(self.read_string()?)),
+            Some('-' | '0'..='9') => self.read_number(),
+            Some(_) => {
+                for (word, value) in [
+                    ("true", PlistValue::Boolean(true)),
+                    ("false", PlistValue::Boolean(false)),
+                    ("null", PlistValue::Null),
+                ] {
+                    if self.rest().starts_with(word) {
+                        self.pos += word.len();
+                        return Ok(value);
+                    }
+                }
+                Err(self.error("Expected a value"))
+            }
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    fn read_object(&mut self) -> Result<PlistValue, PlistError> {
+        self.expect('{')?;
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(PlistValue::Dictionary(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("Expected a string key"));
+            }
+            let key = self.read_string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            entries.push((key, self.read_value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(PlistValue::Dictionary(entries));
+                }
+                _ => return Err(self.error("Expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn read_array(&mut self) -> Result<PlistValue, PlistError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(PlistValue::Array(items));
+        }
+        loop {
+            self.skip_whitespace();
+            items.push(self.read_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(PlistValue::Array(items));
+                }
+                _ => return Err(self.error("Expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn read_hex4(&mut self) -> Result<u32, PlistError> {
+        let hex = self.rest().get(..4).unwrap_or("");
+        match u32::from_str_radix(hex, 16) {
+            Ok(code) if hex.len() == 4 && hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                self.pos += 4;
+                Ok(code)
+            }
+            _ => Err(self.error("Expected 4 hex digits")),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<String, PlistError> {
+        let start = self.pos;
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            let rest = self.rest();
+            let next = match rest.find(['"', '\\']) {
+                Some(next) => next,
+                None => return Err(self.error_at(start, "Unterminated string")),
+            };
+            if let Some(bad) = rest[..next].find(|c: char| c < ' ') {
+                return Err(self.error_at(self.pos + bad, "Control character in string"));
+            }
+            string.push_str(&rest[..next]);
+            self.budget.string(string.len())?;
+            self.pos += next + 1;
+            if rest.as_bytes()[next] == b'"' {
+                return Ok(string);
+            }
+            let escape = self.pos - 1;
+            let c = match self.peek() {
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some('/') => '/',
+                Some('b') => '\u{8}',
+                Some('f') => '\u{c}',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('u') => {
+                    self.pos += 1;
+                    let mut code = self.read_hex4()?;
+                    // Characters outside the BMP are written as a surrogate pair
+                    if (0xD800..0xDC00).contains(&code) && self.rest().starts_with("\\u") {
+                        self.pos += 2;
+                        let low = self.read_hex4()?;
+                        if !(0xDC00..0xE000).contains(&low) {
+                            return Err(self.error_at(escape, "Invalid surrogate pair"));
+                        }
+                        code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                    }
+                    string.push(
+                        char::from_u32(code)
+                            .ok_or_else(|| self.error_at(escape, "Invalid unicode escape"))?,
+                    );
+                    continue;
+                }
+                _ => return Err(self.error_at(escape, "Invalid escape")),
+            };
+            string.push(c);
+            self.pos += 1;
+        }
+    }
+
+    fn read_number(&mut self) -> Result<PlistValue, PlistError> {
+        let start = self.pos;
+        let len = self
+            .rest()
+            .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+            .unwrap_or(self.rest().len());
+        let number = &self.rest()[..len];
+        self.pos += len;
+        let digits = number.strip_prefix('-').unwrap_or(number);
+        if digits.is_empty()
+            || (digits.starts_with('0') && digits.len() > 1 && digits[1..2] != *".")
+        {
+            return Err(self.error_at(start, "Invalid number"));
+        }
+        if !number.contains(['.', 'e', 'E']) {
+            let value = if number.starts_with('-') {
+                number.parse::<i64>().map(|n| n as i128).ok()
+            } else {
+                number.parse::<u64>().map(|n| n as i128).ok()
+            };
+            if let Some(value) = value {
+                return Ok(PlistValue::Integer(value));
+            }
+            return Err(self.error_at(start, "Integer out of range"));
+        }
+        match number.parse::<f64>() {
+            Ok(real) if digits.starts_with(|c: char| c.is_ascii_digit()) => {
+                Ok(PlistValue::Real(real))
+            }
+            _ => Err(self.error_at(start, "Invalid number")),
+        }
+    }
+}
+
+/// Serializes a value as a JSON plist without using the C library
+/// JSON has no dates, data or UIDs, so those are a format error
+pub fn to_string(value: &PlistValue) -> Result<String, PlistError> {
+    trace!("Writing JSON plist natively");
+    let mut out = String::new();
+    write_node(&mut out, value, 0)?;
+    Ok(out)
+}
+
+fn indent(out: &mut String, depth: usize) {
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+}
+
+fn push_escaped(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_node(out: &mut String, value: &PlistValue, depth: usize) -> Result<(), PlistError> {
+    match value {
+        PlistValue::Boolean(b) => out.push_str(if *b { "true" } else { "false" }),
+        // libplist writes null nodes this way too
+        PlistValue::Null => out.push_str("null"),
+        PlistValue::Integer(i) => out.push_str(&i.to_string()),
+        PlistValue::Real(r) if r.is_finite() => {
+            let real = crate::xml::format_real(*r);
+            out.push_str(&real);
+            // Without a fraction or exponent the number would be read back as an integer
+            if !real.contains(['.', 'e']) {
+                out.push_str(".0");
+            }
+        }
+        PlistValue::String(s) => push_escaped(out, s),
+        PlistValue::Array(items) if items.is_empty() => out.push_str("[]"),
+        PlistValue::Array(items) => {
+            out.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(",\n");
+                }
+                indent(out, depth + 1);
+                write_node(out, item, depth + 1)?;
+            }
+            out.push('\n');
+            indent(out, depth);
+            out.push(']');
+        }
+        PlistValue::Dictionary(entries) if entries.is_empty() => out.push_str("{}"),
+        PlistValue::Dictionary(entries) => {
+            out.push_str("{\n");
+            for (i, (key, item)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(",\n");
+                }
+                indent(out, depth + 1);
+                push_escaped(out, key);
+                out.push_str(": ");
+                write_node(out, item, depth + 1)?;
+            }
+            out.push('\n');
+            indent(out, depth);
+            out.push('}');
+        }
+        value => {
+            warn!("{:?} values can't be written to JSON", value.plist_type());
+            return Err(PlistError::Format);
+        }
+    }
+    Ok(())
+}
+
+impl PlistValue {
+    /// Parses a JSON plist without using the C library
+    /// This is the native alternative to `Plist::from_json`
+    pub fn from_json(json: &str) -> Result<PlistValue, PlistError> {
+        from_str(json)
+    }
+    /// Serializes the value as a JSON plist without using the C library
+    pub fn to_json(&self) -> Result<String, PlistError> {
+        to_string(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_json_test() {
+        let value = from_str(
+            r#"{"Name": "a\"bé😀", "Count": -3, "Big": 18446744073709551615,
+                "Ratio": 0.5, "List": [true, false, null, []], "Empty": {}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            PlistValue::Dictionary(vec![
+                (
+                    "Name".to_string(),
+                    PlistValue::String("a\"bé😀".to_string())
+                ),
+                ("Count".to_string(), PlistValue::Integer(-3)),
+                ("Big".to_string(), PlistValue::Integer(u64::MAX as i128)),
+                ("Ratio".to_string(), PlistValue::Real(0.5)),
+                (
+                    "List".to_string(),
+                    PlistValue::Array(vec![
+                        PlistValue::Boolean(true),
+                        PlistValue::Boolean(false),
+                        PlistValue::Null,
+                        PlistValue::Array(vec![]),
+                    ])
+                ),
+                ("Empty".to_string(), PlistValue::Dictionary(vec![])),
+            ])
+        );
+    }
+
+    #[test]
+    fn read_json_malformed_test() {
+        let error = from_str("{\n  \"a\": 1,\n  \"b\": tru\n}").unwrap_err();
+        match error {
+            PlistError::Parse {
+                line,
+                column,
+                snippet,
+                ..
+            } => {
+                assert_eq!((line, column), (3, 8));
+                assert_eq!(snippet, "  \"b\": tru");
+            }
+            e => panic!("Unexpected error {:?}", e),
+        }
+        assert!(from_str("[1,]").is_err());
+        assert!(from_str("01").is_err());
+        assert!(from_str("\"abc").is_err());
+        assert!(from_str("[1] 2").is_err());
+    }
+
+    #[test]
+    fn write_json_test() {
+        let value = PlistValue::Dictionary(vec![
+            ("Name".to_string(), PlistValue::String("a\"b\n".to_string())),
+            (
+                "List".to_string(),
+                PlistValue::Array(vec![
+                    PlistValue::Integer(-3),
+                    PlistValue::Real(2.0),
+                    PlistValue::Boolean(true),
+                ]),
+            ),
+            ("Empty".to_string(), PlistValue::Array(vec![])),
+        ]);
+        let json = to_string(&value).unwrap();
+        assert_eq!(
+            json,
+            "{\n  \"Name\": \"a\\\"b\\n\",\n  \"List\": [\n    -3,\n    2.0,\n    true\n  ],\n  \"Empty\": []\n}"
+        );
+        assert_eq!(from_str(&json).unwrap(), value);
+        assert_eq!(to_string(&PlistValue::Null).unwrap(), "null");
+        assert!(to_string(&PlistValue::Data(vec![1])).is_err());
+        assert!(to_string(&PlistValue::Real(f64::NAN)).is_err());
+    }
+}
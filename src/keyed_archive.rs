@@ -0,0 +1,769 @@
+// jkcoxson
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use log::{trace, warn};
+
+use crate::error::PlistError;
+use crate::limits::{Budget, ParseLimits};
+use crate::value::{mac_secs_to_system_time, system_time_to_mac_secs};
+use crate::{Plist, PlistValue};
+
+/// Decodes the objects of a custom class in an `NSKeyedArchiver` archive
+/// Register it with `KeyedArchive::register` to handle classes the built in decoders don't know.
+pub trait ClassDecoder {
+    /// The classes this decodes, matched against `$classname` and then its superclasses
+    fn class_names(&self) -> &[&str];
+    /// Decodes an archived object, a dictionary whose fields can be resolved with the decoder
+    fn decode(&self, object: &PlistValue, decoder: &mut Decoder) -> Result<PlistValue, PlistError>;
+}
+
+/// Decodes `NSKeyedArchiver` archives into a `PlistValue` tree
+/// References into `$objects` are resolved, and NSDictionary, NSArray, NSSet, NSString,
+/// NSData, NSDate and NSNull are turned into the matching plist values. Numbers and
+/// booleans are archived as plain values and are kept as they are. Objects of other
+/// classes become dictionaries of their fields with the class name under `$class`.
+pub struct KeyedArchive {
+    decoders: Vec<Box<dyn ClassDecoder>>,
+    limits: ParseLimits,
+}
+
+impl Default for KeyedArchive {
+    fn default() -> Self {
+        KeyedArchive::with_limits(ParseLimits::default())
+    }
+}
+
+impl KeyedArchive {
+    /// Creates a decoder that only knows the built in classes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a decoder that stops at the given nesting depth and number of values
+    pub fn with_limits(limits: ParseLimits) -> Self {
+        KeyedArchive {
+            decoders: Vec::new(),
+            limits,
+        }
+    }
+
+    /// Adds a decoder for custom classes
+    /// It is tried before the built in decoders, so it can also replace them.
+    pub fn register(&mut self, decoder: impl ClassDecoder + 'static) {
+        self.decoders.push(Box::new(decoder));
+    }
+
+    /// Decodes an archive with the built in classes
+    ///
+    /// # Example
+    /// ```rust
+    /// use plist_plus::{KeyedArchive, Plist};
+    ///
+    /// let archive = Plist::from_xml(r#"<plist version="1.0"><dict>
+    ///     <key>$archiver</key><string>NSKeyedArchiver</string>
+    ///     <key>$top</key><dict><key>root</key><dict><key>CF$UID</key><integer>1</integer></dict></dict>
+    ///     <key>$objects</key><array><string>$null</string><string>Hello</string></array>
+    /// </dict></plist>"#.to_string()).unwrap();
+    /// let value = KeyedArchive::decode(&archive).unwrap();
+    /// assert_eq!(value.as_str(), Some("Hello"));
+    /// ```
+    pub fn decode(plist: &Plist) -> Result<PlistValue, PlistError> {
+        KeyedArchive::new().decode_plist(plist)
+    }
+
+    /// Decodes an archive with the registered decoders
+    pub fn decode_plist(&self, plist: &Plist) -> Result<PlistValue, PlistError> {
+        self.decode_value(&PlistValue::from(plist))
+    }
+
+    /// Decodes an archive that has already been parsed into a value
+    /// The object under the `root` key of `$top` is returned. Archives without one
+    /// return a dictionary of everything in `$top`.
+    pub fn decode_value(&self, archive: &PlistValue) -> Result<PlistValue, PlistError> {
+        trace!("Decoding keyed archive");
+        if let Some(archiver) = archive.dict_get("$archiver").and_then(PlistValue::as_str) {
+            if archiver != "NSKeyedArchiver" {
+                trace!("Archive was written by {}", archiver);
+            }
+        }
+        let objects = match archive.dict_get("$objects") {
+            Some(PlistValue::Array(objects)) => objects,
+            _ => return Err(malformed("Keyed archive has no $objects array")),
+        };
+        let top = match archive.dict_get("$top") {
+            Some(PlistValue::Dictionary(top)) => top,
+            _ => return Err(malformed("Keyed archive has no $top dictionary")),
+        };
+
+        let mut decoder = Decoder {
+            objects,
+            decoders: &self.decoders,
+            budget: Budget::new(&self.limits),
+            decoding: Vec::new(),
+        };
+        if let Some((_, root)) = top.iter().find(|(key, _)| key == "root") {
+            return decoder.decode(root);
+        }
+        top.iter()
+            .map(|(key, value)| Ok((key.clone(), decoder.decode(value)?)))
+            .collect::<Result<_, PlistError>>()
+            .map(PlistValue::Dictionary)
+    }
+}
+
+/// Resolves references while an archive is being decoded
+pub struct Decoder<'a> {
+    objects: &'a [PlistValue],
+    decoders: &'a [Box<dyn ClassDecoder>],
+    budget: Budget,
+    /// The objects being decoded, to catch references that loop back on themselves
+    decoding: Vec<u64>,
+}
+
+impl Decoder<'_> {
+    /// Decodes a value, following it into `$objects` if it is a reference
+    pub fn decode(&mut self, value: &PlistValue) -> Result<PlistValue, PlistError> {
+        self.budget.node()?;
+        match value {
+            PlistValue::Uid(uid) => self.decode_uid(*uid),
+            PlistValue::Array(items) => items
+                .iter()
+                .map(|item| self.decode(item))
+                .collect::<Result<_, PlistError>>()
+                .map(PlistValue::Array),
+            other => Ok(other.clone()),
+        }
+    }
+
+    /// Decodes the field of an archived object with the given key, if it has one
+    pub fn field(
+        &mut self,
+        object: &PlistValue,
+        key: &str,
+    ) -> Result<Option<PlistValue>, PlistError> {
+        object
+            .dict_get(key)
+            .map(|value| self.decode(value))
+            .transpose()
+    }
+
+    fn decode_uid(&mut self, uid: u64) -> Result<PlistValue, PlistError> {
+        let objects = self.objects;
+        let object = match objects.get(uid as usize) {
+            Some(object) => object,
+            None => return Err(malformed(format!("Reference {} is outside $objects", uid))),
+        };
+        if self.decoding.contains(&uid) {
+            return Err(malformed(format!("Object {} refers to itself", uid)));
+        }
+        self.budget.enter()?;
+        self.decoding.push(uid);
+        let res = self.decode_object(object);
+        self.decoding.pop();
+        self.budget.leave();
+        res
+    }
+
+    fn decode_object(&mut self, object: &PlistValue) -> Result<PlistValue, PlistError> {
+        let class = match object {
+            PlistValue::String(s) if s == "$null" => return Ok(PlistValue::Null),
+            PlistValue::Dictionary(_) => match object.dict_get("$class") {
+                Some(class) => class,
+                None => return self.decode_fields(object),
+            },
+            other => return Ok(other.clone()),
+        };
+        let classes = self.class_names(class)?;
+        let decoders = self.decoders;
+        for name in &classes {
+            if let Some(decoder) = decoders
+                .iter()
+                .find(|decoder| decoder.class_names().contains(&name.as_str()))
+            {
+                trace!("Decoding {} with a registered decoder", name);
+                return decoder.decode(object, self);
+            }
+            if let Some(value) = self.decode_builtin(name, object)? {
+                return Ok(value);
+            }
+        }
+
+        trace!("Decoding unknown class {} as a dictionary", classes[0]);
+        let mut value = self.decode_fields(object)?;
+        if let PlistValue::Dictionary(entries) = &mut value {
+            entries.insert(
+                0,
+                ("$class".to_string(), PlistValue::String(classes[0].clone())),
+            );
+        }
+        Ok(value)
+    }
+
+    /// Returns the name of an object's class followed by its superclasses
+    fn class_names(&mut self, class: &PlistValue) -> Result<Vec<String>, PlistError> {
+        let class = self.decode(class)?;
+        let mut names = Vec::new();
+        if let Some(PlistValue::Array(classes)) = class.dict_get("$classes") {
+            names.extend(
+                classes
+                    .iter()
+                    .filter_map(PlistValue::as_str)
+                    .map(String::from),
+            );
+        }
+        if let Some(name) = class.dict_get("$classname").and_then(PlistValue::as_str) {
+            if !names.iter().any(|n| n == name) {
+                names.insert(0, name.to_string());
+            }
+        }
+        if names.is_empty() {
+            return Err(malformed("Archived class has no $classname"));
+        }
+        Ok(names)
+    }
+
+    fn decode_builtin(
+        &mut self,
+        name: &str,
+        object: &PlistValue,
+    ) -> Result<Option<PlistValue>, PlistError> {
+        let value = match name {
+            "NSDictionary" | "NSMutableDictionary" => {
+                let keys = self.field(object, "NS.keys")?;
+                let values = self.field(object, "NS.objects")?;
+                let (keys, values) = match (keys, values) {
+                    (Some(PlistValue::Array(keys)), Some(PlistValue::Array(values))) => {
+                        (keys, values)
+                    }
+                    (None, None) => (Vec::new(), Vec::new()),
+                    _ => return Err(malformed("NSDictionary keys or objects aren't arrays")),
+                };
+                if keys.len() != values.len() {
+                    return Err(malformed(
+                        "NSDictionary has a different number of keys and objects",
+                    ));
+                }
+                let mut entries = Vec::with_capacity(keys.len());
+                for (key, value) in keys.into_iter().zip(values) {
+                    match key {
+                        PlistValue::String(key) => entries.push((key, value)),
+                        _ => return Err(malformed("NSDictionary key is not a string")),
+                    }
+                }
+                PlistValue::Dictionary(entries)
+            }
+            "NSArray"
+            | "NSMutableArray"
+            | "NSSet"
+            | "NSMutableSet"
+            | "NSOrderedSet"
+            | "NSMutableOrderedSet" => match self.field(object, "NS.objects")? {
+                Some(PlistValue::Array(items)) => PlistValue::Array(items),
+                None => PlistValue::Array(Vec::new()),
+                Some(_) => return Err(malformed(format!("{} objects aren't an array", name))),
+            },
+            "NSString" | "NSMutableString" => {
+                match (
+                    self.field(object, "NS.string")?,
+                    self.field(object, "NS.bytes")?,
+                ) {
+                    (Some(PlistValue::String(s)), _) => PlistValue::String(s),
+                    (_, Some(PlistValue::Data(bytes))) => match String::from_utf8(bytes) {
+                        Ok(s) => PlistValue::String(s),
+                        Err(_) => return Err(malformed("NSString bytes aren't UTF-8")),
+                    },
+                    _ => return Err(malformed("NSString has no string")),
+                }
+            }
+            "NSData" | "NSMutableData" => match self.field(object, "NS.data")? {
+                Some(PlistValue::Data(data)) => PlistValue::Data(data),
+                _ => return Err(malformed("NSData has no data")),
+            },
+            "NSDate" => match self.field(object, "NS.time")? {
+                Some(PlistValue::Real(secs)) => PlistValue::Date(mac_secs_to_system_time(secs)?),
+                Some(PlistValue::Integer(secs)) => {
+                    PlistValue::Date(mac_secs_to_system_time(secs as f64)?)
+                }
+                _ => return Err(malformed("NSDate has no time")),
+            },
+            "NSNull" => PlistValue::Null,
+            _ => return Ok(None),
+        };
+        Ok(Some(value))
+    }
+
+    /// Decodes every field of an object except its class
+    fn decode_fields(&mut self, object: &PlistValue) -> Result<PlistValue, PlistError> {
+        let entries = match object {
+            PlistValue::Dictionary(entries) => entries,
+            _ => return Ok(object.clone()),
+        };
+        entries
+            .iter()
+            .filter(|(key, _)| key != "$class")
+            .map(|(key, value)| Ok((key.clone(), self.decode(value)?)))
+            .collect::<Result<_, PlistError>>()
+            .map(PlistValue::Dictionary)
+    }
+}
+
+/// A type that can be written into an `NSKeyedArchiver` archive
+pub trait Archive {
+    /// Adds the value to the archive and returns what refers to it, usually a `Uid`
+    fn archive(&self, archiver: &mut KeyedArchiver) -> PlistValue;
+}
+
+impl Archive for PlistValue {
+    /// Numbers, booleans and data are archived as plain values, dates as NSDate,
+    /// arrays as NSArray and dictionaries as NSDictionary.
+    /// Strings become NSString objects when they would be mistaken for `$null`.
+    /// A `Uid` is kept as it is, as a reference the caller has already made.
+    fn archive(&self, archiver: &mut KeyedArchiver) -> PlistValue {
+        match self {
+            PlistValue::Null => PlistValue::Uid(0),
+            PlistValue::Uid(uid) => PlistValue::Uid(*uid),
+            PlistValue::String(s) if s == "$null" => archiver.add_object(
+                &["NSString", "NSObject"],
+                vec![("NS.string".to_string(), self.clone())],
+            ),
+            PlistValue::Boolean(_)
+            | PlistValue::Integer(_)
+            | PlistValue::Real(_)
+            | PlistValue::Data(_)
+            | PlistValue::String(_) => archiver.add(self.clone()),
+            PlistValue::Date(date) => archiver.add_object(
+                &["NSDate", "NSObject"],
+                vec![(
+                    "NS.time".to_string(),
+                    PlistValue::Real(system_time_to_mac_secs(*date)),
+                )],
+            ),
+            PlistValue::Array(items) => {
+                let items = items.iter().map(|item| item.archive(archiver)).collect();
+                archiver.add_object(
+                    &["NSArray", "NSObject"],
+                    vec![("NS.objects".to_string(), PlistValue::Array(items))],
+                )
+            }
+            PlistValue::Dictionary(entries) => {
+                let mut keys = Vec::with_capacity(entries.len());
+                let mut values = Vec::with_capacity(entries.len());
+                for (key, value) in entries {
+                    keys.push(PlistValue::String(key.clone()).archive(archiver));
+                    values.push(value.archive(archiver));
+                }
+                archiver.add_object(
+                    &["NSDictionary", "NSObject"],
+                    vec![
+                        ("NS.keys".to_string(), PlistValue::Array(keys)),
+                        ("NS.objects".to_string(), PlistValue::Array(values)),
+                    ],
+                )
+            }
+        }
+    }
+}
+
+impl Archive for Plist {
+    fn archive(&self, archiver: &mut KeyedArchiver) -> PlistValue {
+        PlistValue::from(self).archive(archiver)
+    }
+}
+
+/// Builds `NSKeyedArchiver` archives
+/// Identical objects, including the class descriptions, are only stored once.
+///
+/// # Example
+/// ```rust
+/// use plist_plus::{KeyedArchive, KeyedArchiver, PlistValue};
+///
+/// let value = PlistValue::Array(vec![PlistValue::String("a".into()), PlistValue::String("a".into())]);
+/// let archive = KeyedArchiver::archive(&value);
+/// assert_eq!(KeyedArchive::new().decode_value(&archive).unwrap(), value);
+/// ```
+pub struct KeyedArchiver {
+    objects: Vec<PlistValue>,
+    /// The indices in `objects` by the hash of the object, so duplicates are found quickly
+    index: HashMap<u64, Vec<usize>>,
+    top: Vec<(String, PlistValue)>,
+}
+
+impl Default for KeyedArchiver {
+    fn default() -> Self {
+        let null = PlistValue::String("$null".to_string());
+        KeyedArchiver {
+            index: HashMap::from([(object_hash(&null), vec![0])]),
+            objects: vec![null],
+            top: Vec::new(),
+        }
+    }
+}
+
+impl KeyedArchiver {
+    /// Creates an empty archive
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Archives a value as the root object and returns the finished archive
+    pub fn archive(root: &(impl Archive + ?Sized)) -> PlistValue {
+        let mut archiver = KeyedArchiver::new();
+        archiver.set_top("root", root);
+        archiver.finish()
+    }
+
+    /// Adds a value to `$objects` as it is and returns a reference to it
+    /// If an identical object is already there, that one is referenced instead.
+    pub fn add(&mut self, object: PlistValue) -> PlistValue {
+        let same_hash = self.index.entry(object_hash(&object)).or_default();
+        if let Some(&index) = same_hash.iter().find(|&&i| self.objects[i] == object) {
+            return PlistValue::Uid(index as u64);
+        }
+        same_hash.push(self.objects.len());
+        self.objects.push(object);
+        PlistValue::Uid(self.objects.len() as u64 - 1)
+    }
+
+    /// Adds an object of the given class and returns a reference to it
+    /// The class names start with the class itself, followed by its superclasses.
+    /// Fields should already be archived, so they hold references or plain values.
+    pub fn add_object(
+        &mut self,
+        class_names: &[&str],
+        mut fields: Vec<(String, PlistValue)>,
+    ) -> PlistValue {
+        let class = self.add(PlistValue::Dictionary(vec![
+            (
+                "$classes".to_string(),
+                PlistValue::Array(
+                    class_names
+                        .iter()
+                        .map(|name| PlistValue::String(name.to_string()))
+                        .collect(),
+                ),
+            ),
+            (
+                "$classname".to_string(),
+                PlistValue::String(
+                    class_names
+                        .first()
+                        .copied()
+                        .unwrap_or("NSObject")
+                        .to_string(),
+                ),
+            ),
+        ]));
+        fields.push(("$class".to_string(), class));
+        self.add(PlistValue::Dictionary(fields))
+    }
+
+    /// Archives a value under the given key of `$top`
+    pub fn set_top(&mut self, key: &str, value: &(impl Archive + ?Sized)) {
+        trace!("Archiving {}", key);
+        let value = value.archive(self);
+        match self.top.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.top.push((key.to_string(), value)),
+        }
+    }
+
+    /// Returns the finished archive
+    pub fn finish(self) -> PlistValue {
+        PlistValue::Dictionary(vec![
+            ("$version".to_string(), PlistValue::Integer(100000)),
+            (
+                "$archiver".to_string(),
+                PlistValue::String("NSKeyedArchiver".to_string()),
+            ),
+            ("$top".to_string(), PlistValue::Dictionary(self.top)),
+            ("$objects".to_string(), PlistValue::Array(self.objects)),
+        ])
+    }
+}
+
+fn malformed(message: impl Into<String>) -> PlistError {
+    let message = message.into();
+    warn!("{}", message);
+    PlistError::parse(message)
+}
+
+/// Hashes an object so that equal objects hash the same
+fn object_hash(object: &PlistValue) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_value(object, &mut hasher);
+    hasher.finish()
+}
+
+fn hash_value(value: &PlistValue, state: &mut DefaultHasher) {
+    std::mem::discriminant(value).hash(state);
+    match value {
+        PlistValue::Boolean(b) => b.hash(state),
+        PlistValue::Integer(i) => i.hash(state),
+        // 0.0 and -0.0 are equal, and NaN is never equal to anything
+        PlistValue::Real(r) if *r == 0.0 => 0_u64.hash(state),
+        PlistValue::Real(r) => r.to_bits().hash(state),
+        PlistValue::Date(time) => time.hash(state),
+        PlistValue::Data(data) => data.hash(state),
+        PlistValue::String(s) => s.hash(state),
+        PlistValue::Array(items) => {
+            items.len().hash(state);
+            for item in items {
+                hash_value(item, state);
+            }
+        }
+        PlistValue::Dictionary(entries) => {
+            entries.len().hash(state);
+            for (key, item) in entries {
+                key.hash(state);
+                hash_value(item, state);
+            }
+        }
+        PlistValue::Uid(uid) => uid.hash(state),
+        PlistValue::Null => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Limit;
+
+    fn archive(objects: &str, root: u64) -> PlistValue {
+        crate::xml::from_str(&format!(
+            "<plist version=\"1.0\"><dict>\
+             <key>$archiver</key><string>NSKeyedArchiver</string>\
+             <key>$version</key><integer>100000</integer>\
+             <key>$top</key><dict><key>root</key><dict><key>CF$UID</key><integer>{}</integer></dict></dict>\
+             <key>$objects</key><array><string>$null</string>{}</array>\
+             </dict></plist>",
+            root, objects
+        ))
+        .unwrap()
+    }
+
+    fn uid(uid: u64) -> String {
+        format!("<dict><key>CF$UID</key><integer>{}</integer></dict>", uid)
+    }
+
+    fn class(name: &str, supers: &[&str]) -> String {
+        let classes: String = std::iter::once(name)
+            .chain(supers.iter().copied())
+            .map(|c| format!("<string>{}</string>", c))
+            .collect();
+        format!(
+            "<dict><key>$classname</key><string>{}</string><key>$classes</key><array>{}</array></dict>",
+            name, classes
+        )
+    }
+
+    #[test]
+    fn decode_builtin_test() {
+        // 1: dictionary, 2: key, 3: array, 4: NSDictionary class, 5: NSArray class,
+        // 6: string, 7: NSDate, 8: NSDate class, 9: NSData, 10: NSData class
+        let objects = format!(
+            "<dict><key>NS.keys</key><array>{}</array><key>NS.objects</key><array>{}</array><key>$class</key>{}</dict>\
+             <string>Items</string>\
+             <dict><key>NS.objects</key><array>{}{}<integer>7</integer><true/>{}{}</array><key>$class</key>{}</dict>\
+             {}{}\
+             <string>Hello</string>\
+             <dict><key>NS.time</key><real>0</real><key>$class</key>{}</dict>\
+             {}\
+             <dict><key>NS.data</key><data>AAEC</data><key>$class</key>{}</dict>\
+             {}",
+            uid(2), uid(3), uid(4),
+            uid(6), uid(7), uid(9), uid(0), uid(5),
+            class("NSMutableDictionary", &["NSDictionary", "NSObject"]),
+            class("NSArray", &["NSObject"]),
+            uid(8),
+            class("NSDate", &["NSObject"]),
+            uid(10),
+            class("NSMutableData", &["NSData", "NSObject"]),
+        );
+        let value = KeyedArchive::new()
+            .decode_value(&archive(&objects, 1))
+            .unwrap();
+        let items = value.dict_get("Items").unwrap();
+        assert_eq!(
+            items.array_get(0),
+            Some(&PlistValue::String("Hello".into()))
+        );
+        assert_eq!(
+            items.array_get(1),
+            Some(&PlistValue::Date(mac_secs_to_system_time(0.0).unwrap()))
+        );
+        assert_eq!(items.array_get(2), Some(&PlistValue::Integer(7)));
+        assert_eq!(items.array_get(3), Some(&PlistValue::Boolean(true)));
+        assert_eq!(items.array_get(4), Some(&PlistValue::Data(vec![0, 1, 2])));
+        assert_eq!(items.array_get(5), Some(&PlistValue::Null));
+    }
+
+    struct Point;
+
+    impl ClassDecoder for Point {
+        fn class_names(&self) -> &[&str] {
+            &["Point"]
+        }
+        fn decode(
+            &self,
+            object: &PlistValue,
+            decoder: &mut Decoder,
+        ) -> Result<PlistValue, PlistError> {
+            let x = decoder
+                .field(object, "x")?
+                .unwrap_or(PlistValue::Integer(0));
+            let y = decoder
+                .field(object, "y")?
+                .unwrap_or(PlistValue::Integer(0));
+            Ok(PlistValue::Array(vec![x, y]))
+        }
+    }
+
+    #[test]
+    fn decode_custom_class_test() {
+        let objects = format!(
+            "<dict><key>x</key><integer>3</integer><key>y</key><integer>4</integer><key>$class</key>{}</dict>{}",
+            uid(2),
+            class("Point", &["NSObject"])
+        );
+        let archive = archive(&objects, 1);
+
+        // Without a decoder the fields are kept with the class name
+        let value = KeyedArchive::new().decode_value(&archive).unwrap();
+        assert_eq!(
+            value.dict_get("$class").and_then(PlistValue::as_str),
+            Some("Point")
+        );
+        assert_eq!(value.dict_get("y"), Some(&PlistValue::Integer(4)));
+
+        let mut decoder = KeyedArchive::new();
+        decoder.register(Point);
+        assert_eq!(
+            decoder.decode_value(&archive).unwrap(),
+            PlistValue::Array(vec![PlistValue::Integer(3), PlistValue::Integer(4)])
+        );
+    }
+
+    #[test]
+    fn decode_malformed_test() {
+        // An array that contains itself
+        let objects = format!(
+            "<dict><key>NS.objects</key><array>{}</array><key>$class</key>{}</dict>{}",
+            uid(1),
+            uid(2),
+            class("NSArray", &["NSObject"])
+        );
+        assert!(matches!(
+            KeyedArchive::new().decode_value(&archive(&objects, 1)),
+            Err(PlistError::Parse { .. })
+        ));
+        assert!(KeyedArchive::new()
+            .decode_value(&archive("<string>a</string>", 5))
+            .is_err());
+        assert!(KeyedArchive::new()
+            .decode_value(&PlistValue::Dictionary(Vec::new()))
+            .is_err());
+
+        let objects = format!(
+            "<dict><key>NS.objects</key><array>{}{}</array><key>$class</key>{}</dict>{}<string>a</string>",
+            uid(3),
+            uid(3),
+            uid(2),
+            class("NSArray", &["NSObject"])
+        );
+        let limits = ParseLimits {
+            max_nodes: 2,
+            ..Default::default()
+        };
+        assert!(matches!(
+            KeyedArchive::with_limits(limits).decode_value(&archive(&objects, 1)),
+            Err(PlistError::LimitExceeded {
+                limit: Limit::Nodes,
+                ..
+            })
+        ));
+    }
+    struct Counter {
+        count: i128,
+    }
+
+    impl Archive for Counter {
+        fn archive(&self, archiver: &mut KeyedArchiver) -> PlistValue {
+            let count = PlistValue::Integer(self.count).archive(archiver);
+            archiver.add_object(&["Counter", "NSObject"], vec![("count".to_string(), count)])
+        }
+    }
+
+    #[test]
+    fn archive_round_trip_test() {
+        let value = PlistValue::Dictionary(vec![
+            ("Name".to_string(), PlistValue::String("Name".to_string())),
+            (
+                "Items".to_string(),
+                PlistValue::Array(vec![
+                    PlistValue::Integer(-1),
+                    PlistValue::Real(1.5),
+                    PlistValue::Boolean(false),
+                    PlistValue::Data(vec![1, 2]),
+                    PlistValue::Date(mac_secs_to_system_time(10.0).unwrap()),
+                    PlistValue::String("$null".to_string()),
+                    PlistValue::Null,
+                    PlistValue::Array(Vec::new()),
+                ]),
+            ),
+        ]);
+        let archive = KeyedArchiver::archive(&value);
+        assert_eq!(
+            archive.dict_get("$archiver").and_then(PlistValue::as_str),
+            Some("NSKeyedArchiver")
+        );
+        assert_eq!(KeyedArchive::new().decode_value(&archive).unwrap(), value);
+
+        // It also survives being written out and parsed again
+        let xml = crate::xml::to_string(&archive).unwrap();
+        let parsed = crate::xml::from_str(&xml).unwrap();
+        assert_eq!(KeyedArchive::new().decode_value(&parsed).unwrap(), value);
+    }
+
+    #[test]
+    fn archive_dedup_test() {
+        let mut archiver = KeyedArchiver::new();
+        let first = Counter { count: 1 }.archive(&mut archiver);
+        let second = Counter { count: 1 }.archive(&mut archiver);
+        let third = Counter { count: 2 }.archive(&mut archiver);
+        assert_eq!(first, second);
+        assert_ne!(first, third);
+        archiver.set_top("root", &PlistValue::Array(vec![first, second, third]));
+        let archive = archiver.finish();
+        // $null, 1, the Counter class, the first counter, 2, the second counter,
+        // the NSArray class and the array
+        assert!(matches!(
+            archive.dict_get("$objects"),
+            Some(PlistValue::Array(objects)) if objects.len() == 8
+        ));
+
+        let decoded = KeyedArchive::new().decode_value(&archive).unwrap();
+        let counter = decoded.array_get(2).unwrap();
+        assert_eq!(
+            counter.dict_get("$class").and_then(PlistValue::as_str),
+            Some("Counter")
+        );
+        assert_eq!(counter.dict_get("count"), Some(&PlistValue::Integer(2)));
+
+        let mut archiver = KeyedArchiver::new();
+        assert_eq!(
+            archiver.add(PlistValue::String("$null".to_string())),
+            PlistValue::Uid(0)
+        );
+        assert_eq!(
+            archiver.add(PlistValue::Real(0.0)),
+            archiver.add(PlistValue::Real(-0.0))
+        );
+        assert_ne!(
+            archiver.add(PlistValue::Real(f64::NAN)),
+            archiver.add(PlistValue::Real(f64::NAN))
+        );
+    }
+}
@@ -1,19 +1,70 @@
 #![allow(clippy::not_unsafe_ptr_arg_deref)]
+use backend::{Active, Backend};
 use error::PlistError;
 #[doc = include_str!("../README.md")]
 use log::{trace, warn};
 use rand::Rng;
-use std::{ffi::CString, fmt::Formatter, os::raw::c_char};
+use std::fmt::Formatter;
 
+#[cfg(feature = "async")]
+mod async_io;
+mod backend;
+pub mod bplist;
+mod canonical;
+mod cmp;
+mod codec;
+mod diff;
+pub mod entitlements;
 pub mod error;
+pub mod info_plist;
 mod iterator;
+pub mod json;
+mod keyed_archive;
+mod limits;
+mod merge;
+pub mod openstep;
+mod patch;
+pub mod path;
+pub mod provisioning;
+pub mod strings;
 mod types;
+#[cfg(not(feature = "pure-rust"))]
 mod unsafe_bindings;
+pub mod usbmux;
+mod value;
+pub mod xml;
+
+pub use codec::{PlistCodec, PlistFormat, FRAME_HEADER_LEN};
+pub use diff::{diff, Change};
+pub use entitlements::Entitlements;
+pub use info_plist::InfoPlist;
+pub use keyed_archive::{Archive, ClassDecoder, Decoder, KeyedArchive, KeyedArchiver};
+pub use limits::{Limit, ParseLimits};
+pub use merge::{ArrayMergeStrategy, MergeReport, MergeStrategy, TypeConflictStrategy};
+pub use patch::{PatchOperation, PlistPatch};
+pub use provisioning::ProvisioningProfile;
+pub use strings::StringsFile;
+pub use value::PlistValue;
+
+/// The libplist release the `vendored` feature expects in the `libplist` directory
+pub const VENDORED_LIBPLIST_VERSION: &str = env!("LIBPLIST_VENDORED_VERSION");
+
+/// Returns the version of libplist that pkg-config reported when plist_plus was built
+/// This is the pinned release for vendored builds. A shared libplist loaded at runtime
+/// can be a different version, so this is not checked against the library in use.
+/// None means libplist was found without pkg-config, or isn't linked at all.
+pub fn build_time_libplist_version() -> Option<&'static str> {
+    match env!("LIBPLIST_LINKED_VERSION") {
+        "" => None,
+        version => Some(version),
+    }
+}
 
 /// The main struct for the plist library
-/// This struct contains a pointer to the C compatible structure
+/// This struct contains a pointer to the C compatible structure, or to a native node in
+/// pure-rust builds
 pub struct Plist {
-    pub(crate) plist_t: unsafe_bindings::plist_t,
+    pub(crate) plist_t: backend::Node,
     pub plist_type: PlistType,
     pub(crate) id: u32,
     pub(crate) false_drop: bool,
@@ -23,7 +74,7 @@ unsafe impl Send for Plist {}
 unsafe impl Sync for Plist {}
 
 /// The type of a given plist
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum PlistType {
     Boolean,
     Integer,
@@ -42,55 +93,73 @@ pub enum PlistType {
 impl Plist {
     /// Returns a pointer to the underlying C compatible structure
     /// This is compatible with libraries such as libimobiledevice
+    #[cfg(not(feature = "pure-rust"))]
     pub fn get_pointer(&self) -> *mut std::ffi::c_void {
         self.plist_t
     }
     /// This takes a string in the form of XML and returns a Plist struct
-    pub fn from_xml(xml: String) -> Result<Plist, PlistError> {
-        let xml = match CString::new(xml) {
-            Ok(s) => s,
-            Err(_) => {
-                warn!("Could not convert string to CString");
-                return Err(PlistError::InvalidArg);
+    /// Parse errors report the line and column of the problem where it can be found
+    /// When parsing fails, the text is parsed a second time by the native parser to find it,
+    /// so a failure costs about twice as much as a success.
+    pub fn from_xml(text: String) -> Result<Plist, PlistError> {
+        match Active::from_xml(&text) {
+            Ok(plist_t) => Ok(plist_t.into()),
+            Err(e) => {
+                warn!("Could not parse xml");
+                Err(locate_error(e, xml::from_str(&text)))
             }
-        };
-        let xml_len = std::convert::TryInto::try_into(xml.as_bytes().len()).unwrap();
-        let mut plist_t = unsafe { std::mem::zeroed() };
-        trace!("Parsing xml");
-        unsafe {
-            unsafe_bindings::plist_from_xml(xml.as_ptr() as *const c_char, xml_len, &mut plist_t)
-        };
-        Ok(plist_t.into())
+        }
+    }
+    /// Serializes the plist as JSON
+    /// Dates, data and UIDs can't be represented in JSON and are a format error
+    pub fn to_json(&self) -> Result<String, PlistError> {
+        Active::to_json(&self.plist_t)
+    }
+    /// Serializes the plist in the OpenStep format
+    /// Only strings, data, numbers, arrays and dictionaries can be represented
+    pub fn to_openstep(&self) -> Result<String, PlistError> {
+        Active::to_openstep(&self.plist_t)
+    }
+    /// This takes a string in the form of JSON and returns a Plist struct
+    /// Parse errors report the line and column of the problem where it can be found
+    /// Like `from_xml`, a failed parse is repeated by the native parser to find it
+    pub fn from_json(text: String) -> Result<Plist, PlistError> {
+        match Active::from_json(&text) {
+            Ok(plist_t) => Ok(plist_t.into()),
+            Err(e) => {
+                warn!("Could not parse json");
+                Err(locate_error(e, json::from_str(&text)))
+            }
+        }
+    }
+    /// This takes a string in the OpenStep format and returns a Plist struct
+    /// Parse errors report the line and column of the problem where it can be found
+    /// Like `from_xml`, a failed parse is repeated by the native parser to find it
+    pub fn from_openstep(text: String) -> Result<Plist, PlistError> {
+        match Active::from_openstep(&text) {
+            Ok(plist_t) => Ok(plist_t.into()),
+            Err(e) => {
+                warn!("Could not parse openstep");
+                Err(locate_error(e, openstep::from_str(&text)))
+            }
+        }
     }
     /// This takes a string in the form of binary and returns a Plist struct
     pub fn from_bin(bin: Vec<u8>) -> Result<Plist, PlistError> {
-        let mut plist_t = unsafe { std::mem::zeroed() };
-        let result = unsafe {
-            unsafe_bindings::plist_from_bin(
-                bin.as_ptr() as *const c_char,
-                bin.len() as u32,
-                &mut plist_t,
-            )
-        };
-        if result != 0 {
-            return Err(result.into());
-        }
-        Ok(plist_t.into())
+        Ok(Active::from_bin(&bin)?.into())
+    }
+    /// Like `from_memory`, but for input that can't be trusted
+    /// The input is parsed once by the native parsers, which stop as soon as it goes over the
+    /// limits, and the result is copied into a new plist. libplist never sees the input, so
+    /// strings holding a NUL byte end there, like they would if libplist had read them.
+    pub fn from_memory_with_limits(
+        bin: Vec<u8>,
+        limits: &ParseLimits,
+    ) -> Result<Plist, PlistError> {
+        Ok(limits::from_memory(&bin, limits)?.into())
     }
     pub fn from_memory(bin: Vec<u8>) -> Result<Plist, PlistError> {
-        let mut plist_t = unsafe { std::mem::zeroed() };
-        let result = unsafe {
-            unsafe_bindings::plist_from_memory(
-                bin.as_ptr() as *const c_char,
-                bin.len() as u32,
-                &mut plist_t,
-                std::ptr::null_mut(),
-            )
-        };
-        if result != 0 {
-            return Err(result.into());
-        }
-        Ok(plist_t.into())
+        Ok(Active::from_memory(&bin)?.into())
     }
     /// This will back the plist to the plist it came from
     /// This is unsafe due to how the underlying C library works
@@ -99,28 +168,20 @@ impl Plist {
     /// Don't be stupid
     pub unsafe fn get_parent(self) -> Plist {
         trace!("Getting parent");
-        unsafe_bindings::plist_get_parent(self.plist_t).into()
+        Active::parent(&self.plist_t).into()
     }
     /// Gets the type of the plist from the C library
     pub fn get_node_type(&self) -> PlistType {
         trace!("Getting node type");
-        unsafe { unsafe_bindings::plist_get_node_type(self.plist_t) }.into() // puts on sunglasses
+        Active::node_type(&self.plist_t) // puts on sunglasses
     }
-    /// Queries if the plist has a binary structure
+    /// Queries if the plist is data holding a binary plist
     pub fn is_binary(&self) -> bool {
-        let plist_data = unsafe { std::mem::zeroed() };
-        let plist_len = unsafe { std::mem::zeroed() };
-        trace!("Getting plist data");
-        unsafe {
-            unsafe_bindings::plist_get_data_val(self.plist_t, plist_data, plist_len);
-        }
         trace!("Checking if plist is binary");
-        !matches!(
-            unsafe {
-                unsafe_bindings::plist_is_binary(*plist_data, (*plist_len).try_into().unwrap())
-            },
-            0
-        )
+        match Active::get_data(&self.plist_t) {
+            Ok(data) => Active::is_binary(&data),
+            Err(_) => false,
+        }
     }
     /// Traverses a list of plists
     /// Reimplimented from the C function because function overloading is evil
@@ -161,14 +222,9 @@ impl Plist {
     }
 
     /// Compares two structs and determines if they are equal
-    pub fn compare_node_values(node_l: Plist, node_r: Plist) -> bool {
-        trace!("Comparing node values");
-        matches!(
-            unsafe { unsafe_bindings::plist_compare_node_value(node_l.plist_t, node_r.plist_t) }
-                .to_string()
-                .as_str(),
-            "TRUE"
-        )
+    /// Containers are compared recursively, see the `PartialEq` implementation
+    pub fn compare_node_values(node_l: &Plist, node_r: &Plist) -> bool {
+        node_l == node_r
     }
 
     pub fn get_display_value(&self) -> Result<String, PlistError> {
@@ -178,7 +234,7 @@ impl Plist {
                 to_return = self.get_bool_val()?.to_string();
             }
             PlistType::Integer => {
-                to_return = self.get_uint_val()?.to_string();
+                to_return = self.get_int_val()?.to_string();
             }
             PlistType::Real => {
                 to_return = self.get_real_val()?.to_string();
@@ -237,14 +293,23 @@ impl Plist {
     }
 }
 
-impl From<unsafe_bindings::plist_t> for Plist {
-    fn from(plist_t: unsafe_bindings::plist_t) -> Self {
+/// libplist only reports that parsing failed, so the native parser is used to find where
+/// If it can't point at the problem either, the original error is kept
+fn locate_error(error: PlistError, native: Result<PlistValue, PlistError>) -> PlistError {
+    match native {
+        Err(located @ PlistError::Parse { line: 1.., .. }) => located,
+        _ => error,
+    }
+}
+
+impl From<backend::Node> for Plist {
+    fn from(plist_t: backend::Node) -> Self {
         let mut rng = rand::thread_rng();
         let id = rng.gen::<u32>();
         trace!("Creating plist from plist_t with id {}", id);
         Plist {
             plist_t,
-            plist_type: unsafe { unsafe_bindings::plist_get_node_type(plist_t) }.into(),
+            plist_type: Active::node_type(&plist_t),
             id,
             false_drop: false,
         }
@@ -253,63 +318,27 @@ impl From<unsafe_bindings::plist_t> for Plist {
 
 impl From<Plist> for String {
     fn from(plist: Plist) -> Self {
-        let plist_t = plist.plist_t;
-        let mut plist_data = std::ptr::null_mut();
-        let mut plist_size = 0;
-        trace!("Converting plist to XML data");
-        unsafe {
-            unsafe_bindings::plist_to_xml(plist_t, &mut plist_data, &mut plist_size);
-        }
-        trace!("Assembling XML data");
-        let plist_data = unsafe {
-            std::slice::from_raw_parts(plist_data as *const u8, plist_size.try_into().unwrap())
-        };
-        let plist_data = std::str::from_utf8(plist_data).unwrap();
-
-        String::from(plist_data)
+        plist.to_string()
     }
 }
 
-impl ToString for Plist {
-    fn to_string(&self) -> String {
-        let mut plist_data = std::ptr::null_mut();
-        let mut plist_size = 0;
-        trace!("Converting plist to XML data");
-        unsafe {
-            unsafe_bindings::plist_to_xml(self.plist_t, &mut plist_data, &mut plist_size);
-        }
-        trace!("Assembling XML data");
-
-        let plist_data =
-            unsafe { std::slice::from_raw_parts(plist_data as *mut u8, plist_size as usize) };
-        let plist_data = std::str::from_utf8(plist_data).unwrap();
-
-        plist_data.to_string()
+/// Formats the plist as XML
+impl std::fmt::Display for Plist {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&Active::to_xml(&self.plist_t).unwrap())
     }
 }
 
 impl From<Plist> for Vec<u8> {
     fn from(plist: Plist) -> Self {
-        let plist_t = plist.plist_t;
-        let mut plist_data = std::ptr::null_mut();
-        let mut plist_size = 0;
-        trace!("Converting plist to binary data");
-        unsafe {
-            unsafe_bindings::plist_to_bin(plist_t, &mut plist_data, &mut plist_size);
-        }
-        trace!("Assembling binary data");
-        let plist_data = unsafe {
-            std::slice::from_raw_parts(plist_data as *const u8, plist_size.try_into().unwrap())
-        };
-
-        plist_data.to_vec()
+        Active::to_bin(&plist.plist_t).unwrap()
     }
 }
 
 impl Clone for Plist {
     fn clone(&self) -> Self {
         trace!("Cloning plist");
-        let plist_t = unsafe { unsafe_bindings::plist_copy(self.plist_t) };
+        let plist_t = Active::copy(&self.plist_t);
         trace!("Getting type of cloned plist");
         plist_t.into()
     }
@@ -326,7 +355,7 @@ impl Drop for Plist {
     fn drop(&mut self) {
         if !self.false_drop {
             trace!("Dropping plist {}", self.id);
-            unsafe { unsafe_bindings::plist_free(self.plist_t) }
+            Active::free(self.plist_t);
             trace!("Plist dropped");
         }
     }
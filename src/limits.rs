@@ -0,0 +1,318 @@
+// jkcoxson
+
+use std::fmt::{Display, Formatter};
+
+use log::{trace, warn};
+
+use crate::{error::PlistError, value::PlistValue};
+
+/// Limits on what the parsers will accept
+/// Use these when parsing plists from devices or peers that can't be trusted,
+/// so deeply nested or hugely expanding payloads are rejected instead of exhausting memory
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct ParseLimits {
+    /// How deep arrays and dictionaries may nest
+    pub max_depth: usize,
+    /// How many values the parsed tree may contain
+    /// An object shared in a binary plist counts once for every place it appears
+    pub max_nodes: usize,
+    /// The longest string or key, in bytes
+    pub max_string_len: usize,
+    /// The longest data value, in bytes
+    pub max_data_len: usize,
+    /// The largest input that will be parsed at all, in bytes
+    pub max_total_bytes: usize,
+}
+
+impl Default for ParseLimits {
+    /// Generous limits that no real plist gets near
+    fn default() -> Self {
+        ParseLimits {
+            max_depth: 512,
+            max_nodes: 1 << 20,
+            max_string_len: 16 << 20,
+            max_data_len: 64 << 20,
+            max_total_bytes: 128 << 20,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// Limits that never get in the way
+    pub fn unlimited() -> Self {
+        ParseLimits {
+            max_depth: usize::MAX,
+            max_nodes: usize::MAX,
+            max_string_len: usize::MAX,
+            max_data_len: usize::MAX,
+            max_total_bytes: usize::MAX,
+        }
+    }
+
+    /// The default nesting depth and nothing else, for the parsers without `_with_limits`
+    /// The parsers recurse, so only the depth is kept to stop them overflowing the stack
+    pub(crate) fn depth_only() -> Self {
+        ParseLimits {
+            max_depth: ParseLimits::default().max_depth,
+            ..ParseLimits::unlimited()
+        }
+    }
+}
+
+/// The limit that was exceeded
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Limit {
+    Depth,
+    Nodes,
+    StringLen,
+    DataLen,
+    TotalBytes,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Limit::Depth => "nesting depth",
+                Limit::Nodes => "number of values",
+                Limit::StringLen => "string length",
+                Limit::DataLen => "data length",
+                Limit::TotalBytes => "input size",
+            }
+        )
+    }
+}
+
+fn exceeded(limit: Limit, max: usize) -> PlistError {
+    warn!("The {} is over the limit of {}", limit, max);
+    PlistError::LimitExceeded { limit, max }
+}
+
+/// Tracks how much of the limits a parser has used up
+pub(crate) struct Budget {
+    limits: ParseLimits,
+    depth: usize,
+    nodes: usize,
+}
+
+impl Budget {
+    pub(crate) fn new(limits: &ParseLimits) -> Self {
+        Budget {
+            limits: *limits,
+            depth: 0,
+            nodes: 0,
+        }
+    }
+
+    pub(crate) fn total_bytes(&self, len: usize) -> Result<(), PlistError> {
+        if len > self.limits.max_total_bytes {
+            return Err(exceeded(Limit::TotalBytes, self.limits.max_total_bytes));
+        }
+        Ok(())
+    }
+
+    /// Checks up front that a number of values fits, such as the object count of a binary plist
+    pub(crate) fn nodes(&self, count: u64) -> Result<(), PlistError> {
+        if count > self.limits.max_nodes as u64 {
+            return Err(exceeded(Limit::Nodes, self.limits.max_nodes));
+        }
+        Ok(())
+    }
+
+    /// Counts a value of any type
+    pub(crate) fn node(&mut self) -> Result<(), PlistError> {
+        self.nodes += 1;
+        if self.nodes > self.limits.max_nodes {
+            return Err(exceeded(Limit::Nodes, self.limits.max_nodes));
+        }
+        Ok(())
+    }
+
+    /// Called when the parser steps into a container
+    pub(crate) fn enter(&mut self) -> Result<(), PlistError> {
+        self.depth += 1;
+        if self.depth > self.limits.max_depth {
+            return Err(exceeded(Limit::Depth, self.limits.max_depth));
+        }
+        Ok(())
+    }
+
+    /// Called when the parser steps out of a container
+    pub(crate) fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    pub(crate) fn string(&self, len: usize) -> Result<(), PlistError> {
+        if len > self.limits.max_string_len {
+            return Err(exceeded(Limit::StringLen, self.limits.max_string_len));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn data(&self, len: usize) -> Result<(), PlistError> {
+        if len > self.limits.max_data_len {
+            return Err(exceeded(Limit::DataLen, self.limits.max_data_len));
+        }
+        Ok(())
+    }
+}
+
+/// Parses a plist of any format with the native parsers, the way libplist's
+/// `plist_from_memory` would detect it
+pub(crate) fn from_memory(bin: &[u8], limits: &ParseLimits) -> Result<PlistValue, PlistError> {
+    trace!("Checking plist against limits");
+    Budget::new(limits).total_bytes(bin.len())?;
+    if bin.starts_with(crate::bplist::MAGIC) {
+        return crate::bplist::from_bytes_with_limits(bin, limits);
+    }
+    let bin = bin.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bin);
+    let text = match std::str::from_utf8(bin) {
+        Ok(text) => text,
+        Err(_) => {
+            warn!("Plist is neither binary nor UTF-8 text");
+            return Err(PlistError::parse("Plist is neither binary nor UTF-8 text"));
+        }
+    };
+    let trimmed = text.trim_start();
+    if trimmed.starts_with('<') {
+        crate::xml::from_str_with_limits(text, limits)
+    } else if trimmed.starts_with('[') || is_json_object(trimmed) {
+        crate::json::from_str_with_limits(text, limits)
+    } else {
+        crate::openstep::from_str_with_limits(text, limits)
+    }
+}
+
+/// Tells a JSON object from an OpenStep dictionary with quoted keys
+/// by whether the first key is followed by `:` or `=`
+fn is_json_object(text: &str) -> bool {
+    let rest = match text.strip_prefix('{') {
+        Some(rest) => rest.trim_start(),
+        None => return false,
+    };
+    let key = match rest.strip_prefix('"') {
+        Some(key) => key,
+        None => return rest.starts_with('}'),
+    };
+    let mut escaped = false;
+    for (i, c) in key.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return key[i + 1..].trim_start().starts_with(':'),
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_memory_limits_test() {
+        let limits = ParseLimits {
+            max_depth: 2,
+            ..Default::default()
+        };
+        assert!(from_memory(b"[[1]]", &limits).is_ok());
+        assert!(matches!(
+            from_memory(b"[[[1]]]", &limits),
+            Err(PlistError::LimitExceeded {
+                limit: Limit::Depth,
+                max: 2
+            })
+        ));
+        assert!(matches!(
+            from_memory(b"{ a = (b, (c)); }", &limits),
+            Err(PlistError::LimitExceeded {
+                limit: Limit::Depth,
+                ..
+            })
+        ));
+        assert!(from_memory(b"{\"a\": 1}", &limits).is_ok());
+        assert_eq!(
+            from_memory(b"{ \"a\\\"\" = \"b\"; }", &limits).unwrap(),
+            PlistValue::Dictionary(vec![(
+                "a\"".to_string(),
+                PlistValue::String("b".to_string())
+            )])
+        );
+
+        let limits = ParseLimits {
+            max_total_bytes: 4,
+            ..Default::default()
+        };
+        assert!(matches!(
+            from_memory(b"[1, 2]", &limits),
+            Err(PlistError::LimitExceeded {
+                limit: Limit::TotalBytes,
+                ..
+            })
+        ));
+        assert!(from_memory(b"\xff\xfe", &ParseLimits::unlimited()).is_err());
+
+        let limits = ParseLimits {
+            max_string_len: 3,
+            max_data_len: 3,
+            ..Default::default()
+        };
+        assert!(matches!(
+            from_memory(b"<plist><string>abcd</string></plist>", &limits),
+            Err(PlistError::LimitExceeded {
+                limit: Limit::StringLen,
+                ..
+            })
+        ));
+        assert!(matches!(
+            from_memory(b"(<00010203>)", &limits),
+            Err(PlistError::LimitExceeded {
+                limit: Limit::DataLen,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn plist_from_memory_with_limits_test() {
+        let plist = crate::Plist::from_memory_with_limits(
+            b"{ \"a\" = \"b\\U0000c\"; }".to_vec(),
+            &ParseLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            plist.dict_get_item("a").unwrap().get_string_val().unwrap(),
+            "b"
+        );
+        assert!(crate::Plist::from_memory_with_limits(
+            b"[[[1]]]".to_vec(),
+            &ParseLimits {
+                max_depth: 2,
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn unlimited_entry_points_test() {
+        // Every item of a deduplicated array is a read of the same object, so this is over max_nodes
+        let items = ParseLimits::default().max_nodes + 1;
+        let bin = crate::bplist::BinaryWriter::new()
+            .write(&PlistValue::Array(vec![PlistValue::Boolean(true); items]))
+            .unwrap();
+        assert!(crate::bplist::from_bytes_with_limits(&bin, &ParseLimits::default()).is_err());
+        assert!(crate::bplist::from_bytes(&bin).is_ok());
+        assert_eq!(
+            crate::Plist::from_bin(bin.clone())
+                .unwrap()
+                .array_get_size()
+                .unwrap(),
+            items as u32
+        );
+        assert!(crate::Plist::from_memory(bin).is_ok());
+    }
+}
@@ -0,0 +1,267 @@
+// jkcoxson
+
+use std::collections::HashSet;
+
+use log::{trace, warn};
+
+use crate::error::PlistError;
+use crate::path::{KeyPath, PathSegment};
+use crate::{Plist, PlistType};
+
+/// How arrays present on both sides of a merge are combined
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ArrayMergeStrategy {
+    /// The array from the other plist replaces the existing one
+    #[default]
+    Replace,
+    /// The items from the other plist are appended to the existing array
+    Append,
+    /// Only the items not already present in the existing array are appended
+    Union,
+}
+
+/// What happens when the same key holds values of different types
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum TypeConflictStrategy {
+    /// The merge fails and the plist is left untouched
+    Error,
+    /// The existing value is kept
+    PreferLeft,
+    /// The value from the other plist is used
+    #[default]
+    PreferRight,
+}
+
+/// Options for `Plist::deep_merge`
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct MergeStrategy {
+    pub arrays: ArrayMergeStrategy,
+    pub type_conflicts: TypeConflictStrategy,
+}
+
+/// The outcome of a merge
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct MergeReport {
+    /// Paths whose existing value was replaced by a different one
+    pub overwritten: Vec<KeyPath>,
+    /// Paths where the values had different types
+    pub type_conflicts: Vec<KeyPath>,
+    /// Paths of arrays that items were appended to by `Append` or `Union`
+    pub appended: Vec<KeyPath>,
+}
+
+impl Plist {
+    /// Recursively merges a dictionary into the current dictionary
+    /// Unlike `dict_merge`, nested dictionaries are merged instead of replaced
+    ///
+    /// # Example
+    /// ```rust
+    /// use plist_plus::{MergeStrategy, Plist};
+    ///
+    /// let mut defaults = Plist::new_dict();
+    /// let mut network = Plist::new_dict();
+    /// network.dict_set_item("Timeout", 30_u64.into()).unwrap();
+    /// network.dict_set_item("Retries", 3_u64.into()).unwrap();
+    /// defaults.dict_set_item("Network", network).unwrap();
+    ///
+    /// let mut user = Plist::new_dict();
+    /// let mut network = Plist::new_dict();
+    /// network.dict_set_item("Timeout", 60_u64.into()).unwrap();
+    /// user.dict_set_item("Network", network).unwrap();
+    ///
+    /// let report = defaults.deep_merge(&user, MergeStrategy::default()).unwrap();
+    /// assert_eq!(report.overwritten[0].to_string(), "/Network/Timeout");
+    /// ```
+    pub fn deep_merge(
+        &mut self,
+        other: &Plist,
+        strategy: MergeStrategy,
+    ) -> Result<MergeReport, PlistError> {
+        if self.plist_type != PlistType::Dictionary || other.plist_type != PlistType::Dictionary {
+            return Err(PlistError::InvalidArg);
+        }
+        trace!("Deep merging dict");
+        // Merge into a copy so a conflict doesn't leave a partial result
+        let mut scratch = self.clone();
+        let mut report = MergeReport::default();
+        merge_dict(&KeyPath::root(), &mut scratch, other, strategy, &mut report)?;
+        self.replace_with(scratch)?;
+        Ok(report)
+    }
+}
+
+fn merge_dict(
+    path: &KeyPath,
+    target: &mut Plist,
+    source: &Plist,
+    strategy: MergeStrategy,
+    report: &mut MergeReport,
+) -> Result<(), PlistError> {
+    for key in source.dict_get_keys()? {
+        let child = path.join(PathSegment::Key(key.clone()));
+        let incoming = source.dict_get_item(&key)?;
+        let mut existing = target.dict_get_item(&key)?;
+        if existing.plist_t.is_null() {
+            target.dict_set_item(&key, incoming.clone())?;
+            continue;
+        }
+        if existing.plist_type != incoming.plist_type {
+            report.type_conflicts.push(child.clone());
+            match strategy.type_conflicts {
+                TypeConflictStrategy::Error => {
+                    warn!("Type conflict at {}", child);
+                    return Err(PlistError::InvalidArg);
+                }
+                TypeConflictStrategy::PreferLeft => {}
+                TypeConflictStrategy::PreferRight => {
+                    target.dict_set_item(&key, incoming.clone())?;
+                    report.overwritten.push(child);
+                }
+            }
+            continue;
+        }
+        match existing.plist_type {
+            PlistType::Dictionary => {
+                merge_dict(&child, &mut existing, &incoming, strategy, report)?
+            }
+            PlistType::Array => match strategy.arrays {
+                ArrayMergeStrategy::Replace => {
+                    if existing != incoming {
+                        target.dict_set_item(&key, incoming.clone())?;
+                        report.overwritten.push(child);
+                    }
+                }
+                ArrayMergeStrategy::Append => {
+                    let items = incoming.array_items();
+                    for item in &items {
+                        existing.array_append_item(item.clone())?;
+                    }
+                    if !items.is_empty() {
+                        report.appended.push(child);
+                    }
+                }
+                ArrayMergeStrategy::Union => {
+                    let mut appended = false;
+                    let mut seen: HashSet<Plist> = existing.array_items().into_iter().collect();
+                    for item in incoming.array_items() {
+                        if !seen.contains(&item) {
+                            existing.array_append_item(item.clone())?;
+                            seen.insert(item);
+                            appended = true;
+                        }
+                    }
+                    if appended {
+                        report.appended.push(child);
+                    }
+                }
+            },
+            _ => {
+                if existing != incoming {
+                    target.dict_set_item(&key, incoming.clone())?;
+                    report.overwritten.push(child);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(tags: &[&str], level: Plist) -> Plist {
+        let mut array = Plist::new_array();
+        for tag in tags {
+            array.array_append_item((*tag).into()).unwrap();
+        }
+        let mut p = Plist::new_dict();
+        p.dict_set_item("Tags", array).unwrap();
+        p.dict_set_item("Level", level).unwrap();
+        p
+    }
+
+    #[test]
+    fn merge_union_test() {
+        let mut left = profile(&["a", "b"], 1_u64.into());
+        let right = profile(&["b", "c"], 2_u64.into());
+        let report = left
+            .deep_merge(
+                &right,
+                MergeStrategy {
+                    arrays: ArrayMergeStrategy::Union,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            left.dict_get_item("Tags")
+                .unwrap()
+                .array_get_size()
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            left.dict_get_item("Level").unwrap().get_uint_val().unwrap(),
+            2
+        );
+        assert_eq!(report.overwritten, vec!["/Level".parse().unwrap()]);
+        assert_eq!(report.appended, vec!["/Tags".parse().unwrap()]);
+
+        // Nothing new to add, so nothing is reported
+        let report = left
+            .deep_merge(
+                &right,
+                MergeStrategy {
+                    arrays: ArrayMergeStrategy::Union,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(report.appended.is_empty());
+    }
+
+    #[test]
+    fn merge_append_test() {
+        let mut left = profile(&["a"], 1_u64.into());
+        let right = profile(&["a"], 1_u64.into());
+        let report = left
+            .deep_merge(
+                &right,
+                MergeStrategy {
+                    arrays: ArrayMergeStrategy::Append,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            left.dict_get_item("Tags")
+                .unwrap()
+                .array_get_size()
+                .unwrap(),
+            2
+        );
+        assert_eq!(report.appended, vec!["/Tags".parse().unwrap()]);
+        assert!(report.overwritten.is_empty());
+    }
+
+    #[test]
+    fn merge_conflict_test() {
+        let mut left = profile(&["a"], 1_u64.into());
+        let right = profile(&["a"], "high".into());
+        let strategy = MergeStrategy {
+            type_conflicts: TypeConflictStrategy::Error,
+            ..Default::default()
+        };
+        assert!(left.deep_merge(&right, strategy).is_err());
+        assert_eq!(left, profile(&["a"], 1_u64.into()));
+
+        let strategy = MergeStrategy {
+            type_conflicts: TypeConflictStrategy::PreferLeft,
+            ..Default::default()
+        };
+        let report = left.deep_merge(&right, strategy).unwrap();
+        assert_eq!(report.type_conflicts.len(), 1);
+        assert!(report.overwritten.is_empty());
+    }
+}
@@ -0,0 +1,467 @@
+// jkcoxson
+
+use log::{trace, warn};
+
+use crate::error::PlistError;
+use crate::limits::{Budget, ParseLimits};
+use crate::value::PlistValue;
+
+/// Parses an OpenStep ("old-style") plist into a value without using the C library
+/// The format has no numbers, booleans or dates, so every scalar is read as a string
+/// Only the nesting depth is limited, so use `from_str_with_limits` for input that can't be trusted
+pub fn from_str(openstep: &str) -> Result<PlistValue, PlistError> {
+    from_str_with_limits(openstep, &ParseLimits::depth_only())
+}
+
+/// Parses an OpenStep plist, rejecting it as soon as it goes over the limits
+pub fn from_str_with_limits(
+    openstep: &str,
+    limits: &ParseLimits,
+) -> Result<PlistValue, PlistError> {
+    trace!("Parsing OpenStep plist natively");
+    let mut reader = Reader::new(openstep, limits)?;
+    reader.skip_whitespace()?;
+    let value = reader.read_value()?;
+    reader.skip_whitespace()?;
+    if reader.pos < openstep.len() {
+        return Err(reader.error("Unexpected content after the root value"));
+    }
+    Ok(value)
+}
+
+/// Characters that may appear in a string without quotes
+fn is_unquoted(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '+' | '/' | ':' | '.' | '-')
+}
+
+/// Also used by the .strings reader, which shares the syntax of strings and comments
+pub(crate) struct Reader<'a> {
+    text: &'a str,
+    pub(crate) pos: usize,
+    budget: Budget,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(text: &'a str, limits: &ParseLimits) -> Result<Self, PlistError> {
+        let budget = Budget::new(limits);
+        budget.total_bytes(text.len())?;
+        Ok(Reader {
+            text,
+            pos: 0,
+            budget,
+        })
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    pub(crate) fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    pub(crate) fn error(&self, message: &str) -> PlistError {
+        self.error_at(self.pos, message)
+    }
+
+    fn error_at(&self, pos: usize, message: &str) -> PlistError {
+        warn!("{} at byte {}", message, pos);
+        PlistError::parse_at(self.text, pos, message)
+    }
+
+    /// Skips whitespace and both kinds of comments
+    pub(crate) fn skip_whitespace(&mut self) -> Result<(), PlistError> {
+        loop {
+            let trimmed = self.rest().trim_start();
+            self.pos = self.text.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                match trimmed.find("*/") {
+                    Some(end) => self.pos += end + 2,
+                    None => return Err(self.error("Unterminated comment")),
+                }
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    pub(crate) fn expect(&mut self, c: char) -> Result<(), PlistError> {
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("Expected '{}'", c)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn read_value(&mut self) -> Result<PlistValue, PlistError> {
+        self.budget.node()?;
+        match self.peek() {
+            Some('{') => {
+                self.budget.enter()?;
+                let dict = self.read_dict()?;
+                self.budget.leave();
+                Ok(dict)
+            }
+            Some('(') => {
+                self.budget.enter()?;
+                let array = self.read_array()?;
+                self.budget.leave();
+                Ok(array)
+            }
+            Some('<') => self.read_data(),
+            Some(_) => Ok(PlistValue::String(self.read_string()?)),
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    fn read_dict(&mut self) -> Result<PlistValue, PlistError> {
+        self.expect('{')?;
+        let mut entries: Vec<(String, PlistValue)> = Vec::new();
+        loop {
+            self.skip_whitespace()?;
+            if self.peek() == Some('}') {
+                self.pos += 1;
+                return Ok(PlistValue::Dictionary(entries));
+            }
+            let key = self.read_string()?;
+            self.skip_whitespace()?;
+            self.expect('=')?;
+            self.skip_whitespace()?;
+            let value = self.read_value()?;
+            self.skip_whitespace()?;
+            self.expect(';')?;
+            // Later entries replace earlier ones, like they would in a dictionary
+            match entries.iter_mut().find(|(k, _)| *k == key) {
+                Some(entry) => entry.1 = value,
+                None => entries.push((key, value)),
+            }
+        }
+    }
+
+    fn read_array(&mut self) -> Result<PlistValue, PlistError> {
+        self.expect('(')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace()?;
+            if self.peek() == Some(')') {
+                self.pos += 1;
+                return Ok(PlistValue::Array(items));
+            }
+            items.push(self.read_value()?);
+            self.skip_whitespace()?;
+            match self.peek() {
+                // A trailing comma before the closing parenthesis is allowed
+                Some(',') => self.pos += 1,
+                Some(')') => {}
+                _ => return Err(self.error("Expected ',' or ')'")),
+            }
+        }
+    }
+
+    fn read_data(&mut self) -> Result<PlistValue, PlistError> {
+        let start = self.pos;
+        self.expect('<')?;
+        let end = match self.rest().find('>') {
+            Some(end) => end,
+            None => return Err(self.error_at(start, "Unterminated data")),
+        };
+        let mut data = Vec::new();
+        let mut high: Option<u8> = None;
+        for (i, c) in self.rest()[..end].char_indices() {
+            if c.is_whitespace() {
+                continue;
+            }
+            let nibble = match c.to_digit(16) {
+                Some(nibble) => nibble as u8,
+                None => return Err(self.error_at(self.pos + i, "Invalid hex digit in data")),
+            };
+            match high.take() {
+                Some(high) => data.push(high << 4 | nibble),
+                None => high = Some(nibble),
+            }
+        }
+        if high.is_some() {
+            return Err(self.error_at(start, "Data has an odd number of hex digits"));
+        }
+        self.budget.data(data.len())?;
+        self.pos += end + 1;
+        Ok(PlistValue::Data(data))
+    }
+
+    pub(crate) fn read_string(&mut self) -> Result<String, PlistError> {
+        match self.peek() {
+            Some(quote @ ('"' | '\'')) => self.read_quoted(quote),
+            _ => {
+                let len = self
+                    .rest()
+                    .find(|c| !is_unquoted(c))
+                    .unwrap_or(self.rest().len());
+                if len == 0 {
+                    return Err(self.error("Expected a value"));
+                }
+                self.budget.string(len)?;
+                let string = self.rest()[..len].to_string();
+                self.pos += len;
+                Ok(string)
+            }
+        }
+    }
+
+    fn read_quoted(&mut self, quote: char) -> Result<String, PlistError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut string = String::new();
+        loop {
+            let rest = self.rest();
+            let next = match rest.find([quote, '\\']) {
+                Some(next) => next,
+                None => return Err(self.error_at(start, "Unterminated string")),
+            };
+            string.push_str(&rest[..next]);
+            self.budget.string(string.len())?;
+            self.pos += next + 1;
+            if rest[next..].starts_with(quote) {
+                return Ok(string);
+            }
+            let escape = self.pos - 1;
+            let c = match self.peek() {
+                Some('a') => '\u{7}',
+                Some('b') => '\u{8}',
+                Some('f') => '\u{c}',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('v') => '\u{b}',
+                Some('U') => {
+                    self.pos += 1;
+                    let hex = self.rest().get(..4).unwrap_or("");
+                    let code = match u32::from_str_radix(hex, 16) {
+                        Ok(code) if hex.bytes().all(|b| b.is_ascii_hexdigit()) => code,
+                        _ => return Err(self.error_at(escape, "Expected 4 hex digits")),
+                    };
+                    self.pos += 4;
+                    string.push(
+                        char::from_u32(code)
+                            .ok_or_else(|| self.error_at(escape, "Invalid unicode escape"))?,
+                    );
+                    continue;
+                }
+                Some('0'..='7') => {
+                    let len = self
+                        .rest()
+                        .bytes()
+                        .take(3)
+                        .take_while(|b| (b'0'..=b'7').contains(b))
+                        .count();
+                    let code = u32::from_str_radix(&self.rest()[..len], 8).unwrap();
+                    self.pos += len;
+                    string.push(
+                        char::from_u32(code)
+                            .ok_or_else(|| self.error_at(escape, "Invalid octal escape"))?,
+                    );
+                    continue;
+                }
+                Some(c) => c,
+                None => return Err(self.error_at(start, "Unterminated string")),
+            };
+            string.push(c);
+            self.pos += c.len_utf8();
+        }
+    }
+}
+
+/// Serializes a value as an OpenStep plist without using the C library
+/// Numbers are written as plain text, so they are read back as strings
+/// Booleans, dates, UIDs and nulls have no representation and are a format error
+pub fn to_string(value: &PlistValue) -> Result<String, PlistError> {
+    trace!("Writing OpenStep plist natively");
+    let mut out = String::new();
+    write_node(&mut out, value, 0)?;
+    Ok(out)
+}
+
+fn indent(out: &mut String, depth: usize) {
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+}
+
+/// Strings are only quoted when they have to be
+fn push_string(out: &mut String, text: &str) {
+    if !text.is_empty() && text.chars().all(is_unquoted) {
+        out.push_str(text);
+        return;
+    }
+    push_quoted(out, text);
+}
+
+pub(crate) fn push_quoted(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\{:03o}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_node(out: &mut String, value: &PlistValue, depth: usize) -> Result<(), PlistError> {
+    match value {
+        PlistValue::Integer(i) => out.push_str(&i.to_string()),
+        PlistValue::Real(r) if r.is_finite() => out.push_str(&crate::xml::format_real(*r)),
+        PlistValue::String(s) => push_string(out, s),
+        PlistValue::Data(data) => {
+            out.push('<');
+            for byte in data {
+                out.push_str(&format!("{:02x}", byte));
+            }
+            out.push('>');
+        }
+        PlistValue::Array(items) if items.is_empty() => out.push_str("()"),
+        PlistValue::Array(items) => {
+            out.push_str("(\n");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(",\n");
+                }
+                indent(out, depth + 1);
+                write_node(out, item, depth + 1)?;
+            }
+            out.push('\n');
+            indent(out, depth);
+            out.push(')');
+        }
+        PlistValue::Dictionary(entries) if entries.is_empty() => out.push_str("{}"),
+        PlistValue::Dictionary(entries) => {
+            out.push_str("{\n");
+            for (key, item) in entries {
+                indent(out, depth + 1);
+                push_string(out, key);
+                out.push_str(" = ");
+                write_node(out, item, depth + 1)?;
+                out.push_str(";\n");
+            }
+            indent(out, depth);
+            out.push('}');
+        }
+        value => {
+            warn!(
+                "{:?} values can't be written to OpenStep",
+                value.plist_type()
+            );
+            return Err(PlistError::Format);
+        }
+    }
+    Ok(())
+}
+
+impl PlistValue {
+    /// Parses an OpenStep plist without using the C library
+    /// This is the native alternative to `Plist::from_openstep`
+    pub fn from_openstep(openstep: &str) -> Result<PlistValue, PlistError> {
+        from_str(openstep)
+    }
+    /// Serializes the value as an OpenStep plist without using the C library
+    pub fn to_openstep(&self) -> Result<String, PlistError> {
+        to_string(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_openstep_test() {
+        let value = from_str(
+            r#"// A comment
+            {
+                Name = "a \"b\"\n\U00e9";
+                /* Another comment */
+                Version = 1.0;
+                List = (one, 'two', <0001 ff>,);
+                Empty = {};
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            PlistValue::Dictionary(vec![
+                (
+                    "Name".to_string(),
+                    PlistValue::String("a \"b\"\né".to_string())
+                ),
+                ("Version".to_string(), PlistValue::String("1.0".to_string())),
+                (
+                    "List".to_string(),
+                    PlistValue::Array(vec![
+                        PlistValue::String("one".to_string()),
+                        PlistValue::String("two".to_string()),
+                        PlistValue::Data(vec![0x00, 0x01, 0xff]),
+                    ])
+                ),
+                ("Empty".to_string(), PlistValue::Dictionary(vec![])),
+            ])
+        );
+    }
+
+    #[test]
+    fn read_openstep_malformed_test() {
+        let error = from_str("{\n  a = 1;\n  b = 2\n}").unwrap_err();
+        match error {
+            PlistError::Parse {
+                line,
+                column,
+                snippet,
+                ..
+            } => {
+                assert_eq!((line, column), (4, 1));
+                assert_eq!(snippet, "}");
+            }
+            e => panic!("Unexpected error {:?}", e),
+        }
+        assert!(from_str("(a, , b)").is_err());
+        assert!(from_str("<0g>").is_err());
+        assert!(from_str("<012>").is_err());
+        assert!(from_str("\"abc").is_err());
+        assert!(from_str("{a = b;} c").is_err());
+    }
+
+    #[test]
+    fn write_openstep_test() {
+        let value = PlistValue::Dictionary(vec![
+            (
+                "Name".to_string(),
+                PlistValue::String("a b\u{1}".to_string()),
+            ),
+            (
+                "List".to_string(),
+                PlistValue::Array(vec![
+                    PlistValue::String("one".to_string()),
+                    PlistValue::String(String::new()),
+                    PlistValue::Data(vec![0, 0xff]),
+                ]),
+            ),
+            ("Empty".to_string(), PlistValue::Dictionary(vec![])),
+        ]);
+        let openstep = to_string(&value).unwrap();
+        assert_eq!(
+            openstep,
+            "{\n  Name = \"a b\\001\";\n  List = (\n    one,\n    \"\",\n    <00ff>\n  );\n  Empty = {};\n}"
+        );
+        assert_eq!(from_str(&openstep).unwrap(), value);
+        assert_eq!(
+            to_string(&PlistValue::Integer(-3)).unwrap(),
+            "-3".to_string()
+        );
+        assert!(to_string(&PlistValue::Boolean(true)).is_err());
+    }
+}
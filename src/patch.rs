@@ -0,0 +1,430 @@
+// jkcoxson
+
+use log::{trace, warn};
+
+use crate::backend::{Active, Backend};
+use crate::error::PlistError;
+use crate::path::{KeyPath, PathSegment};
+use crate::{Plist, PlistType};
+
+/// A single operation of a patch, as described by RFC 6902 (JSON Patch)
+#[derive(PartialEq, Debug, Clone)]
+pub enum PatchOperation {
+    /// Inserts a value into an array or sets a dictionary entry
+    Add { path: KeyPath, value: Plist },
+    /// Removes an existing value
+    Remove { path: KeyPath },
+    /// Replaces an existing value
+    Replace { path: KeyPath, value: Plist },
+    /// Removes a value and adds it at another path
+    Move { from: KeyPath, path: KeyPath },
+    /// Adds a copy of a value at another path
+    Copy { from: KeyPath, path: KeyPath },
+    /// Fails the patch if the value at the path is not equal to the given value
+    Test { path: KeyPath, value: Plist },
+}
+
+/// A list of operations that are applied to a plist in order
+///
+/// # Example
+/// ```rust
+/// use plist_plus::{PatchOperation, Plist, PlistPatch};
+///
+/// let mut config = Plist::new_dict();
+/// config.dict_set_item("Version", 1_u64.into()).unwrap();
+///
+/// let mut patch = PlistPatch::new();
+/// patch.push(PatchOperation::Test { path: "/Version".parse().unwrap(), value: 1_u64.into() });
+/// patch.push(PatchOperation::Replace { path: "/Version".parse().unwrap(), value: 2_u64.into() });
+/// config.apply_patch(&patch).unwrap();
+///
+/// assert_eq!(config.dict_get_item("Version").unwrap().get_uint_val().unwrap(), 2);
+/// ```
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct PlistPatch {
+    pub operations: Vec<PatchOperation>,
+}
+
+impl PlistPatch {
+    /// Creates an empty patch
+    pub fn new() -> PlistPatch {
+        PlistPatch::default()
+    }
+    /// Appends an operation to the patch
+    pub fn push(&mut self, operation: PatchOperation) {
+        self.operations.push(operation);
+    }
+}
+
+impl Plist {
+    /// Applies every operation of the patch in order
+    /// If any operation fails, the plist is left untouched
+    pub fn apply_patch(&mut self, patch: &PlistPatch) -> Result<(), PlistError> {
+        trace!("Applying patch with {} operations", patch.operations.len());
+        // Patch a copy so a failure halfway through doesn't leave a partial result
+        let mut scratch = self.clone();
+        for operation in &patch.operations {
+            scratch.apply_operation(operation)?;
+        }
+        self.replace_with(scratch)
+    }
+
+    fn apply_operation(&mut self, operation: &PatchOperation) -> Result<(), PlistError> {
+        match operation {
+            PatchOperation::Add { path, value } => self.patch_add(path, value.clone()),
+            PatchOperation::Remove { path } => self.patch_remove(path).map(|_| ()),
+            PatchOperation::Replace { path, value } if path.is_root() => {
+                self.replace_with(value.clone())
+            }
+            // Replaced in place, so a dictionary key keeps its position
+            PatchOperation::Replace { path, value } => {
+                self.resolve_path(path)?.replace_with(value.clone())
+            }
+            PatchOperation::Move { from, path } => {
+                if from.is_ancestor_of(path) {
+                    warn!("Cannot move {} into one of its children", from);
+                    return Err(PlistError::InvalidArg);
+                }
+                let value = self.patch_remove(from)?;
+                self.patch_add(path, value)
+            }
+            PatchOperation::Copy { from, path } => {
+                let value = self.resolve_path(from)?.clone();
+                self.patch_add(path, value)
+            }
+            PatchOperation::Test { path, value } => {
+                if &self.resolve_path(path)? != value {
+                    warn!("Patch test failed at {}", path);
+                    return Err(PlistError::InvalidArg);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns a false dropped handle to the node at the path
+    pub(crate) fn resolve_path(&self, path: &KeyPath) -> Result<Plist, PlistError> {
+        let mut current: Plist = self.plist_t.into();
+        current.false_drop = true;
+        for segment in &path.0 {
+            let next = match (&current.plist_type, segment) {
+                (PlistType::Dictionary, segment) => current.dict_get_item(&segment.as_key())?,
+                (PlistType::Array, PathSegment::Index(index)) => current.array_get_item(*index)?,
+                _ => {
+                    warn!("Path {} does not exist", path);
+                    return Err(PlistError::InvalidArg);
+                }
+            };
+            if next.plist_t.is_null() {
+                warn!("Path {} does not exist", path);
+                return Err(PlistError::InvalidArg);
+            }
+            current = next;
+        }
+        Ok(current)
+    }
+
+    fn patch_add(&mut self, path: &KeyPath, value: Plist) -> Result<(), PlistError> {
+        let (parent, last) = match path.split_last() {
+            Some(split) => split,
+            None => return self.replace_with(value),
+        };
+        let mut parent = self.resolve_path(&parent)?;
+        match (&parent.plist_type, last) {
+            (PlistType::Dictionary, last) => parent.dict_set_item(&last.as_key(), value),
+            // "-" refers to the end of the array
+            (PlistType::Array, PathSegment::Key(key)) if key == "-" => {
+                parent.array_append_item(value)
+            }
+            (PlistType::Array, PathSegment::Index(index)) => {
+                let size = parent.array_get_size()?;
+                if *index == size {
+                    parent.array_append_item(value)
+                } else if *index < size {
+                    parent.array_insert_item(value, *index)
+                } else {
+                    warn!("Index {} is out of bounds", path);
+                    Err(PlistError::InvalidArg)
+                }
+            }
+            _ => {
+                warn!("Cannot add a value at {}", path);
+                Err(PlistError::InvalidArg)
+            }
+        }
+    }
+
+    /// Removes the node at the path and returns a copy of it
+    fn patch_remove(&mut self, path: &KeyPath) -> Result<Plist, PlistError> {
+        let value = self.resolve_path(path)?.clone();
+        let (parent, last) = match path.split_last() {
+            Some(split) => split,
+            None => {
+                warn!("Cannot remove the root of a plist");
+                return Err(PlistError::InvalidArg);
+            }
+        };
+        let parent = self.resolve_path(&parent)?;
+        match (&parent.plist_type, last) {
+            (PlistType::Dictionary, last) => parent.dict_remove_item(&last.as_key())?,
+            (PlistType::Array, PathSegment::Index(index)) => parent.array_remove_item(*index)?,
+            _ => return Err(PlistError::InvalidArg),
+        }
+        Ok(value)
+    }
+
+    /// Replaces the plist with the value
+    /// A false dropped plist is owned by its parent, so the value takes its place there
+    pub(crate) fn replace_with(&mut self, value: Plist) -> Result<(), PlistError> {
+        if !self.false_drop {
+            *self = value;
+            return Ok(());
+        }
+        let mut parent: Plist = Active::parent(&self.plist_t).into();
+        parent.false_drop = true;
+        match parent.plist_type {
+            PlistType::Dictionary => {
+                let key = self.dict_get_item_key()?;
+                parent.dict_set_item(&key, value)?;
+                *self = parent.dict_get_item(&key)?;
+            }
+            PlistType::Array => {
+                let index = self.array_get_item_index()?;
+                parent.array_set_item(value, index)?;
+                *self = parent.array_get_item(index)?;
+            }
+            _ => {
+                warn!("Cannot replace a borrowed plist without a parent");
+                return Err(PlistError::InvalidArg);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PatchOperation {
+    fn name(&self) -> &'static str {
+        match self {
+            PatchOperation::Add { .. } => "add",
+            PatchOperation::Remove { .. } => "remove",
+            PatchOperation::Replace { .. } => "replace",
+            PatchOperation::Move { .. } => "move",
+            PatchOperation::Copy { .. } => "copy",
+            PatchOperation::Test { .. } => "test",
+        }
+    }
+}
+
+impl From<PatchOperation> for Plist {
+    fn from(operation: PatchOperation) -> Self {
+        let mut dict = Plist::new_dict();
+        dict.dict_set_item("op", operation.name().into()).unwrap();
+        match operation {
+            PatchOperation::Add { path, value }
+            | PatchOperation::Replace { path, value }
+            | PatchOperation::Test { path, value } => {
+                dict.dict_set_item("path", path.to_string().into()).unwrap();
+                dict.dict_set_item("value", value).unwrap();
+            }
+            PatchOperation::Remove { path } => {
+                dict.dict_set_item("path", path.to_string().into()).unwrap();
+            }
+            PatchOperation::Move { from, path } | PatchOperation::Copy { from, path } => {
+                dict.dict_set_item("from", from.to_string().into()).unwrap();
+                dict.dict_set_item("path", path.to_string().into()).unwrap();
+            }
+        }
+        dict
+    }
+}
+
+impl TryFrom<Plist> for PatchOperation {
+    type Error = PlistError;
+    fn try_from(plist: Plist) -> Result<Self, Self::Error> {
+        let get_path = |key: &str| -> Result<KeyPath, PlistError> {
+            let item = plist.dict_get_item(key)?;
+            if item.plist_t.is_null() {
+                warn!("Patch operation is missing {}", key);
+                return Err(PlistError::InvalidArg);
+            }
+            item.get_string_val()?.parse()
+        };
+        let get_value = || -> Result<Plist, PlistError> {
+            let item = plist.dict_get_item("value")?;
+            if item.plist_t.is_null() {
+                warn!("Patch operation is missing value");
+                return Err(PlistError::InvalidArg);
+            }
+            Ok(item.clone())
+        };
+        let op = plist.dict_get_item("op")?;
+        if op.plist_t.is_null() {
+            warn!("Patch operation is missing op");
+            return Err(PlistError::InvalidArg);
+        }
+        Ok(match op.get_string_val()?.as_str() {
+            "add" => PatchOperation::Add {
+                path: get_path("path")?,
+                value: get_value()?,
+            },
+            "remove" => PatchOperation::Remove {
+                path: get_path("path")?,
+            },
+            "replace" => PatchOperation::Replace {
+                path: get_path("path")?,
+                value: get_value()?,
+            },
+            "move" => PatchOperation::Move {
+                from: get_path("from")?,
+                path: get_path("path")?,
+            },
+            "copy" => PatchOperation::Copy {
+                from: get_path("from")?,
+                path: get_path("path")?,
+            },
+            "test" => PatchOperation::Test {
+                path: get_path("path")?,
+                value: get_value()?,
+            },
+            other => {
+                warn!("Unknown patch operation {}", other);
+                return Err(PlistError::InvalidArg);
+            }
+        })
+    }
+}
+
+impl From<PlistPatch> for Plist {
+    fn from(patch: PlistPatch) -> Self {
+        let mut array = Plist::new_array();
+        for operation in patch.operations {
+            array.array_append_item(operation.into()).unwrap();
+        }
+        array
+    }
+}
+
+impl TryFrom<Plist> for PlistPatch {
+    type Error = PlistError;
+    fn try_from(plist: Plist) -> Result<Self, Self::Error> {
+        Ok(PlistPatch {
+            operations: plist
+                .array_items()
+                .into_iter()
+                .map(PatchOperation::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Plist {
+        let mut devices = Plist::new_array();
+        devices.array_append_item("a".into()).unwrap();
+        devices.array_append_item("b".into()).unwrap();
+        let mut p = Plist::new_dict();
+        p.dict_set_item("Devices", devices).unwrap();
+        p.dict_set_item("Version", 1_u64.into()).unwrap();
+        p
+    }
+
+    #[test]
+    fn patch_test() {
+        let mut p = sample();
+        let mut patch = PlistPatch::new();
+        patch.push(PatchOperation::Add {
+            path: "/Devices/-".parse().unwrap(),
+            value: "c".into(),
+        });
+        patch.push(PatchOperation::Move {
+            from: "/Devices/0".parse().unwrap(),
+            path: "/First".parse().unwrap(),
+        });
+        patch.push(PatchOperation::Remove {
+            path: "/Version".parse().unwrap(),
+        });
+        p.apply_patch(&patch).unwrap();
+
+        assert_eq!(
+            p.dict_get_item("First").unwrap().get_string_val().unwrap(),
+            "a"
+        );
+        assert_eq!(
+            p.dict_get_item("Devices")
+                .unwrap()
+                .array_get_size()
+                .unwrap(),
+            2
+        );
+        assert!(p.dict_get_item("Version").unwrap().plist_t.is_null());
+    }
+
+    #[test]
+    fn patch_transaction_test() {
+        let mut p = sample();
+        let mut patch = PlistPatch::new();
+        patch.push(PatchOperation::Remove {
+            path: "/Version".parse().unwrap(),
+        });
+        patch.push(PatchOperation::Test {
+            path: "/Devices/0".parse().unwrap(),
+            value: "z".into(),
+        });
+        assert!(p.apply_patch(&patch).is_err());
+        assert_eq!(p, sample());
+    }
+
+    #[test]
+    fn patch_replace_test() {
+        let mut p = sample();
+        let mut patch = PlistPatch::new();
+        patch.push(PatchOperation::Replace {
+            path: "/Devices".parse().unwrap(),
+            value: "none".into(),
+        });
+        p.apply_patch(&patch).unwrap();
+        // The key keeps its position
+        assert_eq!(p.dict_get_keys().unwrap(), vec!["Devices", "Version"]);
+        assert_eq!(
+            p.dict_get_item("Devices")
+                .unwrap()
+                .get_string_val()
+                .unwrap(),
+            "none"
+        );
+
+        // A borrowed plist is patched where it lives in its parent
+        let p = sample();
+        let mut devices = p.dict_get_item("Devices").unwrap();
+        let mut patch = PlistPatch::new();
+        patch.push(PatchOperation::Replace {
+            path: "/1".parse().unwrap(),
+            value: "c".into(),
+        });
+        devices.apply_patch(&patch).unwrap();
+        assert_eq!(devices.array_get_size().unwrap(), 2);
+        let devices = p.dict_get_item("Devices").unwrap();
+        assert_eq!(
+            devices.array_get_item(1).unwrap().get_string_val().unwrap(),
+            "c"
+        );
+    }
+
+    #[test]
+    fn patch_plist_round_trip_test() {
+        let mut patch = PlistPatch::new();
+        patch.push(PatchOperation::Copy {
+            from: "/Devices/1".parse().unwrap(),
+            path: "/Devices/0".parse().unwrap(),
+        });
+        patch.push(PatchOperation::Replace {
+            path: "/Version".parse().unwrap(),
+            value: 2_u64.into(),
+        });
+        let plist: Plist = patch.clone().into();
+        assert_eq!(PlistPatch::try_from(plist).unwrap(), patch);
+    }
+}
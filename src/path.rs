@@ -0,0 +1,123 @@
+// jkcoxson
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use log::warn;
+
+use crate::error::PlistError;
+
+/// A single step into a container plist
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Clone)]
+pub enum PathSegment {
+    /// A key in a dictionary
+    Key(String),
+    /// An index in an array
+    Index(u32),
+}
+
+impl PathSegment {
+    /// Returns the segment as a dictionary key
+    pub fn as_key(&self) -> String {
+        match self {
+            PathSegment::Key(key) => key.clone(),
+            PathSegment::Index(index) => index.to_string(),
+        }
+    }
+}
+
+/// The location of a node relative to the root of a plist
+/// Displayed as a JSON Pointer, such as `/ProductVersion` or `/Devices/0/UDID`
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Clone, Default)]
+pub struct KeyPath(pub Vec<PathSegment>);
+
+impl KeyPath {
+    /// Returns the path to the root of a plist
+    pub fn root() -> KeyPath {
+        KeyPath(Vec::new())
+    }
+    /// Returns a new path with the given segment appended
+    pub fn join(&self, segment: PathSegment) -> KeyPath {
+        let mut segments = self.0.clone();
+        segments.push(segment);
+        KeyPath(segments)
+    }
+    /// Returns true if the path points at the root
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// Splits the path into the path of the parent and the last segment
+    /// Returns None for the root
+    pub fn split_last(&self) -> Option<(KeyPath, &PathSegment)> {
+        let (last, parent) = self.0.split_last()?;
+        Some((KeyPath(parent.to_vec()), last))
+    }
+    /// Returns true if self is an ancestor of the other path
+    pub fn is_ancestor_of(&self, other: &KeyPath) -> bool {
+        self.0.len() < other.0.len() && other.0.starts_with(&self.0)
+    }
+}
+
+impl Display for PathSegment {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // Escaped as described by RFC 6901
+            PathSegment::Key(key) => write!(f, "{}", key.replace('~', "~0").replace('/', "~1")),
+            PathSegment::Index(index) => write!(f, "{}", index),
+        }
+    }
+}
+
+impl Display for KeyPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for segment in &self.0 {
+            write!(f, "/{}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&str> for PathSegment {
+    fn from(key: &str) -> Self {
+        PathSegment::Key(key.to_string())
+    }
+}
+
+impl From<u32> for PathSegment {
+    fn from(index: u32) -> Self {
+        PathSegment::Index(index)
+    }
+}
+
+impl FromStr for KeyPath {
+    type Err = PlistError;
+
+    /// Parses a JSON Pointer such as `/Devices/0/UDID`
+    /// Segments made only of digits are parsed as indexes, but still match dictionary keys
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(KeyPath::root());
+        }
+        if !s.starts_with('/') {
+            warn!("Key path must start with a slash");
+            return Err(PlistError::InvalidArg);
+        }
+        Ok(KeyPath(
+            s[1..]
+                .split('/')
+                .map(|segment| {
+                    let segment = segment.replace("~1", "/").replace("~0", "~");
+                    match segment.parse::<u32>() {
+                        Ok(index) if is_index(&segment) => PathSegment::Index(index),
+                        _ => PathSegment::Key(segment),
+                    }
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// Array indexes are written in decimal without a sign or leading zeros
+fn is_index(segment: &str) -> bool {
+    segment.bytes().all(|b| b.is_ascii_digit()) && (segment == "0" || !segment.starts_with('0'))
+}
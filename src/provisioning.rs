@@ -0,0 +1,398 @@
+// jkcoxson
+
+use std::borrow::Cow;
+use std::time::SystemTime;
+
+use log::{trace, warn};
+
+use crate::entitlements::Entitlements;
+use crate::error::PlistError;
+use crate::limits::ParseLimits;
+use crate::PlistValue;
+
+/// The DER encoding of the signedData content type, 1.2.840.113549.1.7.2
+const SIGNED_DATA_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+/// How deep elements with indefinite lengths may nest, far more than a SignedData needs
+const MAX_NESTING: usize = 32;
+
+/// A provisioning profile, as found in `.mobileprovision` files
+/// The signature is not verified, so only use the contents of profiles from a trusted source.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ProvisioningProfile {
+    /// Always a dictionary
+    value: PlistValue,
+}
+
+impl ProvisioningProfile {
+    /// Parses the plist inside a `.mobileprovision` file
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PlistError> {
+        Self::from_bytes_with_limits(bytes, &ParseLimits::default())
+    }
+
+    /// Parses a `.mobileprovision` file, rejecting the plist inside as soon as it goes over the limits
+    /// Use this for profiles that can't be trusted, such as ones uploaded by users
+    pub fn from_bytes_with_limits(bytes: &[u8], limits: &ParseLimits) -> Result<Self, PlistError> {
+        trace!("Parsing provisioning profile");
+        let plist = extract_plist(bytes)?;
+        match crate::limits::from_memory(&plist, limits)? {
+            value @ PlistValue::Dictionary(_) => Ok(ProvisioningProfile { value }),
+            _ => {
+                warn!("Provisioning profile is not a dictionary");
+                Err(PlistError::InvalidArg)
+            }
+        }
+    }
+
+    /// Returns the whole dictionary
+    pub fn as_value(&self) -> &PlistValue {
+        &self.value
+    }
+
+    /// Returns the value of any key
+    pub fn get(&self, key: &str) -> Option<&PlistValue> {
+        self.value.dict_get(key)
+    }
+
+    /// `UUID`
+    pub fn uuid(&self) -> Option<&str> {
+        self.get("UUID").and_then(PlistValue::as_str)
+    }
+
+    /// `Name`
+    pub fn name(&self) -> Option<&str> {
+        self.get("Name").and_then(PlistValue::as_str)
+    }
+
+    /// `TeamIdentifier`, usually a single team
+    pub fn team_identifier(&self) -> Vec<&str> {
+        strings(self.get("TeamIdentifier"))
+    }
+
+    /// `CreationDate`
+    pub fn creation_date(&self) -> Option<SystemTime> {
+        date(self.get("CreationDate"))
+    }
+
+    /// `ExpirationDate`
+    pub fn expiration_date(&self) -> Option<SystemTime> {
+        date(self.get("ExpirationDate"))
+    }
+
+    /// Returns whether the profile expired before the given time
+    /// A profile without an expiration date never expires.
+    pub fn is_expired_at(&self, time: SystemTime) -> bool {
+        self.expiration_date()
+            .is_some_and(|expiration| expiration < time)
+    }
+
+    /// `ProvisionedDevices`, the UDIDs the profile installs on
+    /// This is empty for profiles that provision all devices.
+    pub fn provisioned_devices(&self) -> Vec<&str> {
+        strings(self.get("ProvisionedDevices"))
+    }
+
+    /// `ProvisionsAllDevices`, set for enterprise profiles
+    pub fn provisions_all_devices(&self) -> bool {
+        self.get("ProvisionsAllDevices")
+            .and_then(PlistValue::as_bool)
+            .unwrap_or_default()
+    }
+
+    /// `Entitlements`, the most an app signed with this profile may have
+    pub fn entitlements(&self) -> Option<Entitlements> {
+        self.get("Entitlements")
+            .and_then(|value| Entitlements::from_value(value.clone()).ok())
+    }
+
+    /// `DeveloperCertificates`, the DER encoded certificates that may sign with this profile
+    pub fn developer_certificates(&self) -> Vec<&[u8]> {
+        self.get("DeveloperCertificates")
+            .and_then(PlistValue::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(PlistValue::as_data)
+            .collect()
+    }
+}
+
+fn strings(value: Option<&PlistValue>) -> Vec<&str> {
+    value
+        .and_then(PlistValue::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(PlistValue::as_str)
+        .collect()
+}
+
+fn date(value: Option<&PlistValue>) -> Option<SystemTime> {
+    match value {
+        Some(PlistValue::Date(date)) => Some(*date),
+        _ => None,
+    }
+}
+
+/// Returns the plist signed in a CMS SignedData blob
+/// The BER structure is followed to the signed content, which is put back together
+/// if it was split into chunks the way Apple's signing tools do.
+pub fn extract_plist(bytes: &[u8]) -> Result<Cow<'_, [u8]>, PlistError> {
+    match signed_content(bytes) {
+        Some(content) => Ok(content),
+        None => {
+            warn!("No signed content found in the provisioning profile");
+            Err(PlistError::parse(
+                "No signed content found in the provisioning profile",
+            ))
+        }
+    }
+}
+
+/// Walks ContentInfo -> SignedData -> EncapsulatedContentInfo to the signed content
+fn signed_content(bytes: &[u8]) -> Option<Cow<'_, [u8]>> {
+    let (content_info, _) = expect(bytes, 0x30)?;
+    let (oid, rest) = expect(content_info, 0x06)?;
+    if oid != SIGNED_DATA_OID {
+        return None;
+    }
+    let (explicit, _) = expect(rest, 0xa0)?;
+    let (signed_data, _) = expect(explicit, 0x30)?;
+    let (_version, rest) = expect(signed_data, 0x02)?;
+    let (_digest_algorithms, rest) = expect(rest, 0x31)?;
+    let (encap_content_info, _) = expect(rest, 0x30)?;
+    let (_content_type, rest) = expect(encap_content_info, 0x06)?;
+    let (explicit, _) = expect(rest, 0xa0)?;
+    match read_tlv(explicit)? {
+        (0x04, content, _) => Some(Cow::Borrowed(content)),
+        // BER allows the content to be split into several octet strings
+        (0x24, mut chunks, _) => {
+            let mut content = Vec::new();
+            while !chunks.is_empty() {
+                let (chunk, rest) = expect(chunks, 0x04)?;
+                content.extend_from_slice(chunk);
+                chunks = rest;
+            }
+            Some(Cow::Owned(content))
+        }
+        _ => None,
+    }
+}
+
+/// Reads an element with the given tag, returning its contents and what follows it
+fn expect(bytes: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    match read_tlv(bytes)? {
+        (t, content, rest) if t == tag => Some((content, rest)),
+        _ => None,
+    }
+}
+
+/// Reads one BER element with a single byte tag, returning its tag, contents and what follows it
+fn read_tlv(bytes: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    read_nested(bytes, 0)
+}
+
+fn read_nested(bytes: &[u8], depth: usize) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, bytes) = bytes.split_first()?;
+    let (&first, bytes) = bytes.split_first()?;
+    let (len, bytes) = match first {
+        0..=0x7f => (first as usize, bytes),
+        // An indefinite length runs to the end-of-contents octets, which aren't part of the
+        // contents. Only constructed elements may have one.
+        0x80 if tag & 0x20 != 0 && depth < MAX_NESTING => {
+            let mut rest = bytes;
+            while !rest.starts_with(&[0, 0]) {
+                (_, _, rest) = read_nested(rest, depth + 1)?;
+            }
+            let len = bytes.len() - rest.len();
+            return Some((tag, &bytes[..len], &rest[2..]));
+        }
+        0x81..=0x84 => {
+            let count = (first & 0x7f) as usize;
+            let (len, bytes) = bytes.split_at_checked(count)?;
+            (
+                len.iter().fold(0_usize, |acc, b| (acc << 8) | *b as usize),
+                bytes,
+            )
+        }
+        _ => return None,
+    };
+    let (content, rest) = bytes.split_at_checked(len)?;
+    Some((tag, content, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+	<key>Name</key>
+	<string>Example Development</string>
+	<key>UUID</key>
+	<string>0B9A2A3E-5C2B-4C55-9E0C-7B3C1F3E8D21</string>
+	<key>TeamIdentifier</key>
+	<array>
+		<string>ABCDE12345</string>
+	</array>
+	<key>ExpirationDate</key>
+	<date>2030-01-01T00:00:00Z</date>
+	<key>ProvisionedDevices</key>
+	<array>
+		<string>00008030-001A</string>
+	</array>
+	<key>Entitlements</key>
+	<dict>
+		<key>application-identifier</key>
+		<string>ABCDE12345.*</string>
+		<key>get-task-allow</key>
+		<true/>
+	</dict>
+	<key>DeveloperCertificates</key>
+	<array>
+		<data>MIIB</data>
+	</array>
+</dict>
+</plist>
+"#;
+
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        match content.len() {
+            len @ 0..=0x7f => out.push(len as u8),
+            len @ 0x80..=0xff => out.extend([0x81, len as u8]),
+            len => out.extend([0x82, (len >> 8) as u8, len as u8]),
+        }
+        out.extend(content);
+        out
+    }
+
+    /// Wraps content the way a signed profile does, with an empty signer list
+    fn signed(content: Vec<u8>) -> Vec<u8> {
+        let data_oid = der(
+            0x06,
+            &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01],
+        );
+        let encap = der(0x30, &[data_oid, der(0xa0, &content)].concat());
+        let signed_data = der(
+            0x30,
+            &[der(0x02, &[1]), der(0x31, &[]), encap, der(0x31, &[])].concat(),
+        );
+        der(
+            0x30,
+            &[der(0x06, SIGNED_DATA_OID), der(0xa0, &signed_data)].concat(),
+        )
+    }
+
+    fn ber(tag: u8, content: &[u8]) -> Vec<u8> {
+        [&[tag, 0x80], content, &[0, 0]].concat()
+    }
+
+    /// Wraps content like a `.mobileprovision` from Apple, shortened to one fake certificate
+    fn apple_signed(content: &[u8]) -> Vec<u8> {
+        let data_oid = der(
+            0x06,
+            &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01],
+        );
+        let sha256 = der(
+            0x30,
+            &[
+                der(
+                    0x06,
+                    &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01],
+                ),
+                der(0x05, &[]),
+            ]
+            .concat(),
+        );
+        let chunks: Vec<u8> = content.chunks(1000).flat_map(|c| der(0x04, c)).collect();
+        let encap = ber(0x30, &[data_oid, ber(0xa0, &ber(0x24, &chunks))].concat());
+        let signed_data = ber(
+            0x30,
+            &[
+                der(0x02, &[1]),
+                der(0x31, &sha256),
+                encap,
+                der(0xa0, &der(0x30, &[0; 300])),
+                der(0x31, &der(0x30, &[der(0x02, &[1]), sha256].concat())),
+            ]
+            .concat(),
+        );
+        ber(
+            0x30,
+            &[der(0x06, SIGNED_DATA_OID), ber(0xa0, &signed_data)].concat(),
+        )
+    }
+
+    #[test]
+    fn extract_plist_test() {
+        let bytes = signed(der(0x04, PROFILE.as_bytes()));
+        assert_eq!(extract_plist(&bytes).unwrap(), PROFILE.as_bytes());
+
+        let chunked = signed(der(
+            0x24,
+            &[
+                der(0x04, &PROFILE.as_bytes()[..100]),
+                der(0x04, &PROFILE.as_bytes()[100..]),
+            ]
+            .concat(),
+        ));
+        assert_eq!(extract_plist(&chunked).unwrap(), PROFILE.as_bytes());
+
+        // Apple signs profiles with indefinite lengths and the content in 1000 byte chunks
+        let profile = PROFILE.repeat(3);
+        let bytes = apple_signed(profile.as_bytes());
+        assert_eq!(extract_plist(&bytes).unwrap(), profile.as_bytes());
+        // Missing end-of-contents octets
+        assert!(extract_plist(&bytes[..bytes.len() - 2]).is_err());
+        assert!(extract_plist(&[0x30, 0x80].repeat(10000)).is_err());
+
+        assert!(extract_plist(b"\x30\x03\x06\x01\x00").is_err());
+    }
+
+    #[test]
+    fn profile_fields_test() {
+        let profile =
+            ProvisioningProfile::from_bytes(&signed(der(0x04, PROFILE.as_bytes()))).unwrap();
+        assert_eq!(
+            ProvisioningProfile::from_bytes(&apple_signed(PROFILE.as_bytes())).unwrap(),
+            profile
+        );
+        assert_eq!(profile.name(), Some("Example Development"));
+        assert_eq!(profile.uuid(), Some("0B9A2A3E-5C2B-4C55-9E0C-7B3C1F3E8D21"));
+        assert_eq!(profile.team_identifier(), vec!["ABCDE12345"]);
+        assert_eq!(profile.provisioned_devices(), vec!["00008030-001A"]);
+        assert!(!profile.provisions_all_devices());
+        assert_eq!(
+            profile.developer_certificates(),
+            vec![&[0x30, 0x82, 0x01][..]]
+        );
+
+        let expiration = profile.expiration_date().unwrap();
+        assert_eq!(
+            expiration
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            1893456000
+        );
+        assert!(!profile.is_expired_at(SystemTime::UNIX_EPOCH));
+        assert!(profile.is_expired_at(expiration + std::time::Duration::from_secs(1)));
+
+        let entitlements = profile.entitlements().unwrap();
+        assert!(entitlements.get_task_allow());
+        let app = Entitlements::from_bytes(
+            br#"{ "application-identifier" = "ABCDE12345.com.example.app"; }"#,
+        )
+        .unwrap();
+        assert!(app.is_subset_of(&entitlements));
+
+        let limits = ParseLimits {
+            max_depth: 1,
+            ..Default::default()
+        };
+        assert!(ProvisioningProfile::from_bytes_with_limits(
+            &signed(der(0x04, PROFILE.as_bytes())),
+            &limits
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,337 @@
+// jkcoxson
+
+use log::{trace, warn};
+
+use crate::error::PlistError;
+use crate::limits::ParseLimits;
+use crate::openstep::{push_quoted, Reader};
+use crate::{Plist, PlistValue};
+
+/// The text encoding of a .strings file
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum StringsEncoding {
+    #[default]
+    Utf8,
+    /// UTF-8 with a byte order mark
+    Utf8Bom,
+    /// UTF-16 little-endian with a byte order mark, the encoding Xcode used to write
+    Utf16Le,
+    /// UTF-16 big-endian with a byte order mark
+    Utf16Be,
+}
+
+/// An entry of a .strings file
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct StringsEntry {
+    /// The comments and whitespace before the entry, exactly as written
+    pub leading: String,
+    pub key: String,
+    pub value: String,
+    /// The entry as written, with the key and value it had, so it can be written back unchanged
+    source: Option<(String, String, String)>,
+}
+
+impl StringsEntry {
+    /// Creates an entry with no comment
+    pub fn new(key: &str, value: &str) -> Self {
+        StringsEntry {
+            leading: String::new(),
+            key: key.to_string(),
+            value: value.to_string(),
+            source: None,
+        }
+    }
+
+    fn write(&self, out: &mut String) {
+        out.push_str(&self.leading);
+        if let Some((key, value, source)) = &self.source {
+            if *key == self.key && *value == self.value {
+                out.push_str(source);
+                return;
+            }
+        }
+        push_quoted(out, &self.key);
+        out.push_str(" = ");
+        push_quoted(out, &self.value);
+        out.push(';');
+    }
+}
+
+/// A localized .strings file of `"key" = "value";` entries
+/// Comments, blank lines and the order of the entries are kept, and entries that aren't
+/// changed are written back exactly as they were, so the file can be edited in place.
+/// Comments between the parts of a single entry are dropped.
+///
+/// # Example
+/// ```rust
+/// use plist_plus::StringsFile;
+///
+/// let mut strings = StringsFile::parse(b"/* Title of the main window */\n\"title\" = \"Hello\";\n").unwrap();
+/// assert_eq!(strings.get("title"), Some("Hello"));
+/// strings.set("title", "Bonjour");
+/// assert_eq!(strings.write(), b"/* Title of the main window */\n\"title\" = \"Bonjour\";\n");
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct StringsFile {
+    pub entries: Vec<StringsEntry>,
+    /// The comments and whitespace after the last entry
+    pub trailing: String,
+    pub encoding: StringsEncoding,
+}
+
+impl StringsFile {
+    /// Creates an empty UTF-8 .strings file
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a .strings file in UTF-8, or in UTF-8 or UTF-16 with a byte order mark
+    pub fn parse(bytes: &[u8]) -> Result<Self, PlistError> {
+        Self::parse_with_limits(bytes, &ParseLimits::default())
+    }
+
+    /// Parses a .strings file, rejecting it as soon as it goes over the limits
+    pub fn parse_with_limits(bytes: &[u8], limits: &ParseLimits) -> Result<Self, PlistError> {
+        trace!("Parsing strings file");
+        let (text, encoding) = decode(bytes)?;
+        let mut reader = Reader::new(&text, limits)?;
+        let mut entries = Vec::new();
+        loop {
+            let start = reader.pos;
+            reader.skip_whitespace()?;
+            let leading = text[start..reader.pos].to_string();
+            if reader.peek().is_none() {
+                return Ok(StringsFile {
+                    entries,
+                    trailing: leading,
+                    encoding,
+                });
+            }
+
+            let entry_start = reader.pos;
+            let key = reader.read_string()?;
+            reader.skip_whitespace()?;
+            // An entry without a value uses its key as the value
+            let value = if reader.peek() == Some(';') {
+                key.clone()
+            } else {
+                reader.expect('=')?;
+                reader.skip_whitespace()?;
+                let value = reader.read_string()?;
+                reader.skip_whitespace()?;
+                value
+            };
+            reader.expect(';')?;
+            let source = text[entry_start..reader.pos].to_string();
+            entries.push(StringsEntry {
+                leading,
+                source: Some((key.clone(), value.clone(), source)),
+                key,
+                value,
+            });
+        }
+    }
+
+    /// Returns the file in its encoding
+    pub fn write(&self) -> Vec<u8> {
+        trace!("Writing strings file");
+        let mut text = String::new();
+        for entry in &self.entries {
+            entry.write(&mut text);
+        }
+        text.push_str(&self.trailing);
+        encode(&text, self.encoding)
+    }
+
+    /// Returns the value of a key
+    /// If a key appears more than once, the last one wins, like it does when the file is loaded.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.key == key)
+            .map(|entry| entry.value.as_str())
+    }
+
+    /// Sets the value of a key, keeping its place and comment if it already exists
+    /// A new key is added on its own line at the end.
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.entries.iter_mut().rev().find(|entry| entry.key == key) {
+            Some(entry) => entry.value = value.to_string(),
+            None => {
+                let mut entry = StringsEntry::new(key, value);
+                if !self.entries.is_empty() {
+                    entry.leading.push('\n');
+                }
+                self.entries.push(entry);
+                if !self.trailing.ends_with('\n') {
+                    self.trailing.push('\n');
+                }
+            }
+        }
+    }
+
+    /// Removes every entry with the key, returning the value of the last one
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let value = self.get(key).map(String::from);
+        self.entries.retain(|entry| entry.key != key);
+        value
+    }
+
+    /// Returns the entries as a dictionary of strings
+    pub fn to_value(&self) -> PlistValue {
+        let mut dict = PlistValue::Dictionary(Vec::new());
+        for entry in &self.entries {
+            dict.dict_set(&entry.key, PlistValue::String(entry.value.clone()));
+        }
+        dict
+    }
+
+    /// Returns the entries as a dictionary plist
+    pub fn to_plist(&self) -> Plist {
+        self.to_value().into()
+    }
+}
+
+/// Returns the text of a file and the encoding it was in
+fn decode(bytes: &[u8]) -> Result<(String, StringsEncoding), PlistError> {
+    let (text, encoding) = if let Some(rest) = bytes.strip_prefix(b"\xEF\xBB\xBF") {
+        (utf8(rest)?, StringsEncoding::Utf8Bom)
+    } else if let Some(rest) = bytes.strip_prefix(b"\xFF\xFE") {
+        (utf16(rest, u16::from_le_bytes)?, StringsEncoding::Utf16Le)
+    } else if let Some(rest) = bytes.strip_prefix(b"\xFE\xFF") {
+        (utf16(rest, u16::from_be_bytes)?, StringsEncoding::Utf16Be)
+    } else {
+        (utf8(bytes)?, StringsEncoding::Utf8)
+    };
+    Ok((text, encoding))
+}
+
+fn utf8(bytes: &[u8]) -> Result<String, PlistError> {
+    match String::from_utf8(bytes.to_vec()) {
+        Ok(text) => Ok(text),
+        Err(_) => {
+            warn!("Strings file is not valid UTF-8");
+            Err(PlistError::parse("Strings file is not valid UTF-8"))
+        }
+    }
+}
+
+fn utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Result<String, PlistError> {
+    if !bytes.len().is_multiple_of(2) {
+        warn!("Strings file has an odd number of UTF-16 bytes");
+        return Err(PlistError::parse(
+            "Strings file has an odd number of UTF-16 bytes",
+        ));
+    }
+    let units = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    match char::decode_utf16(units).collect::<Result<String, _>>() {
+        Ok(text) => Ok(text),
+        Err(_) => {
+            warn!("Strings file is not valid UTF-16");
+            Err(PlistError::parse("Strings file is not valid UTF-16"))
+        }
+    }
+}
+
+fn encode(text: &str, encoding: StringsEncoding) -> Vec<u8> {
+    match encoding {
+        StringsEncoding::Utf8 => text.as_bytes().to_vec(),
+        StringsEncoding::Utf8Bom => [b"\xEF\xBB\xBF", text.as_bytes()].concat(),
+        StringsEncoding::Utf16Le => std::iter::once(0xFEFF)
+            .chain(text.encode_utf16())
+            .flat_map(u16::to_le_bytes)
+            .collect(),
+        StringsEncoding::Utf16Be => std::iter::once(0xFEFF)
+            .chain(text.encode_utf16())
+            .flat_map(u16::to_be_bytes)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRINGS: &str = "/* Localizable.strings\n   Example */\n\n\
+        // The greeting\n\
+        \"greeting\"   =   \"Hello, %@!\";\n\
+        \"farewell\" = \"Goodbye\\n\\\"friend\\\"\";\n\
+        legacy;\n\
+        /* Ends here */\n";
+
+    #[test]
+    fn parse_strings_test() {
+        let strings = StringsFile::parse(STRINGS.as_bytes()).unwrap();
+        assert_eq!(strings.encoding, StringsEncoding::Utf8);
+        assert_eq!(strings.entries.len(), 3);
+        assert_eq!(
+            strings.entries[0].leading,
+            "/* Localizable.strings\n   Example */\n\n// The greeting\n"
+        );
+        assert_eq!(strings.get("greeting"), Some("Hello, %@!"));
+        assert_eq!(strings.get("farewell"), Some("Goodbye\n\"friend\""));
+        assert_eq!(strings.get("legacy"), Some("legacy"));
+        assert_eq!(strings.trailing, "\n/* Ends here */\n");
+        assert_eq!(
+            strings.to_value().dict_get("greeting"),
+            Some(&PlistValue::String("Hello, %@!".to_string()))
+        );
+
+        // Untouched files are written back byte for byte
+        assert_eq!(strings.write(), STRINGS.as_bytes());
+
+        match StringsFile::parse(b"\"a\" = \"b\";\n\"c\" = ;") {
+            Err(PlistError::Parse { line, .. }) => assert_eq!(line, 2),
+            e => panic!("Unexpected result {:?}", e),
+        }
+        assert!(StringsFile::parse(b"\"a\" = \"b\"").is_err());
+    }
+
+    #[test]
+    fn edit_strings_test() {
+        let mut strings = StringsFile::parse(STRINGS.as_bytes()).unwrap();
+        strings.set("greeting", "Bonjour, %@ !");
+        strings.set("new", "Nouveau");
+        assert_eq!(strings.remove("legacy"), Some("legacy".to_string()));
+        let written = String::from_utf8(strings.write()).unwrap();
+        assert_eq!(
+            written,
+            "/* Localizable.strings\n   Example */\n\n\
+             // The greeting\n\
+             \"greeting\" = \"Bonjour, %@ !\";\n\
+             \"farewell\" = \"Goodbye\\n\\\"friend\\\"\";\n\
+             \"new\" = \"Nouveau\";\n\
+             /* Ends here */\n"
+        );
+        assert_eq!(
+            StringsFile::parse(written.as_bytes()).unwrap().get("new"),
+            Some("Nouveau")
+        );
+
+        let mut empty = StringsFile::new();
+        empty.set("a", "b");
+        assert_eq!(empty.write(), b"\"a\" = \"b\";\n");
+    }
+
+    #[test]
+    fn strings_encoding_test() {
+        for encoding in [
+            StringsEncoding::Utf8Bom,
+            StringsEncoding::Utf16Le,
+            StringsEncoding::Utf16Be,
+        ] {
+            let bytes = encode("\"café\" = \"naïve 🎉\";\n", encoding);
+            let strings = StringsFile::parse(&bytes).unwrap();
+            assert_eq!(strings.encoding, encoding);
+            assert_eq!(strings.get("café"), Some("naïve 🎉"));
+            assert_eq!(strings.write(), bytes);
+        }
+        assert_eq!(
+            &encode("a", StringsEncoding::Utf16Le),
+            &[0xFF, 0xFE, b'a', 0]
+        );
+        assert!(StringsFile::parse(b"\xFF\xFE\x22").is_err());
+        assert!(StringsFile::parse(b"\xFF\xFE\x00\xD8").is_err());
+    }
+}
@@ -7,3 +7,4 @@ pub mod integer;
 pub mod key;
 pub mod real;
 pub mod string;
+pub mod uid;
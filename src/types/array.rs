@@ -2,13 +2,17 @@
 
 use log::trace;
 
-use crate::{error::PlistError, unsafe_bindings, Plist, PlistType};
+use crate::{
+    backend::{Active, Backend},
+    error::PlistError,
+    Plist, PlistType,
+};
 
 impl Plist {
     /// Creates a new plist with an empty array
     pub fn new_array() -> Plist {
         trace!("Generating new array plist");
-        unsafe { unsafe_bindings::plist_new_array() }.into()
+        Active::new_array().into()
     }
     /// Returns the number of elements in the array
     pub fn array_get_size(&self) -> Result<u32, PlistError> {
@@ -16,7 +20,7 @@ impl Plist {
             return Err(PlistError::InvalidArg);
         }
         trace!("Getting array size");
-        Ok(unsafe { unsafe_bindings::plist_array_get_size(self.plist_t) })
+        Active::array_len(&self.plist_t)
     }
     /// Returns the element at the given index
     pub fn array_get_item(&self, index: u32) -> Result<Plist, PlistError> {
@@ -24,20 +28,15 @@ impl Plist {
             return Err(PlistError::InvalidArg);
         }
         trace!("Getting array item");
-        let mut plist: Plist = unsafe { unsafe_bindings::plist_array_get_item(self.plist_t, index) }.into();
+        let mut plist: Plist = Active::array_get(&self.plist_t, index)?.into();
         plist.false_drop = true;
 
         Ok(plist)
     }
-    /// Gets the index of an array item
+    /// Gets the index of this item in the array holding it
     pub fn array_get_item_index(&self) -> Result<u32, PlistError> {
-        if self.plist_type != PlistType::Array {
-            return Err(PlistError::InvalidArg);
-        }
         trace!("Getting array item index");
-        Ok(unsafe {
-            unsafe_bindings::plist_array_get_item_index(self.plist_t) // ???
-        })
+        Active::array_item_index(&self.plist_t)
     }
     /// Sets an array item at the given index
     pub fn array_set_item(&mut self, item: Plist, index: u32) -> Result<(), PlistError> {
@@ -45,7 +44,7 @@ impl Plist {
             return Err(PlistError::InvalidArg);
         }
         trace!("Setting array item");
-        unsafe { unsafe_bindings::plist_array_set_item(self.plist_t, item.plist_t, index) };
+        Active::array_set(&mut self.plist_t, index, item.plist_t)?;
         item.false_drop();
         Ok(())
     }
@@ -55,7 +54,7 @@ impl Plist {
             return Err(PlistError::InvalidArg);
         }
         trace!("Appending array item");
-        unsafe { unsafe_bindings::plist_array_append_item(self.plist_t, item.plist_t) };
+        Active::array_append(&mut self.plist_t, item.plist_t)?;
         item.false_drop();
         Ok(())
     }
@@ -65,7 +64,7 @@ impl Plist {
             return Err(PlistError::InvalidArg);
         }
         trace!("Inserting array item");
-        unsafe { unsafe_bindings::plist_array_insert_item(self.plist_t, item.plist_t, index) }
+        Active::array_insert(&mut self.plist_t, index, item.plist_t)?;
         item.false_drop();
         Ok(())
     }
@@ -75,14 +74,12 @@ impl Plist {
             return Err(PlistError::InvalidArg);
         }
         trace!("Removing array item");
-        unsafe { unsafe_bindings::plist_array_remove_item(self.plist_t, index) };
-        Ok(())
+        Active::array_remove(&mut { self.plist_t }, index)
     }
     /// Removes one self from an array
     pub fn array_item_remove(&self) -> Result<(), PlistError> {
         trace!("Removing array item");
-        unsafe { unsafe_bindings::plist_array_item_remove(self.plist_t) }
-        Ok(())
+        Active::array_item_remove(&mut { self.plist_t })
     }
 }
 
@@ -113,8 +110,17 @@ mod tests {
 
         // Check if the items are still present.
         // They should be because we false drop them
-        assert_eq!("1", arr.array_get_item(0).unwrap().get_string_val().unwrap());
-        assert_eq!("2", arr.array_get_item(1).unwrap().get_string_val().unwrap());
-        assert_eq!("3", arr.array_get_item(2).unwrap().get_string_val().unwrap());
+        assert_eq!(
+            "1",
+            arr.array_get_item(0).unwrap().get_string_val().unwrap()
+        );
+        assert_eq!(
+            "2",
+            arr.array_get_item(1).unwrap().get_string_val().unwrap()
+        );
+        assert_eq!(
+            "3",
+            arr.array_get_item(2).unwrap().get_string_val().unwrap()
+        );
     }
 }
@@ -4,36 +4,27 @@ use std::os::raw::c_char;
 
 use log::{trace, warn};
 
-use crate::{error::PlistError, unsafe_bindings, Plist, PlistType};
+use crate::{
+    backend::{Active, Backend},
+    error::PlistError,
+    Plist, PlistType,
+};
 
 impl Plist {
     /// Returns a new plist with a type of data
     /// The data type is equivalent to a collection of bytes
     pub fn new_data(data: &[u8]) -> Plist {
         trace!("Generating new data plist");
-        unsafe {
-            unsafe_bindings::plist_new_data(
-                data.as_ptr() as *const c_char,
-                std::convert::TryInto::try_into(data.len()).unwrap(),
-            )
-        }
-        .into()
+        Active::new_data(data).into()
     }
     /// Returns the data value contained in a plist
     pub fn get_data_val(&self) -> Result<Vec<c_char>, PlistError> {
         if self.plist_type != PlistType::Data {
             return Err(PlistError::InvalidArg);
         }
-        let mut val = std::ptr::null_mut();
-        let mut size = 0;
         trace!("Getting data value");
-        unsafe {
-            unsafe_bindings::plist_get_data_val(self.plist_t, &mut val, &mut size);
-        }
-
-        let val = unsafe { std::vec::Vec::from_raw_parts(val, size as usize, size as usize) };
-
-        Ok(val.to_vec())
+        let val = Active::get_data(&self.plist_t)?;
+        Ok(val.into_iter().map(|b| b as c_char).collect())
     }
     /// Sets the contents of a plist to the given data
     pub fn set_data_val(&self, val: &[c_char]) -> Result<(), PlistError> {
@@ -42,7 +33,8 @@ impl Plist {
             return Err(PlistError::InvalidArg);
         }
         trace!("Setting data value");
-        unsafe { unsafe_bindings::plist_set_data_val(self.plist_t, val.as_ptr(), val.len() as u64) }
+        let val: Vec<u8> = val.iter().map(|&b| b as u8).collect();
+        Active::set_data(&mut { self.plist_t }, &val);
         Ok(())
     }
 }
@@ -59,8 +51,8 @@ mod tests {
 
     #[test]
     fn byte_tests() {
-        let p = Plist::new_data(&vec![1, 2, 3, 4, 5]);
-        p.set_data_val(&vec![5, 4, 3, 2, 1]).unwrap();
+        let p = Plist::new_data(&[1, 2, 3, 4, 5]);
+        p.set_data_val(&[5, 4, 3, 2, 1]).unwrap();
         assert_eq!(p.get_data_val().unwrap(), vec![5, 4, 3, 2, 1]);
     }
 }
@@ -1,10 +1,31 @@
 // jkcoxson
 
-use crate::{error::PlistError, unsafe_bindings, Plist, PlistType};
-use log::trace;
+use crate::{
+    backend::{Active, Backend},
+    error::PlistError,
+    Plist, PlistType,
+};
+use log::{trace, warn};
 use std::time::{Duration, SystemTime};
 
-const MAC_EPOCH: u64 = 978307200; // 01/01/2001
+pub(crate) const MAC_EPOCH: u64 = 978307200; // 01/01/2001
+
+/// Splits a Unix Timestamp into the seconds and microseconds since the Mac Epoch
+/// Dates before 2001 have negative seconds, and dates an i32 can't reach are clamped
+fn to_mac(date: Duration) -> (i32, i32) {
+    let secs = date.as_secs() as i64 - MAC_EPOCH as i64;
+    if secs < i32::MIN as i64 || secs > i32::MAX as i64 {
+        warn!("Date is out of range and will be clamped");
+    }
+    let secs = secs.clamp(i32::MIN as i64, i32::MAX as i64);
+    (secs as i32, date.subsec_micros() as i32)
+}
+
+/// Turns the seconds and microseconds since the Mac Epoch back into a Unix Timestamp
+fn from_mac(sec: i32, usec: i32) -> Duration {
+    let micros = (sec as i64 + MAC_EPOCH as i64) * 1000000 + usec as i64;
+    Duration::from_micros(micros.max(0) as u64)
+}
 
 impl Plist {
     /// Returns a new plist with a date.
@@ -23,11 +44,8 @@ impl Plist {
     /// ```
     pub fn new_date(date: Duration) -> Plist {
         trace!("Generating new date plist");
-        // The number of seconds since 01/01/2001
-        let duration = date - Duration::from_secs(MAC_EPOCH);
-        let secs = duration.as_secs();
-        let usecs = duration.as_micros() - (secs * 1000000) as u128;
-        unsafe { unsafe_bindings::plist_new_date(secs as i32, usecs as i32) }.into()
+        let (secs, usecs) = to_mac(date);
+        Active::new_date(secs, usecs).into()
     }
 
     /// Returns a duration (a Unix Timestamp) of the date
@@ -35,21 +53,16 @@ impl Plist {
         if self.plist_type != PlistType::Date {
             return Err(PlistError::InvalidArg);
         }
-        let mut sec = unsafe { std::mem::zeroed() };
-        let mut usec = unsafe { std::mem::zeroed() };
         trace!("Getting date value");
-        unsafe { unsafe_bindings::plist_get_date_val(self.plist_t, &mut sec, &mut usec) };
-        let date = usec as u64 + (sec as u64) * 1000000;
-        Ok(Duration::from_micros(date) + Duration::from_secs(MAC_EPOCH))
+        let (sec, usec) = Active::get_date(&self.plist_t)?;
+        Ok(from_mac(sec, usec))
     }
 
     /// Sets the date with a Unix Timestamp
     pub fn set_date_val(&self, date: Duration) {
-        let duration = date - Duration::from_secs(MAC_EPOCH);
-        let secs = duration.as_secs();
-        let usecs = duration.as_micros() - (secs * 1000000) as u128;
+        let (secs, usecs) = to_mac(date);
         trace!("Setting date value");
-        unsafe { unsafe_bindings::plist_set_date_val(self.plist_t, secs as i32, usecs as i32) };
+        Active::set_date(&mut { self.plist_t }, secs, usecs);
     }
 }
 
@@ -72,6 +85,21 @@ mod tests {
     use super::*;
     use std::time::{Duration, SystemTime};
 
+    #[test]
+    fn mac_epoch_test() {
+        let after = Duration::from_micros(1546635600123456); // Jan 04 2019 21:00:00.123456
+        assert_eq!(to_mac(after), ((1546635600 - MAC_EPOCH) as i32, 123456));
+        assert_eq!(from_mac((1546635600 - MAC_EPOCH) as i32, 123456), after);
+
+        // Dates before 2001 used to underflow
+        let before = Duration::from_micros(946684800500000); // Jan 01 2000 00:00:00.5
+        let (sec, usec) = to_mac(before);
+        assert_eq!((sec, usec), (-31622400, 500000));
+        assert_eq!(from_mac(sec, usec), before);
+
+        assert_eq!(to_mac(Duration::from_secs(u32::MAX as u64 * 2)).0, i32::MAX);
+    }
+
     #[ignore = "https://github.com/libimobiledevice/libplist/issues/264"]
     #[test]
     fn check_unix_mac_date() {
@@ -83,8 +111,7 @@ mod tests {
         let secs = 1546635600 - MAC_EPOCH;
         let usecs = 123456;
 
-        let mac_plist: Plist =
-            unsafe { unsafe_bindings::plist_new_date(secs as i32, usecs) }.into();
+        let mac_plist: Plist = Active::new_date(secs as i32, usecs).into();
 
         assert_eq!(
             unix_plist.get_date_val().unwrap(),
@@ -1,17 +1,19 @@
 // jkcoxson
 
-use std::{ffi::CString, os::raw::c_char};
-
 use log::trace;
 
-use crate::{error::PlistError, unsafe_bindings, Plist, PlistType};
+use crate::{
+    backend::{Active, Backend},
+    error::PlistError,
+    Plist, PlistType,
+};
 
 impl Plist {
     /// Returns a plist with type dictionary
     /// This plist is empty
     pub fn new_dict() -> Plist {
         trace!("Generating new dictionary plist");
-        unsafe { unsafe_bindings::plist_new_dict() }.into()
+        Active::new_dict().into()
     }
     /// Returns the number of items contained in the plist dictionary
     pub fn dict_get_size(&self) -> Result<u32, PlistError> {
@@ -19,87 +21,77 @@ impl Plist {
             return Err(PlistError::InvalidArg);
         }
         trace!("Getting dict size");
-        Ok(unsafe { unsafe_bindings::plist_dict_get_size(self.plist_t) })
+        Active::dict_len(&self.plist_t)
     }
-    /// Get the key associated with the item
+    /// Get the key this item is stored under in the dictionary holding it
     pub fn dict_get_item_key(&self) -> Result<String, PlistError> {
+        trace!("Getting dict item key for {}", self.id);
+        Active::dict_item_key(&self.plist_t)
+    }
+    /// Returns the keys of the dictionary in the order they are stored
+    /// Unlike iterating, this does not take ownership of the plist
+    pub fn dict_get_keys(&self) -> Result<Vec<String>, PlistError> {
         if self.plist_type != PlistType::Dictionary {
             return Err(PlistError::InvalidArg);
         }
-        let mut key = std::ptr::null_mut();
-        trace!("Getting dict item key for {}", self.id);
-        unsafe { unsafe_bindings::plist_dict_get_item_key(self.plist_t, &mut key) };
-        trace!("Converting key to string");
-        let key = unsafe { std::ffi::CStr::from_ptr(key).to_string_lossy().into_owned() };
-        Ok(key)
+        trace!("Getting dict keys for {}", self.id);
+        Active::dict_keys(&self.plist_t)
     }
     /// Get the item associated with the key
     pub fn dict_get_item(&self, key: &str) -> Result<Plist, PlistError> {
         if self.plist_type != PlistType::Dictionary {
             return Err(PlistError::InvalidArg);
         }
-        let key_c_string = CString::new(key).unwrap();
         trace!("Getting dict item for {}", self.id);
-        let mut item: Plist =
-            unsafe { unsafe_bindings::plist_dict_get_item(self.plist_t, key_c_string.as_ptr()) }
-                .into();
+        // A missing key gives a null plist, like the C library
+        let mut item: Plist = Active::dict_get(&self.plist_t, key)?
+            .unwrap_or(std::ptr::null_mut())
+            .into();
         item.false_drop = true;
         Ok(item)
     }
     /// Get the key associated with self within a dictionary
+    /// The key is a copy, so changing it doesn't change the dictionary
     pub fn dict_item_get_key(&self) -> Result<Plist, PlistError> {
         trace!("Getting dict item key");
-        Ok(unsafe { unsafe_bindings::plist_dict_item_get_key(self.plist_t) }.into())
+        Ok(Active::dict_item_key_node(&self.plist_t).into())
     }
     pub fn dict_set_item(&mut self, key: &str, item: Plist) -> Result<(), PlistError> {
-        let key = CString::new(key).unwrap();
         if self.plist_type != PlistType::Dictionary {
             return Err(PlistError::InvalidArg);
         }
         trace!("Setting dict item");
-        unsafe { unsafe_bindings::plist_dict_set_item(self.plist_t, key.as_ptr(), item.plist_t) }
+        Active::dict_set(&mut self.plist_t, key, item.plist_t)?;
         item.false_drop();
         Ok(())
     }
     /// Inserts a new item into the dictionary
     /// The item must also be a plist
     pub fn dict_insert_item(&mut self, key: &str, item: Plist) -> Result<(), PlistError> {
-        let key = CString::new(key).unwrap();
         if self.plist_type != PlistType::Dictionary {
             return Err(PlistError::InvalidArg);
         }
         trace!("Inserting dict item");
-        unsafe {
-            unsafe_bindings::plist_dict_set_item(
-                self.plist_t,
-                key.as_ptr() as *const c_char,
-                item.plist_t,
-            )
-        }
+        Active::dict_set(&mut self.plist_t, key, item.plist_t)?;
         item.false_drop();
         Ok(())
     }
     /// Removes an item from the dictionary with a given key
     pub fn dict_remove_item(&self, key: &str) -> Result<(), PlistError> {
-        let key = CString::new(key).unwrap();
         if self.plist_type != PlistType::Dictionary {
             return Err(PlistError::InvalidArg);
         }
         trace!("Removing dict item");
-        unsafe {
-            unsafe_bindings::plist_dict_remove_item(self.plist_t, key.as_ptr() as *const c_char)
-        }
-        Ok(())
+        Active::dict_remove(&mut { self.plist_t }, key)
     }
     /// Merges a dictionary into the current dictionary
+    /// The items are copied, replacing any with the same key
     pub fn dict_merge(&mut self, dict: Plist) -> Result<(), PlistError> {
         if self.plist_type != PlistType::Dictionary {
             return Err(PlistError::InvalidArg);
         }
         trace!("Merging dict");
-        unsafe { unsafe_bindings::plist_dict_merge(&mut self.plist_t, dict.plist_t) }
-        dict.false_drop();
-        Ok(())
+        Active::dict_merge(&mut self.plist_t, &dict.plist_t)
     }
 }
 
@@ -113,6 +105,6 @@ mod tests {
         let mut p = Plist::new_dict();
         p.dict_set_item("b", b).unwrap();
         let b = p.dict_get_item("b").unwrap();
-        assert_eq!(b.get_bool_val().unwrap(), false);
+        assert!(!b.get_bool_val().unwrap());
     }
 }
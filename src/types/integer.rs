@@ -2,30 +2,50 @@
 
 use log::trace;
 
-use crate::{error::PlistError, unsafe_bindings, Plist, PlistType};
+use crate::{
+    backend::{Active, Backend},
+    error::PlistError,
+    Plist, PlistType,
+};
 
 impl Plist {
     /// Creates a new plist with the type of an integer
     pub fn new_uint(uint: u64) -> Plist {
         trace!("Generating new uint plist");
-        unsafe { unsafe_bindings::plist_new_uint(uint) }.into()
+        Active::new_uint(uint).into()
+    }
+    /// Creates a new plist with the type of an integer from a signed value
+    pub fn new_int(int: i64) -> Plist {
+        trace!("Generating new int plist");
+        Active::new_int(int).into()
     }
     /// Sets the plist as type integer with the given value
     pub fn set_uint_val(&self, val: u64) {
         trace!("Setting uint value");
-        unsafe { unsafe_bindings::plist_set_uint_val(self.plist_t, val) }
+        Active::set_uint(&mut { self.plist_t }, val)
     }
     /// Returns the value of the integer
     pub fn get_uint_val(&self) -> Result<u64, PlistError> {
         if self.plist_type != PlistType::Integer {
             return Err(PlistError::InvalidArg);
         }
-        let mut val = unsafe { std::mem::zeroed() };
         trace!("Getting uint value");
-        Ok(unsafe {
-            unsafe_bindings::plist_get_uint_val(self.plist_t, &mut val);
-            val
-        })
+        Active::get_uint(&self.plist_t)
+    }
+    /// Returns the value of the integer with its sign
+    /// Integers can hold any u64 or i64, so the result is wide enough for both
+    pub fn get_int_val(&self) -> Result<i128, PlistError> {
+        if self.plist_type != PlistType::Integer {
+            return Err(PlistError::InvalidArg);
+        }
+        trace!("Getting int value");
+        Active::get_int(&self.plist_t)
+    }
+}
+
+impl From<i64> for Plist {
+    fn from(val: i64) -> Self {
+        Plist::new_int(val)
     }
 }
 
@@ -101,7 +121,17 @@ mod tests {
     #[test]
     fn int_test() {
         let p = Plist::new_uint(123412340987);
-        p.set_uint_val(098709781234);
-        assert_eq!(p.get_uint_val().unwrap(), 098709781234);
+        p.set_uint_val(98709781234);
+        assert_eq!(p.get_uint_val().unwrap(), 98709781234);
+    }
+
+    #[test]
+    fn signed_int_test() {
+        let p = Plist::new_int(-42);
+        assert_eq!(p.get_int_val().unwrap(), -42);
+        assert_eq!(p.get_display_value().unwrap(), "-42");
+        let p = Plist::new_uint(u64::MAX);
+        assert_eq!(p.get_int_val().unwrap(), u64::MAX as i128);
+        assert_eq!(p.get_display_value().unwrap(), u64::MAX.to_string());
     }
 }
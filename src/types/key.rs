@@ -1,10 +1,12 @@
 // jkcoxson
 
-use std::{ffi::CString, os::raw::c_char};
-
 use log::trace;
 
-use crate::{error::PlistError, unsafe_bindings, Plist, PlistType};
+use crate::{
+    backend::{Active, Backend},
+    error::PlistError,
+    Plist, PlistType,
+};
 
 impl Plist {
     /// Gets the key plist value
@@ -13,19 +15,14 @@ impl Plist {
         if self.plist_type != PlistType::Key {
             return Err(PlistError::InvalidArg);
         }
-        let mut key = std::ptr::null_mut();
         trace!("Getting key value");
-        unsafe { unsafe_bindings::plist_get_key_val(self.plist_t, &mut key) };
-        trace!("Converting key to string");
-        let key = unsafe { std::ffi::CStr::from_ptr(key).to_string_lossy().into_owned() };
-        Ok(key)
+        Active::get_key(&self.plist_t)
     }
 
     /// Sets the key plist value
     /// Current uses of this are unknown
-    pub fn set_key_val(&self, key: &str) {
-        let key = CString::new(key).unwrap();
+    pub fn set_key_val(&self, key: &str) -> Result<(), PlistError> {
         trace!("Setting key value");
-        unsafe { unsafe_bindings::plist_set_key_val(self.plist_t, key.as_ptr() as *const c_char) }
+        Active::set_key(&mut { self.plist_t }, key)
     }
 }
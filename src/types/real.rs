@@ -2,30 +2,30 @@
 
 use log::trace;
 
-use crate::{error::PlistError, unsafe_bindings, Plist, PlistType};
+use crate::{
+    backend::{Active, Backend},
+    error::PlistError,
+    Plist, PlistType,
+};
 
 impl Plist {
     /// Creates a new plist with type float
     pub fn new_real(real: f64) -> Plist {
         trace!("Generating new float plist");
-        unsafe { unsafe_bindings::plist_new_real(real) }.into()
+        Active::new_real(real).into()
     }
     /// Returns the value of the float
     pub fn get_real_val(&self) -> Result<f64, PlistError> {
         if self.plist_type != PlistType::Real {
             return Err(PlistError::InvalidArg);
         }
-        let mut val = unsafe { std::mem::zeroed() };
         trace!("Getting float value");
-        Ok(unsafe {
-            unsafe_bindings::plist_get_real_val(self.plist_t, &mut val);
-            val
-        })
+        Active::get_real(&self.plist_t)
     }
     /// Sets a plist to type float with the given value
     pub fn set_real_val(&self, val: f64) -> Result<(), PlistError> {
         trace!("Setting float value");
-        unsafe { unsafe_bindings::plist_set_real_val(self.plist_t, val) }
+        Active::set_real(&mut { self.plist_t }, val);
         Ok(())
     }
 }
@@ -49,7 +49,7 @@ mod tests {
 
     #[test]
     fn real_test() {
-        let p = Plist::new_real(3.1415926);
+        let p = Plist::new_real(2.5);
         p.set_real_val(1234.098765).unwrap();
         assert_eq!(p.get_real_val().unwrap(), 1234.098765)
     }
@@ -1,53 +1,45 @@
 // jkcoxson
 
-use std::{ffi::CString, os::raw::c_char};
+#[cfg(not(feature = "pure-rust"))]
+use std::os::raw::c_char;
 
 use log::trace;
 
-use crate::{error::PlistError, unsafe_bindings, Plist, PlistType};
+#[cfg(not(feature = "pure-rust"))]
+use crate::unsafe_bindings;
+use crate::{
+    backend::{Active, Backend},
+    error::PlistError,
+    Plist, PlistType,
+};
 
 impl Plist {
     /// Creates a new plist with type string
     pub fn new_string(string: &str) -> Plist {
         trace!("Generating new string plist");
-        let string = match CString::new(string) {
-            Ok(s) => s,
-            Err(_) => {
-                panic!("Could not convert string to CString");
-            }
-        };
-        unsafe { unsafe_bindings::plist_new_string(string.as_ptr() as *const c_char) }.into()
+        Active::new_string(string).into()
     }
     /// Returns the value of the string
     pub fn get_string_val(&self) -> Result<String, PlistError> {
         if self.plist_type != PlistType::String {
             return Err(PlistError::InvalidArg);
         }
-        let mut val = std::ptr::null_mut();
         trace!("Getting string value");
-        unsafe { unsafe_bindings::plist_get_string_val(self.plist_t, &mut val) };
-        trace!("Converting cstring to string");
-        let val = unsafe {
-            std::ffi::CString::from_raw(val)
-                .to_str()
-                .unwrap()
-                .to_string()
-        };
-        Ok(val)
+        Active::get_string(&self.plist_t)
     }
     /// Returns a C pointer to a CString containing the value of the string
     /// # Safety
     /// Don't be stupid
+    /// Only available when libplist backs the plist
+    #[cfg(not(feature = "pure-rust"))]
     pub unsafe fn get_string_ptr(&self) -> *const c_char {
         unsafe_bindings::plist_get_string_ptr(self.plist_t, std::ptr::null_mut())
     }
     /// Sets a plist to type string with the given value
-    pub fn set_string_val(&self, val: &str) {
-        let val = CString::new(val).unwrap();
+    /// Fails if the value holds a NUL byte, which libplist can't store
+    pub fn set_string_val(&self, val: &str) -> Result<(), PlistError> {
         trace!("Setting string value");
-        unsafe {
-            unsafe_bindings::plist_set_string_val(self.plist_t, val.as_ptr() as *const c_char)
-        }
+        Active::set_string(&mut { self.plist_t }, val)
     }
 }
 
@@ -76,7 +68,11 @@ mod tests {
     #[test]
     fn string_test() {
         let p = Plist::new_string("this is a string");
-        p.set_string_val("this is a different string");
-        assert_eq!(p.get_string_val().unwrap(), "this is a different string")
+        p.set_string_val("this is a different string").unwrap();
+        assert_eq!(p.get_string_val().unwrap(), "this is a different string");
+        assert!(matches!(
+            p.set_string_val("nul \0 byte"),
+            Err(PlistError::InvalidArg)
+        ));
     }
 }
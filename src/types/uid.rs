@@ -1,28 +1,30 @@
 // jkcoxson
 
-use crate::{debug, unsafe_bindings, Plist, PlistType};
+use log::trace;
+
+use crate::{
+    backend::{Active, Backend},
+    error::PlistError,
+    Plist, PlistType,
+};
 
 impl Plist {
     /// Creates a new plist with type uid
     pub fn new_uid(uid: u64) -> Plist {
-        debug!("Generating new plist uid");
-        unsafe { unsafe_bindings::plist_new_uid(uid) }.into()
+        trace!("Generating new plist uid");
+        Active::new_uid(uid).into()
     }
     /// Returns the value of the uid
-    pub fn get_uid_val(&self) -> Result<u64, ()> {
+    pub fn get_uid_val(&self) -> Result<u64, PlistError> {
         if self.plist_type != PlistType::Uid {
-            return Err(());
-        }
-        let mut val = unsafe { std::mem::zeroed() };
-        debug!("Getting uid value");
-        unsafe {
-            unsafe_bindings::plist_get_uid_val(self.plist_t, &mut val);
+            return Err(PlistError::InvalidArg);
         }
-        Ok(val)
+        trace!("Getting uid value");
+        Active::get_uid(&self.plist_t)
     }
     /// Sets a plist to type uid with the given value
     pub fn set_uid_val(&self, val: u64) {
-        debug!("Setting uid value");
-        unsafe { unsafe_bindings::plist_set_uid_val(self.plist_t, val) }
+        trace!("Setting uid value");
+        Active::set_uid(&mut { self.plist_t }, val)
     }
 }
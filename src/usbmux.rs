@@ -0,0 +1,461 @@
+// jkcoxson
+
+use std::io::{Read, Write};
+
+use log::{trace, warn};
+
+use crate::error::PlistError;
+use crate::limits::{Budget, ParseLimits};
+use crate::{Plist, PlistValue};
+
+/// The length in bytes of the header in front of every message
+pub const HEADER_LEN: usize = 16;
+/// The protocol version for plist messages
+pub const PLIST_VERSION: u32 = 1;
+/// The message type for plist messages
+pub const PLIST_MESSAGE_TYPE: u32 = 8;
+/// The client name sent with every request
+const CLIENT_NAME: &str = "plist_plus";
+/// The version of libusbmuxd this speaks the protocol of
+const LIBUSBMUX_VERSION: i128 = 3;
+
+/// The header in front of every usbmuxd message
+/// All fields are little-endian on the wire.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Header {
+    /// The length of the whole message, including the header
+    pub length: u32,
+    pub version: u32,
+    pub message_type: u32,
+    /// Chosen by the client and echoed back in the reply
+    pub tag: u32,
+}
+
+impl Header {
+    /// Reads a header from its bytes
+    pub fn parse(bytes: [u8; HEADER_LEN]) -> Header {
+        let field = |i: usize| u32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+        Header {
+            length: field(0),
+            version: field(1),
+            message_type: field(2),
+            tag: field(3),
+        }
+    }
+
+    /// Returns the bytes of the header
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        for (i, field) in [self.length, self.version, self.message_type, self.tag]
+            .iter()
+            .enumerate()
+        {
+            bytes[i * 4..i * 4 + 4].copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// A device known to usbmuxd
+#[derive(PartialEq, Debug, Clone)]
+pub struct Device {
+    pub device_id: u32,
+    /// The dictionary usbmuxd describes the device with,
+    /// holding keys like `SerialNumber`, `ConnectionType` and `ProductID`
+    pub properties: PlistValue,
+}
+
+impl Device {
+    /// Returns the UDID of the device
+    pub fn serial_number(&self) -> Option<&str> {
+        self.properties
+            .dict_get("SerialNumber")
+            .and_then(PlistValue::as_str)
+    }
+
+    fn from_value(value: &PlistValue) -> Result<Device, PlistError> {
+        Ok(Device {
+            device_id: get_u32(value, "DeviceID")?,
+            properties: value
+                .dict_get("Properties")
+                .cloned()
+                .unwrap_or(PlistValue::Dictionary(Vec::new())),
+        })
+    }
+
+    fn to_value(&self) -> PlistValue {
+        PlistValue::Dictionary(vec![
+            message_type("Attached"),
+            (
+                "DeviceID".to_string(),
+                PlistValue::Integer(self.device_id as i128),
+            ),
+            ("Properties".to_string(), self.properties.clone()),
+        ])
+    }
+}
+
+/// A message to or from usbmuxd, the daemon that multiplexes connections to devices
+/// On the wire every message is a `Header` followed by an XML plist.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Message {
+    /// Asks to be told about devices as they attach and detach
+    Listen,
+    /// Asks for a connection to a port on a device
+    /// After a successful `Result` the socket is connected to the device.
+    Connect { device_id: u32, port: u16 },
+    /// Asks for the devices that are attached
+    ListDevices,
+    /// Asks for the pair record of a device by its UDID
+    ReadPairRecord { pair_record_id: String },
+    /// The reply to a request, where 0 means success
+    Result { number: u32 },
+    /// A device was attached
+    Attached(Device),
+    /// A device was detached
+    Detached { device_id: u32 },
+    /// The reply to `ListDevices`
+    DeviceList(Vec<Device>),
+    /// The reply to `ReadPairRecord`, holding the pair record as a plist
+    PairRecord(Vec<u8>),
+}
+
+impl Message {
+    /// Returns the plist body of the message
+    pub fn to_value(&self) -> PlistValue {
+        let mut entries = match self {
+            Message::Listen => vec![message_type("Listen")],
+            Message::Connect { device_id, port } => vec![
+                message_type("Connect"),
+                (
+                    "DeviceID".to_string(),
+                    PlistValue::Integer(*device_id as i128),
+                ),
+                // usbmuxd wants the port in network byte order
+                (
+                    "PortNumber".to_string(),
+                    PlistValue::Integer(port.swap_bytes() as i128),
+                ),
+            ],
+            Message::ListDevices => vec![message_type("ListDevices")],
+            Message::ReadPairRecord { pair_record_id } => vec![
+                message_type("ReadPairRecord"),
+                (
+                    "PairRecordID".to_string(),
+                    PlistValue::String(pair_record_id.clone()),
+                ),
+            ],
+            Message::Result { number } => {
+                return PlistValue::Dictionary(vec![
+                    message_type("Result"),
+                    ("Number".to_string(), PlistValue::Integer(*number as i128)),
+                ])
+            }
+            Message::Attached(device) => return device.to_value(),
+            Message::Detached { device_id } => {
+                return PlistValue::Dictionary(vec![
+                    message_type("Detached"),
+                    (
+                        "DeviceID".to_string(),
+                        PlistValue::Integer(*device_id as i128),
+                    ),
+                ])
+            }
+            Message::DeviceList(devices) => {
+                return PlistValue::Dictionary(vec![(
+                    "DeviceList".to_string(),
+                    PlistValue::Array(devices.iter().map(Device::to_value).collect()),
+                )])
+            }
+            Message::PairRecord(data) => {
+                return PlistValue::Dictionary(vec![(
+                    "PairRecordData".to_string(),
+                    PlistValue::Data(data.clone()),
+                )])
+            }
+        };
+        // Requests say who is asking
+        entries.extend([
+            (
+                "ClientVersionString".to_string(),
+                PlistValue::String(CLIENT_NAME.to_string()),
+            ),
+            (
+                "ProgName".to_string(),
+                PlistValue::String(CLIENT_NAME.to_string()),
+            ),
+            (
+                "kLibUSBMuxVersion".to_string(),
+                PlistValue::Integer(LIBUSBMUX_VERSION),
+            ),
+        ]);
+        PlistValue::Dictionary(entries)
+    }
+
+    /// Reads a message from its plist body
+    pub fn from_value(value: &PlistValue) -> Result<Message, PlistError> {
+        let kind = match value.dict_get("MessageType").and_then(PlistValue::as_str) {
+            Some(kind) => kind,
+            None => {
+                // Replies to ListDevices and ReadPairRecord have no message type
+                if let Some(PlistValue::Array(devices)) = value.dict_get("DeviceList") {
+                    return devices
+                        .iter()
+                        .map(Device::from_value)
+                        .collect::<Result<_, _>>()
+                        .map(Message::DeviceList);
+                }
+                if let Some(PlistValue::Data(data)) = value.dict_get("PairRecordData") {
+                    return Ok(Message::PairRecord(data.clone()));
+                }
+                return Err(malformed("usbmux message has no MessageType"));
+            }
+        };
+        Ok(match kind {
+            "Listen" => Message::Listen,
+            "Connect" => Message::Connect {
+                device_id: get_u32(value, "DeviceID")?,
+                port: match u16::try_from(get_u32(value, "PortNumber")?) {
+                    Ok(port) => port.swap_bytes(),
+                    Err(_) => return Err(malformed("usbmux PortNumber is out of range")),
+                },
+            },
+            "ListDevices" => Message::ListDevices,
+            "ReadPairRecord" => Message::ReadPairRecord {
+                pair_record_id: match value.dict_get("PairRecordID").and_then(PlistValue::as_str) {
+                    Some(id) => id.to_string(),
+                    None => return Err(malformed("usbmux message has no PairRecordID")),
+                },
+            },
+            "Result" => Message::Result {
+                number: get_u32(value, "Number")?,
+            },
+            "Attached" => Message::Attached(Device::from_value(value)?),
+            "Detached" => Message::Detached {
+                device_id: get_u32(value, "DeviceID")?,
+            },
+            kind => return Err(malformed(format!("Unknown usbmux message type {}", kind))),
+        })
+    }
+
+    /// Returns the plist body of the message as a `Plist`
+    pub fn to_plist(&self) -> Plist {
+        self.to_value().into()
+    }
+
+    /// Reads a message from a `Plist` body
+    pub fn from_plist(plist: &Plist) -> Result<Message, PlistError> {
+        Message::from_value(&PlistValue::from(plist))
+    }
+
+    /// Returns the message with its header, ready to be sent
+    pub fn encode(&self, tag: u32) -> Result<Vec<u8>, PlistError> {
+        let body = crate::xml::to_string(&self.to_value())?;
+        let length = match u32::try_from(HEADER_LEN + body.len()) {
+            Ok(length) => length,
+            Err(_) => {
+                warn!("usbmux message is too long");
+                return Err(PlistError::InvalidArg);
+            }
+        };
+        let header = Header {
+            length,
+            version: PLIST_VERSION,
+            message_type: PLIST_MESSAGE_TYPE,
+            tag,
+        };
+        let mut bytes = header.to_bytes().to_vec();
+        bytes.extend(body.into_bytes());
+        Ok(bytes)
+    }
+
+    /// Reads a message and its tag from the bytes of one whole message
+    pub fn decode(bytes: &[u8]) -> Result<(Message, u32), PlistError> {
+        Message::read(bytes, &ParseLimits::default())
+    }
+
+    /// Reads one message and its tag from a stream
+    /// A message longer than `max_total_bytes` is rejected before its body is read.
+    pub fn read(mut reader: impl Read, limits: &ParseLimits) -> Result<(Message, u32), PlistError> {
+        let mut header = [0; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        let header = Header::parse(header);
+        trace!("Reading usbmux message of {} bytes", header.length);
+        if header.version != PLIST_VERSION || header.message_type != PLIST_MESSAGE_TYPE {
+            return Err(malformed(format!(
+                "Unsupported usbmux message version {} type {}",
+                header.version, header.message_type
+            )));
+        }
+        let len = match (header.length as usize).checked_sub(HEADER_LEN) {
+            Some(len) => len,
+            None => return Err(malformed("usbmux message is shorter than its header")),
+        };
+        Budget::new(limits).total_bytes(len)?;
+        let mut body = Vec::new();
+        reader.take(len as u64).read_to_end(&mut body)?;
+        if body.len() != len {
+            warn!("Stream ended in the middle of a usbmux message");
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        let body = match String::from_utf8(body) {
+            Ok(body) => body,
+            Err(_) => return Err(malformed("usbmux message body isn't UTF-8")),
+        };
+        let value = crate::xml::from_str_with_limits(&body, limits)?;
+        Ok((Message::from_value(&value)?, header.tag))
+    }
+
+    /// Writes the message with its header to a stream
+    pub fn write(&self, mut writer: impl Write, tag: u32) -> Result<(), PlistError> {
+        writer.write_all(&self.encode(tag)?)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn message_type(kind: &str) -> (String, PlistValue) {
+    (
+        "MessageType".to_string(),
+        PlistValue::String(kind.to_string()),
+    )
+}
+
+fn get_u32(value: &PlistValue, key: &str) -> Result<u32, PlistError> {
+    match value.dict_get(key) {
+        Some(PlistValue::Integer(i)) => match u32::try_from(*i) {
+            Ok(i) => Ok(i),
+            Err(_) => Err(malformed(format!("usbmux {} is out of range", key))),
+        },
+        _ => Err(malformed(format!("usbmux message has no {}", key))),
+    }
+}
+
+fn malformed(message: impl Into<String>) -> PlistError {
+    let message = message.into();
+    warn!("{}", message);
+    PlistError::parse(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_test() {
+        let header = Header {
+            length: 16 + 300,
+            version: PLIST_VERSION,
+            message_type: PLIST_MESSAGE_TYPE,
+            tag: 7,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[..8], &[0x3c, 0x01, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(Header::parse(bytes), header);
+    }
+
+    #[test]
+    fn message_round_trip_test() {
+        let device = Device {
+            device_id: 3,
+            properties: PlistValue::Dictionary(vec![(
+                "SerialNumber".to_string(),
+                PlistValue::String("00008030-001A".to_string()),
+            )]),
+        };
+        let messages = [
+            Message::Listen,
+            Message::Connect {
+                device_id: 3,
+                port: 62078,
+            },
+            Message::ListDevices,
+            Message::ReadPairRecord {
+                pair_record_id: "00008030-001A".to_string(),
+            },
+            Message::Result { number: 0 },
+            Message::Attached(device.clone()),
+            Message::Detached { device_id: 3 },
+            Message::DeviceList(vec![device]),
+            Message::PairRecord(vec![1, 2, 3]),
+        ];
+        for (tag, message) in messages.iter().enumerate() {
+            let bytes = message.encode(tag as u32).unwrap();
+            assert_eq!(
+                Header::parse(bytes[..16].try_into().unwrap()).length as usize,
+                bytes.len()
+            );
+            assert_eq!(
+                Message::decode(&bytes).unwrap(),
+                (message.clone(), tag as u32)
+            );
+        }
+
+        let connect = Message::Connect {
+            device_id: 1,
+            port: 62078,
+        }
+        .to_value();
+        assert_eq!(
+            connect.dict_get("PortNumber"),
+            Some(&PlistValue::Integer(32498))
+        );
+    }
+
+    #[test]
+    fn decode_malformed_test() {
+        let mut bytes = Message::Listen.encode(0).unwrap();
+        bytes[4] = 0;
+        assert!(Message::decode(&bytes).is_err());
+
+        let mut bytes = Message::Listen.encode(0).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        assert!(matches!(Message::decode(&bytes), Err(PlistError::Io(_))));
+
+        let body = b"<plist><dict><key>MessageType</key><string>Paired</string></dict></plist>";
+        let mut bytes = Header {
+            length: (HEADER_LEN + body.len()) as u32,
+            version: PLIST_VERSION,
+            message_type: PLIST_MESSAGE_TYPE,
+            tag: 0,
+        }
+        .to_bytes()
+        .to_vec();
+        bytes.extend(body);
+        assert!(matches!(
+            Message::decode(&bytes),
+            Err(PlistError::Parse { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_test() {
+        use std::os::unix::net::UnixStream;
+
+        // A stand-in for usbmuxd that answers a Listen and reports a device
+        let (mut client, mut daemon) = UnixStream::pair().unwrap();
+        let daemon = std::thread::spawn(move || {
+            let (message, tag) = Message::read(&mut daemon, &ParseLimits::default()).unwrap();
+            assert_eq!(message, Message::Listen);
+            Message::Result { number: 0 }
+                .write(&mut daemon, tag)
+                .unwrap();
+            Message::Detached { device_id: 9 }
+                .write(&mut daemon, 0)
+                .unwrap();
+        });
+
+        Message::Listen.write(&mut client, 42).unwrap();
+        let limits = ParseLimits::default();
+        assert_eq!(
+            Message::read(&mut client, &limits).unwrap(),
+            (Message::Result { number: 0 }, 42)
+        );
+        assert_eq!(
+            Message::read(&mut client, &limits).unwrap(),
+            (Message::Detached { device_id: 9 }, 0)
+        );
+        daemon.join().unwrap();
+    }
+}
@@ -0,0 +1,319 @@
+// jkcoxson
+
+use std::time::{Duration, SystemTime};
+
+use log::{trace, warn};
+
+use crate::backend::{Active, Backend};
+use crate::error::PlistError;
+use crate::types::date::MAC_EPOCH;
+use crate::{Plist, PlistType};
+
+/// An owned plist tree that lives entirely in Rust memory
+/// Unlike `Plist`, this doesn't need the C library, so it can be produced by the native parsers
+/// and converted to a `Plist` when the libplist API is needed.
+#[derive(PartialEq, Debug, Clone)]
+pub enum PlistValue {
+    Boolean(bool),
+    /// Integers can be stored as signed 64 bit or unsigned 64 bit values
+    Integer(i128),
+    Real(f64),
+    Date(SystemTime),
+    Data(Vec<u8>),
+    String(String),
+    Array(Vec<PlistValue>),
+    /// Entries are kept in the order they were inserted
+    Dictionary(Vec<(String, PlistValue)>),
+    Uid(u64),
+    Null,
+}
+
+impl PlistValue {
+    /// Returns the type of the value
+    pub fn plist_type(&self) -> PlistType {
+        match self {
+            PlistValue::Boolean(_) => PlistType::Boolean,
+            PlistValue::Integer(_) => PlistType::Integer,
+            PlistValue::Real(_) => PlistType::Real,
+            PlistValue::Date(_) => PlistType::Date,
+            PlistValue::Data(_) => PlistType::Data,
+            PlistValue::String(_) => PlistType::String,
+            PlistValue::Array(_) => PlistType::Array,
+            PlistValue::Dictionary(_) => PlistType::Dictionary,
+            PlistValue::Uid(_) => PlistType::Uid,
+            PlistValue::Null => PlistType::None,
+        }
+    }
+    /// Returns the value with the given key if this is a dictionary
+    pub fn dict_get(&self, key: &str) -> Option<&PlistValue> {
+        match self {
+            PlistValue::Dictionary(entries) => {
+                entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            _ => None,
+        }
+    }
+    /// Returns the item at the given index if this is an array
+    pub fn array_get(&self, index: usize) -> Option<&PlistValue> {
+        match self {
+            PlistValue::Array(items) => items.get(index),
+            _ => None,
+        }
+    }
+    /// Returns the string if this is a string
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            PlistValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+    /// Returns the boolean if this is a boolean
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            PlistValue::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+    /// Returns the integer if this is an integer
+    pub fn as_integer(&self) -> Option<i128> {
+        match self {
+            PlistValue::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+    /// Returns the bytes if this is data
+    pub fn as_data(&self) -> Option<&[u8]> {
+        match self {
+            PlistValue::Data(data) => Some(data),
+            _ => None,
+        }
+    }
+    /// Returns the items if this is an array
+    pub fn as_array(&self) -> Option<&[PlistValue]> {
+        match self {
+            PlistValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+    /// Sets the value of a key if this is a dictionary
+    /// An existing key keeps its place, a new one is added at the end.
+    /// Returns the value that was replaced.
+    pub fn dict_set(&mut self, key: &str, value: PlistValue) -> Option<PlistValue> {
+        match self {
+            PlistValue::Dictionary(entries) => match entries.iter_mut().find(|(k, _)| k == key) {
+                Some((_, v)) => Some(std::mem::replace(v, value)),
+                None => {
+                    entries.push((key.to_string(), value));
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+    /// Removes a key if this is a dictionary, returning its value
+    pub fn dict_remove(&mut self, key: &str) -> Option<PlistValue> {
+        match self {
+            PlistValue::Dictionary(entries) => {
+                let index = entries.iter().position(|(k, _)| k == key)?;
+                Some(entries.remove(index).1)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Converts seconds since the Mac Epoch into a SystemTime
+/// NaN, infinities and dates SystemTime can't hold are a parse error, since they come from input
+pub(crate) fn mac_secs_to_system_time(secs: f64) -> Result<SystemTime, PlistError> {
+    let unix = secs + MAC_EPOCH as f64;
+    let time = match Duration::try_from_secs_f64(unix.abs()) {
+        Ok(since) if unix >= 0.0 => SystemTime::UNIX_EPOCH.checked_add(since),
+        Ok(before) => SystemTime::UNIX_EPOCH.checked_sub(before),
+        Err(_) => None,
+    };
+    match time {
+        Some(time) => Ok(time),
+        None => {
+            warn!("Date {} is out of range", secs);
+            Err(PlistError::parse(format!("Date {} is out of range", secs)))
+        }
+    }
+}
+
+/// Converts a SystemTime into seconds since the Mac Epoch
+pub(crate) fn system_time_to_mac_secs(time: SystemTime) -> f64 {
+    let secs = match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => since.as_secs_f64(),
+        Err(before) => -before.duration().as_secs_f64(),
+    };
+    secs - MAC_EPOCH as f64
+}
+
+/// Cuts a string at its first NUL byte, which libplist can't store
+/// libplist does the same when it reads a binary plist holding one
+fn until_nul(s: &str) -> &str {
+    s.split('\0').next().unwrap_or_default()
+}
+
+impl From<&Plist> for PlistValue {
+    fn from(plist: &Plist) -> Self {
+        trace!("Converting plist to value");
+        match plist.plist_type {
+            PlistType::Boolean => PlistValue::Boolean(plist.get_bool_val().unwrap_or_default()),
+            PlistType::Integer => PlistValue::Integer(plist.get_int_val().unwrap_or_default()),
+            PlistType::Real => PlistValue::Real(plist.get_real_val().unwrap_or_default()),
+            PlistType::Date => {
+                let (sec, usec) = Active::get_date(&plist.plist_t).unwrap_or_default();
+                // Every date the backends can store is in range
+                PlistValue::Date(
+                    mac_secs_to_system_time(sec as f64 + usec as f64 / 1000000.0)
+                        .unwrap_or(SystemTime::UNIX_EPOCH),
+                )
+            }
+            PlistType::Data => PlistValue::Data(
+                plist
+                    .get_data_val()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|b| b as u8)
+                    .collect(),
+            ),
+            PlistType::String => PlistValue::String(plist.get_string_val().unwrap_or_default()),
+            PlistType::Key => PlistValue::String(plist.get_key_val().unwrap_or_default()),
+            PlistType::Array => {
+                PlistValue::Array(plist.array_items().iter().map(PlistValue::from).collect())
+            }
+            PlistType::Dictionary => PlistValue::Dictionary(
+                plist
+                    .dict_get_keys()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|key| {
+                        let item = plist.dict_get_item(&key).unwrap();
+                        (key, PlistValue::from(&item))
+                    })
+                    .collect(),
+            ),
+            PlistType::Uid => PlistValue::Uid(plist.get_uid_val().unwrap_or_default()),
+            PlistType::Unknown | PlistType::None => PlistValue::Null,
+        }
+    }
+}
+
+impl From<Plist> for PlistValue {
+    fn from(plist: Plist) -> Self {
+        PlistValue::from(&plist)
+    }
+}
+
+impl From<&PlistValue> for Plist {
+    fn from(value: &PlistValue) -> Self {
+        trace!("Converting value to plist");
+        match value {
+            PlistValue::Boolean(b) => Plist::new_bool(*b),
+            PlistValue::Integer(i) if *i < 0 => Plist::new_int(*i as i64),
+            PlistValue::Integer(i) => Plist::new_uint(*i as u64),
+            PlistValue::Real(r) => Plist::new_real(*r),
+            PlistValue::Date(time) => {
+                let secs = system_time_to_mac_secs(*time);
+                let sec = secs.floor();
+                let usec = ((secs - sec) * 1000000.0).round();
+                Active::new_date(sec as i32, usec as i32).into()
+            }
+            PlistValue::Data(data) => Plist::new_data(data),
+            PlistValue::String(s) => Plist::new_string(until_nul(s)),
+            PlistValue::Array(items) => {
+                let mut array = Plist::new_array();
+                for item in items {
+                    array.array_append_item(item.into()).unwrap();
+                }
+                array
+            }
+            PlistValue::Dictionary(entries) => {
+                let mut dict = Plist::new_dict();
+                for (key, item) in entries {
+                    dict.dict_set_item(until_nul(key), item.into()).unwrap();
+                }
+                dict
+            }
+            PlistValue::Uid(uid) => Plist::new_uid(*uid),
+            PlistValue::Null => Active::new_null().into(),
+        }
+    }
+}
+
+impl From<PlistValue> for Plist {
+    fn from(value: PlistValue) -> Self {
+        Plist::from(&value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_round_trip_test() {
+        let value = PlistValue::Dictionary(vec![
+            ("b".to_string(), PlistValue::Boolean(true)),
+            ("n".to_string(), PlistValue::Integer(-5)),
+            (
+                "a".to_string(),
+                PlistValue::Array(vec![
+                    PlistValue::String("x".to_string()),
+                    PlistValue::Data(vec![1, 2, 3]),
+                ]),
+            ),
+        ]);
+        let plist: Plist = (&value).into();
+        assert_eq!(PlistValue::from(&plist), value);
+    }
+
+    #[test]
+    fn dict_set_test() {
+        let mut value = PlistValue::Dictionary(vec![
+            ("a".to_string(), PlistValue::Integer(1)),
+            ("b".to_string(), PlistValue::Integer(2)),
+        ]);
+        assert_eq!(
+            value.dict_set("a", PlistValue::Boolean(true)),
+            Some(PlistValue::Integer(1))
+        );
+        assert_eq!(value.dict_set("c", PlistValue::Null), None);
+        assert_eq!(value.dict_remove("b"), Some(PlistValue::Integer(2)));
+        assert_eq!(
+            value,
+            PlistValue::Dictionary(vec![
+                ("a".to_string(), PlistValue::Boolean(true)),
+                ("c".to_string(), PlistValue::Null),
+            ])
+        );
+        assert_eq!(
+            value.dict_get("a").and_then(PlistValue::as_bool),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn mac_epoch_test() {
+        let time = mac_secs_to_system_time(0.0).unwrap();
+        assert_eq!(
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            MAC_EPOCH
+        );
+        assert_eq!(system_time_to_mac_secs(time), 0.0);
+        assert_eq!(
+            mac_secs_to_system_time(-(MAC_EPOCH as f64) - 1.5).unwrap(),
+            SystemTime::UNIX_EPOCH - Duration::from_millis(1500)
+        );
+
+        for secs in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e300, -1e300] {
+            assert!(matches!(
+                mac_secs_to_system_time(secs),
+                Err(PlistError::Parse { .. })
+            ));
+        }
+    }
+}
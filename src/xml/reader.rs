@@ -0,0 +1,524 @@
+// jkcoxson
+
+use std::time::{Duration, SystemTime};
+
+use log::{trace, warn};
+
+use crate::error::PlistError;
+use crate::limits::{Budget, ParseLimits};
+use crate::value::PlistValue;
+
+use super::{base64, days_from_civil};
+
+/// Parses an XML plist into a value without using the C library
+/// Only the nesting depth is limited, so use `from_str_with_limits` for input that can't be trusted
+pub fn from_str(xml: &str) -> Result<PlistValue, PlistError> {
+    from_str_with_limits(xml, &ParseLimits::depth_only())
+}
+
+/// Parses an XML plist, rejecting it as soon as it goes over the limits
+pub fn from_str_with_limits(xml: &str, limits: &ParseLimits) -> Result<PlistValue, PlistError> {
+    trace!("Parsing XML plist natively");
+    let budget = Budget::new(limits);
+    budget.total_bytes(xml.len())?;
+    let mut reader = Reader {
+        text: xml,
+        pos: 0,
+        budget,
+    };
+    reader.skip_misc()?;
+    let tag = reader.read_tag()?;
+    let value = if tag.name == "plist" {
+        if tag.kind != TagKind::Open {
+            return Err(reader.error("Expected a value inside <plist>"));
+        }
+        reader.skip_misc()?;
+        let inner = reader.read_tag()?;
+        let value = reader.read_value(inner)?;
+        reader.skip_misc()?;
+        let close = reader.read_tag()?;
+        if close.name != "plist" || close.kind != TagKind::Close {
+            return Err(reader.error_at(close.start, "Expected </plist>"));
+        }
+        value
+    } else {
+        reader.read_value(tag)?
+    };
+    reader.skip_misc()?;
+    if reader.pos < reader.text.len() && !reader.rest().trim_end_matches('\0').is_empty() {
+        return Err(reader.error("Unexpected content after the root element"));
+    }
+    Ok(value)
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum TagKind {
+    Open,
+    Close,
+    Empty,
+}
+
+#[derive(Debug)]
+struct Tag<'a> {
+    name: &'a str,
+    kind: TagKind,
+    /// The position of the '<'
+    start: usize,
+}
+
+struct Reader<'a> {
+    text: &'a str,
+    pos: usize,
+    budget: Budget,
+}
+
+impl<'a> Reader<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> PlistError {
+        self.error_at(self.pos, message)
+    }
+
+    fn error_at(&self, pos: usize, message: &str) -> PlistError {
+        warn!("{} at byte {}", message, pos);
+        PlistError::parse_at(self.text, pos, message)
+    }
+
+    /// Skips to just after the given pattern
+    fn skip_past(&mut self, pattern: &str) -> Result<(), PlistError> {
+        match self.rest().find(pattern) {
+            Some(index) => {
+                self.pos += index + pattern.len();
+                Ok(())
+            }
+            None => Err(self.error(&format!("Expected {}", pattern))),
+        }
+    }
+
+    /// Skips whitespace, comments, processing instructions and the DOCTYPE
+    fn skip_misc(&mut self) -> Result<(), PlistError> {
+        loop {
+            let trimmed = self.rest().trim_start();
+            self.pos = self.text.len() - trimmed.len();
+            if trimmed.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if trimmed.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if trimmed.starts_with("<!DOCTYPE") {
+                // The DOCTYPE may contain an internal subset in brackets
+                let mut depth = 0;
+                let mut end = None;
+                for (i, c) in trimmed.char_indices() {
+                    match c {
+                        '[' => depth += 1,
+                        ']' => depth -= 1,
+                        '>' if depth == 0 => {
+                            end = Some(i);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                match end {
+                    Some(end) => self.pos += end + 1,
+                    None => return Err(self.error("Unterminated DOCTYPE")),
+                }
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn read_tag(&mut self) -> Result<Tag<'a>, PlistError> {
+        let rest = self.rest();
+        if !rest.starts_with('<') {
+            return Err(self.error("Expected a tag"));
+        }
+        let closing = rest.starts_with("</");
+        let name_start = if closing { 2 } else { 1 };
+        let name_len = rest[name_start..]
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .ok_or_else(|| self.error("Unterminated tag"))?;
+        let name = &rest[name_start..name_start + name_len];
+        if name.is_empty() {
+            return Err(self.error("Expected a tag name"));
+        }
+        // Attributes are ignored, but quoted values may contain '>'
+        let mut quote = None;
+        let mut end = None;
+        for (i, c) in rest[name_start + name_len..].char_indices() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"') | (None, '\'') => quote = Some(c),
+                (None, '>') => {
+                    end = Some(name_start + name_len + i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let end = end.ok_or_else(|| self.error("Unterminated tag"))?;
+        let kind = if closing {
+            TagKind::Close
+        } else if rest[..end].ends_with('/') {
+            TagKind::Empty
+        } else {
+            TagKind::Open
+        };
+        let start = self.pos;
+        self.pos += end + 1;
+        Ok(Tag { name, kind, start })
+    }
+
+    /// Reads the text content of a leaf element up to its closing tag and decodes entities
+    fn read_text(&mut self, tag: &Tag) -> Result<String, PlistError> {
+        if tag.kind == TagKind::Empty {
+            return Ok(String::new());
+        }
+        let mut text = String::new();
+        loop {
+            let rest = self.rest();
+            let next = rest
+                .find(['<', '&'])
+                .ok_or_else(|| self.error(&format!("Expected </{}>", tag.name)))?;
+            text.push_str(&rest[..next]);
+            self.pos += next;
+            let rest = self.rest();
+            if rest.starts_with("<![CDATA[") {
+                let end = rest
+                    .find("]]>")
+                    .ok_or_else(|| self.error("Unterminated CDATA section"))?;
+                text.push_str(&rest[9..end]);
+                self.pos += end + 3;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with('&') {
+                let end = rest
+                    .find(';')
+                    .ok_or_else(|| self.error("Unterminated entity"))?;
+                text.push(self.decode_entity(&rest[1..end])?);
+                self.pos += end + 1;
+            } else {
+                let close = self.read_tag()?;
+                if close.kind != TagKind::Close || close.name != tag.name {
+                    return Err(self.error_at(close.start, &format!("Expected </{}>", tag.name)));
+                }
+                return Ok(text);
+            }
+        }
+    }
+
+    fn decode_entity(&self, entity: &str) -> Result<char, PlistError> {
+        let code = match entity {
+            "lt" => return Ok('<'),
+            "gt" => return Ok('>'),
+            "amp" => return Ok('&'),
+            "quot" => return Ok('"'),
+            "apos" => return Ok('\''),
+            e if e.starts_with("#x") || e.starts_with("#X") => {
+                u32::from_str_radix(&e[2..], 16).ok()
+            }
+            e if e.starts_with('#') => e[1..].parse().ok(),
+            _ => None,
+        };
+        code.and_then(char::from_u32)
+            .ok_or_else(|| self.error(&format!("Unknown entity &{};", entity)))
+    }
+
+    fn read_value(&mut self, tag: Tag) -> Result<PlistValue, PlistError> {
+        if tag.kind == TagKind::Close {
+            return Err(self.error_at(tag.start, &format!("Unexpected </{}>", tag.name)));
+        }
+        self.budget.node()?;
+        // Errors in the contents of a leaf point at the start of the contents
+        let contents = self.pos;
+        Ok(match tag.name {
+            "true" | "false" => {
+                if tag.kind == TagKind::Open {
+                    self.read_text(&tag)?;
+                }
+                PlistValue::Boolean(tag.name == "true")
+            }
+            "string" => {
+                let text = self.read_text(&tag)?;
+                self.budget.string(text.len())?;
+                PlistValue::String(text)
+            }
+            "integer" => {
+                let text = self.read_text(&tag)?;
+                PlistValue::Integer(
+                    parse_integer(text.trim())
+                        .ok_or_else(|| self.error_at(contents, "Invalid integer"))?,
+                )
+            }
+            "real" => {
+                let text = self.read_text(&tag)?;
+                PlistValue::Real(
+                    parse_real(text.trim())
+                        .ok_or_else(|| self.error_at(contents, "Invalid real"))?,
+                )
+            }
+            "date" => {
+                let text = self.read_text(&tag)?;
+                PlistValue::Date(
+                    parse_date(text.trim())
+                        .ok_or_else(|| self.error_at(contents, "Invalid date"))?,
+                )
+            }
+            "data" => {
+                let text = self.read_text(&tag)?;
+                let data = base64::decode(&text)
+                    .ok_or_else(|| self.error_at(contents, "Invalid base64"))?;
+                self.budget.data(data.len())?;
+                PlistValue::Data(data)
+            }
+            "array" => {
+                let mut items = Vec::new();
+                if tag.kind == TagKind::Open {
+                    self.budget.enter()?;
+                    loop {
+                        self.skip_misc()?;
+                        let next = self.read_tag()?;
+                        if next.kind == TagKind::Close && next.name == "array" {
+                            break;
+                        }
+                        items.push(self.read_value(next)?);
+                    }
+                    self.budget.leave();
+                }
+                PlistValue::Array(items)
+            }
+            "dict" => {
+                let mut entries = Vec::new();
+                if tag.kind == TagKind::Open {
+                    self.budget.enter()?;
+                    loop {
+                        self.skip_misc()?;
+                        let key_tag = self.read_tag()?;
+                        if key_tag.kind == TagKind::Close && key_tag.name == "dict" {
+                            break;
+                        }
+                        if key_tag.name != "key" {
+                            return Err(self.error_at(key_tag.start, "Expected <key>"));
+                        }
+                        let key = self.read_text(&key_tag)?;
+                        self.budget.string(key.len())?;
+                        self.skip_misc()?;
+                        let value_tag = self.read_tag()?;
+                        entries.push((key, self.read_value(value_tag)?));
+                    }
+                    self.budget.leave();
+                }
+                // UIDs are written as a dictionary with a single CF$UID key
+                match entries.as_slice() {
+                    [(key, PlistValue::Integer(uid))] if key == "CF$UID" && *uid >= 0 => {
+                        PlistValue::Uid(*uid as u64)
+                    }
+                    _ => PlistValue::Dictionary(entries),
+                }
+            }
+            name => return Err(self.error_at(tag.start, &format!("Unknown element <{}>", name))),
+        })
+    }
+}
+
+/// Parses a decimal or hexadecimal integer that fits in an i64 or u64
+fn parse_integer(text: &str) -> Option<i128> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()? as i128,
+        None => digits.parse::<u64>().ok()? as i128,
+    };
+    if negative {
+        (value <= -(i64::MIN as i128)).then_some(-value)
+    } else {
+        Some(value)
+    }
+}
+
+fn parse_real(text: &str) -> Option<f64> {
+    match text.to_ascii_lowercase().as_str() {
+        "nan" => Some(f64::NAN),
+        "inf" | "+inf" | "infinity" | "+infinity" => Some(f64::INFINITY),
+        "-inf" | "-infinity" => Some(f64::NEG_INFINITY),
+        text => text.parse().ok(),
+    }
+}
+
+/// Parses an ISO 8601 date such as `2019-01-04T21:00:00Z`
+fn parse_date(text: &str) -> Option<SystemTime> {
+    let text = text.strip_suffix('Z').unwrap_or(text);
+    let (date, time) = text.split_once('T')?;
+    let mut date_parts = date.splitn(3, '-');
+    // Any year an i32 holds keeps the arithmetic below from overflowing
+    let year: i32 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+    let (time, fraction) = match time.split_once('.') {
+        Some((time, fraction)) => (time, parse_fraction(fraction)?),
+        None => (time, Duration::ZERO),
+    };
+    let mut time_parts = time.splitn(3, ':');
+    let hour: u32 = time_parts.next()?.parse().ok()?;
+    let minute: u32 = time_parts.next()?.parse().ok()?;
+    let second: u32 = time_parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    let secs = days_from_civil(year as i64, month, day) * 86400
+        + (hour * 3600 + minute * 60 + second) as i64;
+    let time = if secs >= 0 {
+        SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(secs as u64))?
+    } else {
+        SystemTime::UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))?
+    };
+    time.checked_add(fraction)
+}
+
+/// Parses the digits after the decimal point of the seconds
+/// Only plain digits are allowed, and anything past nanoseconds is dropped
+fn parse_fraction(digits: &str) -> Option<Duration> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let nanos = digits
+        .bytes()
+        .chain(std::iter::repeat(b'0'))
+        .take(9)
+        .fold(0, |nanos, digit| nanos * 10 + (digit - b'0') as u32);
+    Some(Duration::from_nanos(nanos as u64))
+}
+
+impl PlistValue {
+    /// Parses an XML plist without using the C library
+    /// This is the native alternative to `Plist::from_xml`
+    pub fn from_xml(xml: &str) -> Result<PlistValue, PlistError> {
+        from_str(xml)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_xml_test() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<!-- a comment -->
+	<key>Name</key>
+	<string>Tom &amp; Jerry &#x263A;</string>
+	<key>Count</key>
+	<integer>-3</integer>
+	<key>Blob</key>
+	<data>
+	AAEC
+	Aw==
+	</data>
+	<key>When</key>
+	<date>2001-01-01T00:01:00Z</date>
+	<key>Items</key>
+	<array>
+		<true/>
+		<real>1.5</real>
+		<dict>
+			<key>CF$UID</key>
+			<integer>7</integer>
+		</dict>
+	</array>
+	<key>Empty</key>
+	<dict/>
+</dict>
+</plist>
+"#;
+        let value = from_str(xml).unwrap();
+        assert_eq!(
+            value.dict_get("Name").unwrap().as_str().unwrap(),
+            "Tom & Jerry \u{263A}"
+        );
+        assert_eq!(value.dict_get("Count"), Some(&PlistValue::Integer(-3)));
+        assert_eq!(
+            value.dict_get("Blob"),
+            Some(&PlistValue::Data(vec![0, 1, 2, 3]))
+        );
+        assert_eq!(
+            value.dict_get("When"),
+            Some(&PlistValue::Date(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(978307260)
+            ))
+        );
+        let items = value.dict_get("Items").unwrap();
+        assert_eq!(items.array_get(0), Some(&PlistValue::Boolean(true)));
+        assert_eq!(items.array_get(2), Some(&PlistValue::Uid(7)));
+        assert_eq!(
+            value.dict_get("Empty"),
+            Some(&PlistValue::Dictionary(vec![]))
+        );
+    }
+
+    #[test]
+    fn read_xml_round_trip_test() {
+        let value = PlistValue::Array(vec![
+            PlistValue::String("<&>".to_string()),
+            PlistValue::Real(0.1),
+            PlistValue::Integer(u64::MAX as i128),
+            PlistValue::Data((0..100).collect()),
+        ]);
+        let xml = value.to_xml().unwrap();
+        assert_eq!(from_str(&xml).unwrap(), value);
+    }
+
+    #[test]
+    fn read_xml_malformed_test() {
+        assert!(from_str("<plist><dict><key>a</key></dict></plist>").is_err());
+        assert!(from_str("<plist><string>a</plist>").is_err());
+        assert!(from_str("<plist><integer>abc</integer></plist>").is_err());
+        assert!(from_str("<plist><array></plist>").is_err());
+
+        let error =
+            from_str("<plist>\n<dict>\n\t<key>a</key>\n\t<strin>b</string>\n</dict>\n</plist>")
+                .unwrap_err();
+        match error {
+            PlistError::Parse { line, column, .. } => assert_eq!((line, column), (4, 2)),
+            e => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn read_xml_date_test() {
+        let date = |text: &str| from_str(&format!("<plist><date>{}</date></plist>", text));
+        assert_eq!(
+            date("2001-01-01T00:00:00.25Z").unwrap(),
+            PlistValue::Date(SystemTime::UNIX_EPOCH + Duration::from_millis(978307200250))
+        );
+        assert_eq!(
+            date("1969-12-31T23:59:59.5Z").unwrap(),
+            PlistValue::Date(SystemTime::UNIX_EPOCH - Duration::from_millis(500))
+        );
+        for text in [
+            "2001-01-01T00:00:00.5e400Z",
+            "2001-01-01T00:00:00.Z",
+            "2001-01-01T00:00:00.-5Z",
+            "2001-01-01T-1:00:00Z",
+            "99999999999999999-01-01T00:00:00Z",
+        ] {
+            assert!(date(text).is_err(), "{}", text);
+        }
+    }
+}
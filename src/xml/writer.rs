@@ -0,0 +1,241 @@
+// jkcoxson
+
+use log::{trace, warn};
+
+use crate::error::PlistError;
+use crate::types::date::MAC_EPOCH;
+use crate::value::{system_time_to_mac_secs, PlistValue};
+
+use super::{base64, civil_from_days};
+
+const PROLOG: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+<plist version=\"1.0\">\n";
+const EPILOG: &str = "</plist>\n";
+
+/// Serializes a value as an XML plist without using the C library
+/// The output matches what libplist's `plist_to_xml` produces for the same tree
+pub fn to_string(value: &PlistValue) -> Result<String, PlistError> {
+    trace!("Writing XML plist natively");
+    let mut out = String::from(PROLOG);
+    write_node(&mut out, value, 0)?;
+    out.push_str(EPILOG);
+    Ok(out)
+}
+
+fn indent(out: &mut String, depth: usize) {
+    for _ in 0..depth {
+        out.push('\t');
+    }
+}
+
+/// Only the characters that would break the markup are escaped, like libplist does
+fn push_escaped(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn write_node(out: &mut String, value: &PlistValue, depth: usize) -> Result<(), PlistError> {
+    indent(out, depth);
+    match value {
+        PlistValue::Boolean(true) => out.push_str("<true/>"),
+        PlistValue::Boolean(false) => out.push_str("<false/>"),
+        PlistValue::Integer(i) => out.push_str(&format!("<integer>{}</integer>", i)),
+        PlistValue::Real(r) => out.push_str(&format!("<real>{}</real>", format_real(*r))),
+        PlistValue::Date(time) => {
+            // libplist truncates the date to whole seconds
+            let secs = system_time_to_mac_secs(*time).trunc() as i64 + MAC_EPOCH as i64;
+            out.push_str(&format!("<date>{}</date>", format_date(secs)));
+        }
+        PlistValue::String(s) => {
+            out.push_str("<string>");
+            push_escaped(out, s);
+            out.push_str("</string>");
+        }
+        PlistValue::Data(data) => {
+            out.push_str("<data>\n");
+            // Lines are kept under 76 columns, counting each tab as 8
+            let depth = depth.min(8);
+            let per_line = ((76 - (depth << 3)) >> 2) * 3;
+            for chunk in data.chunks(per_line) {
+                indent(out, depth);
+                out.push_str(&base64::encode(chunk));
+                out.push('\n');
+            }
+            indent(out, depth);
+            out.push_str("</data>");
+        }
+        PlistValue::Array(items) if items.is_empty() => out.push_str("<array/>"),
+        PlistValue::Array(items) => {
+            out.push_str("<array>\n");
+            for item in items {
+                write_node(out, item, depth + 1)?;
+            }
+            indent(out, depth);
+            out.push_str("</array>");
+        }
+        PlistValue::Dictionary(entries) if entries.is_empty() => out.push_str("<dict/>"),
+        PlistValue::Dictionary(entries) => {
+            out.push_str("<dict>\n");
+            for (key, item) in entries {
+                indent(out, depth + 1);
+                out.push_str("<key>");
+                push_escaped(out, key);
+                out.push_str("</key>\n");
+                write_node(out, item, depth + 1)?;
+            }
+            indent(out, depth);
+            out.push_str("</dict>");
+        }
+        // XML has no UID type, so it's written as a dictionary with a single CF$UID key
+        PlistValue::Uid(uid) => {
+            out.push_str("<dict>\n");
+            indent(out, depth + 1);
+            out.push_str("<key>CF$UID</key>\n");
+            indent(out, depth + 1);
+            out.push_str(&format!("<integer>{}</integer>\n", uid));
+            indent(out, depth);
+            out.push_str("</dict>");
+        }
+        PlistValue::Null => {
+            warn!("Null values can't be written to XML");
+            return Err(PlistError::Format);
+        }
+    }
+    out.push('\n');
+    Ok(())
+}
+
+/// Formats a real the way libplist does, which is C's `%.17g` with a few special cases
+pub(crate) fn format_real(r: f64) -> String {
+    if r.is_nan() {
+        return "nan".to_string();
+    }
+    if r.is_infinite() {
+        return if r > 0.0 { "+infinity" } else { "-infinity" }.to_string();
+    }
+    if r == 0.0 {
+        return "0.0".to_string();
+    }
+    // 17 significant digits, correctly rounded
+    let scientific = format!("{:.16e}", r);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let negative = mantissa.starts_with('-');
+    let digits: String = mantissa.chars().filter(|c| c.is_ascii_digit()).collect();
+
+    let mut out = String::new();
+    if negative {
+        out.push('-');
+    }
+    if (-4..17).contains(&exponent) {
+        let (int_part, frac_part) = if exponent >= 0 {
+            let split = exponent as usize + 1;
+            (digits[..split].to_string(), digits[split..].to_string())
+        } else {
+            let zeros = "0".repeat((-exponent - 1) as usize);
+            ("0".to_string(), format!("{}{}", zeros, digits))
+        };
+        out.push_str(&int_part);
+        let frac_part = frac_part.trim_end_matches('0');
+        if !frac_part.is_empty() {
+            out.push('.');
+            out.push_str(frac_part);
+        }
+    } else {
+        out.push_str(&digits[..1]);
+        let frac_part = digits[1..].trim_end_matches('0');
+        if !frac_part.is_empty() {
+            out.push('.');
+            out.push_str(frac_part);
+        }
+        out.push_str(&format!(
+            "e{}{:02}",
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        ));
+    }
+    out
+}
+
+/// Formats seconds since the Unix Epoch as an ISO 8601 date in UTC
+fn format_date(secs: i64) -> String {
+    let days = secs.div_euclid(86400);
+    let time = secs.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+impl PlistValue {
+    /// Serializes the value as an XML plist without using the C library
+    /// This is the native alternative to `Plist::to_string`
+    pub fn to_xml(&self) -> Result<String, PlistError> {
+        to_string(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_real_test() {
+        assert_eq!(format_real(1.0), "1");
+        assert_eq!(format_real(0.0), "0.0");
+        assert_eq!(format_real(3.5), "3.5");
+        assert_eq!(format_real(0.1), "0.10000000000000001");
+        assert_eq!(format_real(-0.0001), "-0.0001");
+        assert_eq!(format_real(1e20), "1e+20");
+        assert_eq!(format_real(1.5e-7), "1.4999999999999999e-07");
+        assert_eq!(format_real(1e16), "10000000000000000");
+        assert_eq!(format_real(1e-5), "1.0000000000000001e-05");
+        assert_eq!(
+            format_real(1.7976931348623157e308),
+            "1.7976931348623157e+308"
+        );
+        assert_eq!(format_real(f64::INFINITY), "+infinity");
+    }
+
+    #[test]
+    fn write_xml_test() {
+        let value = PlistValue::Dictionary(vec![
+            ("Name".to_string(), PlistValue::String("a < b".to_string())),
+            ("Blob".to_string(), PlistValue::Data(vec![0, 1, 2])),
+            ("Empty".to_string(), PlistValue::Array(vec![])),
+            ("Ref".to_string(), PlistValue::Uid(3)),
+        ]);
+        let expected = format!(
+            "{}<dict>\n\
+             \t<key>Name</key>\n\
+             \t<string>a &lt; b</string>\n\
+             \t<key>Blob</key>\n\
+             \t<data>\n\
+             \tAAEC\n\
+             \t</data>\n\
+             \t<key>Empty</key>\n\
+             \t<array/>\n\
+             \t<key>Ref</key>\n\
+             \t<dict>\n\
+             \t\t<key>CF$UID</key>\n\
+             \t\t<integer>3</integer>\n\
+             \t</dict>\n\
+             </dict>\n{}",
+            PROLOG, EPILOG
+        );
+        assert_eq!(to_string(&value).unwrap(), expected);
+    }
+}
@@ -0,0 +1,253 @@
+// jkcoxson
+
+//! Round trips arbitrary plist trees through every format
+//! Each format only gets the types it can represent, so whatever goes in has to come back out
+//! The `plist_*` tests go through `Plist`, which is libplist unless built with pure-rust,
+//! and the `native_*` tests through the native readers and writers
+
+use std::time::{Duration, SystemTime};
+
+use plist_plus::{bplist::BinaryWriter, Plist, PlistValue};
+use proptest::{collection, prelude::*, strategy::Union};
+
+/// The Mac Epoch as a Unix Timestamp
+const MAC_EPOCH: u64 = 978307200;
+
+/// The types a format can hold
+#[derive(Clone, Copy)]
+struct Format {
+    booleans: bool,
+    numbers: bool,
+    /// Integers above i64::MAX
+    big_integers: bool,
+    dates: bool,
+    /// Dates with a fraction of a second, which XML drops like libplist does
+    fractional_dates: bool,
+    data: bool,
+    nulls: bool,
+}
+
+const XML: Format = Format {
+    booleans: true,
+    numbers: true,
+    big_integers: true,
+    dates: true,
+    fractional_dates: false,
+    data: true,
+    nulls: false,
+};
+const BINARY: Format = Format {
+    fractional_dates: true,
+    ..XML
+};
+const JSON: Format = Format {
+    booleans: true,
+    numbers: true,
+    big_integers: false,
+    dates: false,
+    fractional_dates: false,
+    data: false,
+    nulls: true,
+};
+// OpenStep reads every scalar back as a string
+const OPENSTEP: Format = Format {
+    booleans: false,
+    numbers: false,
+    big_integers: false,
+    dates: false,
+    fractional_dates: false,
+    data: true,
+    nulls: false,
+};
+
+fn string() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9 _.<>&\"'\\\\é😀-]{0,16}"
+}
+
+/// Seconds on both sides of the Mac Epoch, as far as libplist's i32 reaches
+fn date_secs() -> impl Strategy<Value = u64> {
+    0..MAC_EPOCH + i32::MAX as u64
+}
+
+/// Fractions are in 64ths of a second, which libplist's double holds exactly
+fn date(secs: u64, fraction: u32) -> Duration {
+    Duration::from_secs(secs) + Duration::from_micros(15625) * fraction
+}
+
+fn leaf(format: Format) -> BoxedStrategy<PlistValue> {
+    let mut leaves = vec![string().prop_map(PlistValue::String).boxed()];
+    if format.booleans {
+        leaves.push(any::<bool>().prop_map(PlistValue::Boolean).boxed());
+    }
+    if format.numbers {
+        let max = if format.big_integers {
+            u64::MAX
+        } else {
+            i64::MAX as u64
+        };
+        leaves.push(
+            (0..=max)
+                .prop_map(|i| PlistValue::Integer(i as i128))
+                .boxed(),
+        );
+        leaves.push(
+            (i64::MIN..0)
+                .prop_map(|i| PlistValue::Integer(i as i128))
+                .boxed(),
+        );
+        // Reals with a fraction, so no format can mistake them for integers
+        leaves.push(
+            (-1e12..1e12_f64)
+                .prop_filter("has a fraction", |r| r.fract() != 0.0)
+                .prop_map(PlistValue::Real)
+                .boxed(),
+        );
+    }
+    if format.dates {
+        let fractions: u32 = if format.fractional_dates { 64 } else { 1 };
+        leaves.push(
+            (date_secs(), 0..fractions)
+                .prop_map(|(secs, fraction)| {
+                    PlistValue::Date(SystemTime::UNIX_EPOCH + date(secs, fraction))
+                })
+                .boxed(),
+        );
+    }
+    if format.data {
+        leaves.push(
+            collection::vec(any::<u8>(), 0..48)
+                .prop_map(PlistValue::Data)
+                .boxed(),
+        );
+    }
+    if format.nulls {
+        leaves.push(Just(PlistValue::Null).boxed());
+    }
+    Union::new(leaves).boxed()
+}
+
+fn dictionary(item: BoxedStrategy<PlistValue>) -> impl Strategy<Value = PlistValue> {
+    collection::btree_map(string(), item, 0..6)
+        .prop_map(|entries| PlistValue::Dictionary(entries.into_iter().collect()))
+}
+
+/// A tree with a dictionary at the root, which every format accepts
+fn tree(format: Format) -> impl Strategy<Value = PlistValue> {
+    let item = leaf(format).prop_recursive(4, 48, 6, |inner| {
+        prop_oneof![
+            collection::vec(inner.clone(), 0..6).prop_map(PlistValue::Array),
+            dictionary(inner),
+        ]
+    });
+    dictionary(item.boxed())
+}
+
+/// Builds the tree with the public constructors
+fn build(value: &PlistValue) -> Plist {
+    match value {
+        PlistValue::Boolean(b) => Plist::new_bool(*b),
+        PlistValue::Integer(i) if *i < 0 => Plist::new_int(*i as i64),
+        PlistValue::Integer(i) => Plist::new_uint(*i as u64),
+        PlistValue::Real(r) => Plist::new_real(*r),
+        PlistValue::Date(time) => {
+            Plist::new_date(time.duration_since(SystemTime::UNIX_EPOCH).unwrap())
+        }
+        PlistValue::Data(data) => Plist::new_data(data),
+        PlistValue::String(s) => Plist::new_string(s),
+        PlistValue::Array(items) => {
+            let mut array = Plist::new_array();
+            for item in items {
+                array.array_append_item(build(item)).unwrap();
+            }
+            array
+        }
+        PlistValue::Dictionary(entries) => {
+            let mut dict = Plist::new_dict();
+            for (key, item) in entries {
+                dict.dict_set_item(key, build(item)).unwrap();
+            }
+            dict
+        }
+        PlistValue::Null => Plist::from(value),
+        value => panic!("No generator makes {:?}", value),
+    }
+}
+
+proptest! {
+    #[test]
+    fn plist_xml_round_trip(value in tree(XML)) {
+        let parsed = Plist::from_xml(build(&value).to_string()).unwrap();
+        prop_assert_eq!(PlistValue::from(&parsed), value);
+    }
+
+    #[test]
+    fn plist_binary_round_trip(value in tree(BINARY)) {
+        let parsed = Plist::from_bin(build(&value).into()).unwrap();
+        prop_assert_eq!(PlistValue::from(&parsed), value);
+    }
+
+    #[test]
+    fn plist_json_round_trip(value in tree(JSON)) {
+        let parsed = Plist::from_json(build(&value).to_json().unwrap()).unwrap();
+        prop_assert_eq!(PlistValue::from(&parsed), value);
+    }
+
+    #[test]
+    fn plist_openstep_round_trip(value in tree(OPENSTEP)) {
+        let parsed = Plist::from_openstep(build(&value).to_openstep().unwrap()).unwrap();
+        prop_assert_eq!(PlistValue::from(&parsed), value);
+    }
+
+    #[test]
+    fn plist_integer_display(int in any::<i64>(), uint in any::<u64>()) {
+        prop_assert_eq!(Plist::new_int(int).get_display_value().unwrap(), int.to_string());
+        prop_assert_eq!(Plist::new_uint(uint).get_display_value().unwrap(), uint.to_string());
+    }
+
+    #[test]
+    fn plist_date_round_trip(secs in date_secs(), fraction in 0..64_u32) {
+        let date = date(secs, fraction);
+        prop_assert_eq!(Plist::new_date(date).get_date_val().unwrap(), date);
+    }
+
+    #[cfg(not(feature = "pure-rust"))]
+    #[test]
+    fn libplist_and_native_xml_agree(value in tree(XML)) {
+        let xml = plist_plus::xml::to_string(&value).unwrap();
+        prop_assert_eq!(PlistValue::from(&Plist::from_xml(xml).unwrap()), value.clone());
+        prop_assert_eq!(plist_plus::xml::from_str(&build(&value).to_string()).unwrap(), value);
+    }
+
+    #[cfg(not(feature = "pure-rust"))]
+    #[test]
+    fn libplist_and_native_binary_agree(value in tree(BINARY)) {
+        let bin = BinaryWriter::new().write(&value).unwrap();
+        prop_assert_eq!(PlistValue::from(&Plist::from_bin(bin).unwrap()), value.clone());
+        let bin: Vec<u8> = build(&value).into();
+        prop_assert_eq!(plist_plus::bplist::from_bytes(&bin).unwrap(), value);
+    }
+
+    #[test]
+    fn native_xml_round_trip(value in tree(XML)) {
+        let xml = plist_plus::xml::to_string(&value).unwrap();
+        prop_assert_eq!(plist_plus::xml::from_str(&xml).unwrap(), value);
+    }
+
+    #[test]
+    fn native_binary_round_trip(value in tree(BINARY)) {
+        let bin = BinaryWriter::new().write(&value).unwrap();
+        prop_assert_eq!(plist_plus::bplist::from_bytes(&bin).unwrap(), value);
+    }
+
+    #[test]
+    fn native_json_round_trip(value in tree(JSON)) {
+        let json = plist_plus::json::to_string(&value).unwrap();
+        prop_assert_eq!(plist_plus::json::from_str(&json).unwrap(), value);
+    }
+
+    #[test]
+    fn native_openstep_round_trip(value in tree(OPENSTEP)) {
+        let openstep = plist_plus::openstep::to_string(&value).unwrap();
+        prop_assert_eq!(plist_plus::openstep::from_str(&openstep).unwrap(), value);
+    }
+}